@@ -0,0 +1,564 @@
+use crate::*;
+use std::fmt::Display;
+
+/// Range of frequencies in the histogram
+const MIN_FREQUENCY: f32 = 100.0;
+const MAX_FREQUENCY: f32 = 20000.0;
+const BINS_PER_OCTAVE: f32 = 48.0;
+/// Periods within this relative deviation from a peak of the histogram are averaged to the frequency of the tone
+const PEAK_WIDTH: f32 = 0.06;
+/// Least ratio between the frequencies of the two tones
+const MIN_TONE_RATIO: f32 = 1.3;
+/// Least part of all periods that has to be at a tone
+const MIN_TONE_FRACTION: f32 = 0.05;
+/// Largest relative deviation of a period from a tone for it to count as that tone
+const TONE_TOLERANCE: f32 = 0.1;
+/// Most periods of the lower tone per bit that are tried
+const MAX_PERIODS_PER_BIT: usize = 16;
+/// Largest fraction of a period of the higher tone left over at the end of a bit
+const MAX_FRACTIONAL_PERIOD: f32 = 0.15;
+/// Number of consecutive changes of tone over which they are compared with a bit clock
+const COHERENCE_WINDOW: usize = 64;
+/// The longest bit length that fits the changes of tone at least this well compared to the best fit is chosen,
+/// as the changes of tone also fit every fraction of the bit length
+const MIN_RELATIVE_COHERENCE: f32 = 0.75;
+/// Error rates closer than this are treated as equal when ranking the framings
+const ERROR_RATE_RESOLUTION: f32 = 0.005;
+/// Least number of bytes a framing has to decode to be recommended
+const MIN_BYTES: usize = 16;
+/// Relative deviation of the tones from the tones of a preset for the preset to be recommended
+const PRESET_TOLERANCE: f32 = 0.05;
+
+/// Tape format detection
+///
+/// Collects the samples of a recording. On `finish`, the two tones are found as the peaks of the histogram of the
+/// frequencies of the periods between rising zero crossings. The recording is then band-pass filtered around the
+/// tones. The bit length is the longest whole number of periods of the lower tone that the changes of tone line up with. The bits are then decoded with `BitClockRecovery` and framed with every
+/// combination of 7 or 8 data bits, parity and 1 or 2 stop bits, which are ranked by their rate of framing errors.
+/// The idle tone of the leader is the stop bit level.
+///
+/// ```
+/// use kcs_decoder::*;
+///
+/// let config = DecoderConfig {
+///     stopbits: (2, SignalCondition::Mark),
+///     ..DecoderConfig::default()
+/// };
+/// let encoder_config = EncoderConfig {
+///     sample_rate: 44100,
+///     leader_length: 0.5,
+///     ..Default::default()
+/// };
+/// let data = (0..200u32).map(|val| (val * 97 % 256) as u8).collect::<Vec<_>>();
+/// let samples = Encoder::new(config, encoder_config).unwrap().encode(&data);
+///
+/// let mut analyzer = Analyzer::new(44100);
+/// analyzer.process(&samples);
+/// let analysis = analyzer.finish().unwrap();
+/// assert!((analysis.baud_rate - 1200.0).abs() < 10.0);
+/// assert_eq!(analysis.preset, Some(Preset::MSX1200));
+/// assert_eq!(analysis.config, DecoderConfig::get_preset(&Preset::MSX1200));
+/// assert_eq!(analysis.candidates[0].bytes, data);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Analyzer {
+    sample_rate: u32,
+    samples: Vec<f32>,
+}
+
+/// Framing tried by `Analyzer` and how well it fits the recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub config: DecoderConfig,
+    pub bytes: Vec<u8>,
+    pub errors: usize,
+}
+
+impl Candidate {
+    /// Framing errors relative to all frames
+    pub fn error_rate(&self) -> f32 {
+        self.errors as f32 / (self.bytes.len() + self.errors).max(1) as f32
+    }
+
+    /// Short name of the framing, e.g. 8N1
+    pub fn framing(&self) -> String {
+        let parity = self.config.parity.to_string();
+        format!(
+            "{}{}{}",
+            self.config.num_databits,
+            &parity[..1],
+            self.config.stopbits.0
+        )
+    }
+}
+
+/// Tape format found by `Analyzer`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    /// Measured frequencies of the lower and the higher tone in Hz
+    pub tones: [f32; 2],
+    pub baud_rate: f32,
+    /// Tone of the leader, which is the stop bit level
+    pub idle_tone: usize,
+    /// Every framing, best first
+    pub candidates: Vec<Candidate>,
+    /// Recommended configuration, with the tones of the matching preset if there is one
+    pub config: DecoderConfig,
+    /// Preset with the tones and framing of `config`, or the tones only if no preset has the framing
+    pub preset: Option<Preset>,
+}
+
+impl Analyzer {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            samples: vec![],
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        self.samples.extend_from_slice(samples);
+    }
+
+    /// Analyzes the recording so far, none if no tape format was found
+    pub fn finish(&self) -> Option<Analysis> {
+        let dc_block = DecoderConfig {
+            filter: Filter::DcBlock,
+            ..DecoderConfig::default()
+        };
+        let tones = tones(&self.periods(&dc_block))?;
+        // The lower tone is the space symbol, as in the presets
+        let signals = [SignalCondition::Space, SignalCondition::Mark];
+        let tone_symbols = |periods: [usize; 2]| {
+            [0, 1].map(|tone| Symbol {
+                frequency: tones[tone].round() as usize,
+                half_periods: 2 * periods[tone],
+                signal: signals[tone],
+            })
+        };
+        // The band-pass filter only depends on the frequencies of the tones
+        let band = DecoderConfig {
+            symbols: tone_symbols([1, 1]),
+            ..DecoderConfig::default()
+        };
+        let periods = self.periods(&band);
+        let (transitions, idle_tone) = self.transitions(&periods, tones)?;
+        let periods_per_bit = self.periods_per_bit(tones, &transitions)?;
+        let baud_rate = tones[0] / periods_per_bit as f32;
+
+        // The idle tone gives the levels of start and stop bits
+        let measured = DecoderConfig {
+            symbols: tone_symbols(tones.map(|tone| (tone / baud_rate).round() as usize)),
+            startbits: (1, signals[1 - idle_tone]),
+            stopbits: (1, signals[idle_tone]),
+            ..DecoderConfig::default()
+        };
+        let mut clock = BitClockRecovery::new(&measured, self.sample_rate)?;
+        let mut bits = periods
+            .iter()
+            .filter_map(|period| clock.process(*period))
+            .map(|(_, bit)| bit)
+            .collect::<Vec<_>>();
+        bits.extend(clock.flush().into_iter().map(|(_, bit)| bit));
+
+        let mut candidates = vec![];
+        for num_databits in [8, 7] {
+            for parity in [
+                Parity::NONE,
+                Parity::EVEN,
+                Parity::ODD,
+                Parity::MARK,
+                Parity::SPACE,
+            ] {
+                for num_stopbits in [1, 2] {
+                    let config = DecoderConfig {
+                        num_databits,
+                        parity,
+                        stopbits: (num_stopbits, measured.stopbits.1),
+                        ..measured
+                    };
+                    candidates.push(frame(config, &bits)?);
+                }
+            }
+        }
+        // Framings that check more bits are preferred if they fit as well, the order above decides between equals
+        let error_rate = |candidate: &Candidate| {
+            (candidate.error_rate() / ERROR_RATE_RESOLUTION).round() as usize
+        };
+        candidates.sort_by_key(|candidate| {
+            let checked_bits =
+                candidate.config.stopbits.0 + usize::from(candidate.config.parity != Parity::NONE);
+            (error_rate(candidate), usize::MAX - checked_bits)
+        });
+        if candidates[0].bytes.len() < MIN_BYTES {
+            return None;
+        }
+        // The gaps between the bytes of a NASCOM tape also fit more stop bits, the framing of the preset is preferred
+        let is_nascom = nascom::find_header(&candidates[0].bytes).is_some();
+        if is_nascom {
+            let preset = DecoderConfig::get_preset(&Preset::NASCOM);
+            let best_rate = error_rate(&candidates[0]);
+            if let Some(idx) = candidates.iter().position(|candidate| {
+                error_rate(candidate) == best_rate
+                    && framing_differences(&candidate.config, &preset) == 0
+            }) {
+                let candidate = candidates.remove(idx);
+                candidates.insert(0, candidate);
+            }
+        }
+
+        let best = &candidates[0].config;
+        let preset = matching_preset(best);
+        let mut config = match preset {
+            Some(preset) => DecoderConfig::get_preset(&preset),
+            None => DecoderConfig::default(),
+        };
+        if preset.is_none() {
+            config.symbols = best.symbols;
+        }
+        config.startbits = best.startbits;
+        config.num_databits = best.num_databits;
+        config.parity = best.parity;
+        config.stopbits = best.stopbits;
+        if is_nascom {
+            config.framing = Framing::NASCOM;
+        }
+        Some(Analysis {
+            tones,
+            baud_rate,
+            idle_tone,
+            candidates,
+            config,
+            preset,
+        })
+    }
+
+    /// Periods between rising zero crossings after filtering the recording with the filter of `config`
+    fn periods(&self, config: &DecoderConfig) -> Vec<(usize, f32)> {
+        let mut stage = PreFilter::new(config, self.sample_rate)
+            .then(ZeroCrossingDetector::with_interpolation(
+                0.0,
+                ZeroCrossingInterpolation::Linear,
+            ))
+            .then(FrequencyIdentifier::new(
+                ZeroCrossingDirection::Pos,
+                self.sample_rate,
+            ));
+        self.samples
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, sample)| stage.process((idx, *sample)))
+            .collect()
+    }
+
+    /// Times of the changes of tone in samples and the tone of the longest run, which is the idle tone
+    fn transitions(&self, periods: &[(usize, f32)], tones: [f32; 2]) -> Option<(Vec<f64>, usize)> {
+        let classify = |frequency: f32| {
+            (0..2).find(|tone| (frequency / tones[*tone] - 1.0).abs() <= TONE_TOLERANCE)
+        };
+        let tone_periods = tones.map(|tone| self.sample_rate as f64 / tone as f64);
+        let mut transitions = vec![];
+        let mut longest_run = (0.0, 0);
+        let mut run_start = 0.0;
+        // Tone and end of the last period of a tone, and the end of the previous period
+        let mut last_tone: Option<(usize, f64)> = None;
+        let mut last_end: Option<f64> = None;
+        for &(idx, frequency) in periods {
+            let period = self.sample_rate as f64 / frequency as f64;
+            // Adding up the periods keeps the precision of the interpolated crossings
+            let end = match last_end {
+                Some(last_end) if (last_end + period - idx as f64).abs() < 2.0 => last_end + period,
+                _ => idx as f64,
+            };
+            let start = end - period;
+            last_end = Some(end);
+
+            let tone = classify(frequency);
+            let transition = match (tone, last_tone) {
+                (Some(tone), Some((last, last_tone_end))) if tone != last => {
+                    Some(match start - last_tone_end < 1.0 {
+                        true => start,
+                        false => (start + last_tone_end) / 2.0,
+                    })
+                }
+                // With a continuous phase, a period between the tones is part of a period of each
+                (None, Some((last, last_tone_end))) if start - last_tone_end < 1.0 => {
+                    let (last_period, next_period) = (tone_periods[last], tone_periods[1 - last]);
+                    let part = (next_period - period) / (next_period - last_period);
+                    (part > 0.0 && part < 1.0).then_some(start + part * last_period)
+                }
+                _ => None,
+            };
+            if let Some(transition) = transition {
+                if let Some((last, last_tone_end)) = last_tone {
+                    if last_tone_end - run_start > longest_run.0 {
+                        longest_run = (last_tone_end - run_start, last);
+                    }
+                    last_tone = Some((1 - last, end));
+                }
+                transitions.push(transition);
+                run_start = transition;
+            }
+            match tone {
+                Some(tone) => {
+                    if last_tone.is_none() {
+                        run_start = start;
+                    }
+                    last_tone = Some((tone, end));
+                }
+                None if transition.is_none() => last_tone = None,
+                None => {}
+            }
+        }
+        if let Some((last, last_tone_end)) = last_tone {
+            if last_tone_end - run_start > longest_run.0 {
+                longest_run = (last_tone_end - run_start, last);
+            }
+        }
+        match transitions.len() > COHERENCE_WINDOW {
+            true => Some((transitions, longest_run.1)),
+            false => None,
+        }
+    }
+
+    /// Number of periods of the lower tone per bit, for which the changes of tone are at bit boundaries
+    fn periods_per_bit(&self, tones: [f32; 2], transitions: &[f64]) -> Option<usize> {
+        let coherence = |bit_length: f64| {
+            let windows = transitions.chunks_exact(COHERENCE_WINDOW);
+            let num_windows = windows.len();
+            windows
+                .map(|window| {
+                    let (sin, cos) = window.iter().fold((0.0, 0.0), |(sin, cos), time| {
+                        let phase = std::f64::consts::TAU * time / bit_length;
+                        (sin + phase.sin(), cos + phase.cos())
+                    });
+                    (sin * sin + cos * cos).sqrt() / COHERENCE_WINDOW as f64
+                })
+                .sum::<f64>() as f32
+                / num_windows as f32
+        };
+        let coherences = (1..=MAX_PERIODS_PER_BIT)
+            .filter(|periods| {
+                // The higher tone also has a whole number of periods per bit
+                let high_periods = *periods as f32 * tones[1] / tones[0];
+                (high_periods - high_periods.round()).abs() <= MAX_FRACTIONAL_PERIOD
+            })
+            .map(|periods| {
+                let bit_length = periods as f64 * self.sample_rate as f64 / tones[0] as f64;
+                (periods, coherence(bit_length))
+            })
+            .collect::<Vec<_>>();
+        let best = coherences
+            .iter()
+            .map(|(_, coherence)| *coherence)
+            .fold(0.0, f32::max);
+        coherences
+            .into_iter()
+            .filter(|(_, coherence)| *coherence >= MIN_RELATIVE_COHERENCE * best)
+            .map(|(periods, _)| periods)
+            .max()
+    }
+}
+
+/// Frequencies of the two tones, the lower one first
+fn tones(periods: &[(usize, f32)]) -> Option<[f32; 2]> {
+    let bin =
+        |frequency: f32| ((frequency / MIN_FREQUENCY).log2() * BINS_PER_OCTAVE).round() as usize;
+    let mut histogram = vec![0usize; bin(MAX_FREQUENCY) + 1];
+    for (_, frequency) in periods {
+        if (MIN_FREQUENCY..MAX_FREQUENCY).contains(frequency) {
+            histogram[bin(*frequency)] += 1;
+        }
+    }
+    let frequency = |bin: usize| MIN_FREQUENCY * (bin as f32 / BINS_PER_OCTAVE).exp2();
+    let peak = |excluded: Option<f32>| {
+        (0..histogram.len())
+            .filter(|bin| {
+                excluded.is_none_or(|excluded| {
+                    let ratio = frequency(*bin) / excluded;
+                    ratio.max(1.0 / ratio) >= MIN_TONE_RATIO
+                })
+            })
+            .max_by_key(|bin| histogram[*bin])
+            .map(frequency)
+    };
+    let first = peak(None)?;
+    let second = peak(Some(first))?;
+
+    // The mean of the periods around each peak
+    let mut tones = [first.min(second), first.max(second)];
+    for tone in &mut tones {
+        let near = periods
+            .iter()
+            .map(|(_, frequency)| *frequency)
+            .filter(|frequency| (frequency / *tone - 1.0).abs() <= PEAK_WIDTH)
+            .collect::<Vec<_>>();
+        if (near.len() as f32) < MIN_TONE_FRACTION * periods.len() as f32 {
+            return None;
+        }
+        *tone = near.len() as f32 / near.iter().map(|frequency| 1.0 / frequency).sum::<f32>();
+    }
+    Some(tones)
+}
+
+/// Decodes the bits with `config` and counts the bytes and framing errors
+fn frame(config: DecoderConfig, bits: &[SignalCondition]) -> Option<Candidate> {
+    let mut decoder = Decoder::new(config)?;
+    let mut candidate = Candidate {
+        config,
+        bytes: vec![],
+        errors: 0,
+    };
+    for bit in bits {
+        match decoder.process(*bit) {
+            // The candidates have at most 8 data bits
+            Ok(val) => candidate.bytes.push(val as u8),
+            Err(Some(DecoderError::Signal)) | Err(None) => {}
+            Err(Some(_)) => candidate.errors += 1,
+        }
+    }
+    Some(candidate)
+}
+
+/// Preset with the tones of `config` whose framing differs the least from it
+fn matching_preset(config: &DecoderConfig) -> Option<Preset> {
+    let presets = [
+        Preset::NASCOM,
+        Preset::MSX1200,
+        Preset::Std,
+        Preset::MSX2400,
+    ];
+    let same_tones = |preset: &Preset| {
+        let preset = DecoderConfig::get_preset(preset);
+        preset.symbols.iter().zip(&config.symbols).all(|(a, b)| {
+            a.half_periods == b.half_periods
+                && (b.frequency as f32 / a.frequency as f32 - 1.0).abs() <= PRESET_TOLERANCE
+        })
+    };
+    presets
+        .into_iter()
+        .filter(same_tones)
+        .min_by_key(|preset| framing_differences(config, &DecoderConfig::get_preset(preset)))
+}
+
+/// Number of framing options that differ between the configurations
+fn framing_differences(a: &DecoderConfig, b: &DecoderConfig) -> usize {
+    [
+        a.startbits == b.startbits,
+        a.num_databits == b.num_databits,
+        a.parity == b.parity,
+        a.stopbits.0 == b.stopbits.0,
+        a.stopbits.1 == b.stopbits.1,
+    ]
+    .into_iter()
+    .filter(|same| !same)
+    .count()
+}
+
+impl Display for Analysis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Tones: {:.0} Hz and {:.0} Hz, idle tone {:.0} Hz, {:.0} baud",
+            self.tones[0], self.tones[1], self.tones[self.idle_tone], self.baud_rate
+        )?;
+        write!(f, "Framings:")?;
+        for candidate in &self.candidates {
+            write!(
+                f,
+                "\n  {}: {} bytes, {} framing errors ({:.1}%)",
+                candidate.framing(),
+                candidate.bytes.len(),
+                candidate.errors,
+                candidate.error_rate() * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(config: DecoderConfig, data: &[u8], speed: f32) -> Option<Analysis> {
+        let encoder_config = EncoderConfig {
+            sample_rate: 44100,
+            leader_length: 0.5,
+            trailer_length: 0.1,
+            ..Default::default()
+        };
+        let samples = Encoder::new(config, encoder_config).unwrap().encode(data);
+        let mut analyzer = Analyzer::new((44100.0 * speed) as u32);
+        for block in samples.chunks(1000) {
+            analyzer.process(block);
+        }
+        analyzer.finish()
+    }
+
+    #[test]
+    fn analyzer_presets() {
+        let data = (0..300u32)
+            .map(|val| (val * 151 % 256) as u8)
+            .collect::<Vec<_>>();
+        for preset in [
+            Preset::Std,
+            Preset::NASCOM,
+            Preset::MSX1200,
+            Preset::MSX2400,
+        ] {
+            let config = DecoderConfig::get_preset(&preset);
+            // The tape runs 4% fast
+            for speed in [1.0, 1.04] {
+                let analysis = analyze(config, &data, speed).unwrap();
+                assert_eq!(analysis.preset, Some(preset), "{preset} {speed}");
+                assert_eq!(analysis.config, config, "{preset} {speed}");
+                assert_eq!(analysis.candidates[0].bytes, data, "{preset} {speed}");
+                assert_eq!(analysis.candidates[0].errors, 0, "{preset} {speed}");
+                let baud_rate = 1.0 / config.symbols[0].length() as f32;
+                assert!(
+                    (analysis.baud_rate / speed / baud_rate - 1.0).abs() < 0.01,
+                    "{preset} {speed} {}",
+                    analysis.baud_rate
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn analyzer_framing() {
+        // 7 data bits with odd parity, the idle tone is the lower one
+        let config = DecoderConfig {
+            num_databits: 7,
+            parity: Parity::ODD,
+            startbits: (1, SignalCondition::Mark),
+            stopbits: (1, SignalCondition::Space),
+            ..DecoderConfig::default()
+        };
+        let data = b"The quick brown fox jumps over the lazy dog. 0123456789".repeat(4);
+        let analysis = analyze(config, &data, 1.0).unwrap();
+        assert_eq!(analysis.idle_tone, 0, "{analysis}");
+        assert_eq!(analysis.candidates[0].framing(), "7O1", "{analysis}");
+        assert_eq!(analysis.config, config);
+        assert_eq!(analysis.preset, Some(Preset::NASCOM));
+        // 8 data bits read the parity bit as data, 7 bits without parity read it as the stop bit
+        let rates = analysis
+            .candidates
+            .iter()
+            .map(|candidate| (candidate.framing(), candidate.error_rate()))
+            .collect::<std::collections::HashMap<_, _>>();
+        assert_eq!(rates["8N1"], 0.0);
+        assert!(rates["7N1"] > 0.2);
+        assert!(rates["7E1"] > 0.9);
+
+        // Silence and a single tone are not a tape format
+        let mut analyzer = Analyzer::new(44100);
+        analyzer.process(&[0.0; 44100]);
+        assert_eq!(analyzer.finish(), None);
+        let samples = (0..44100)
+            .map(|idx| (idx as f32 * 0.3).sin())
+            .collect::<Vec<_>>();
+        analyzer.process(&samples);
+        assert_eq!(analyzer.finish(), None);
+    }
+}
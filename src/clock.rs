@@ -0,0 +1,402 @@
+use crate::{DecoderConfig, FrequencyTracker, SignalCondition, Stage, Symbol};
+use std::collections::VecDeque;
+
+/// Part of the phase error at a change of tone that is corrected at once
+const PHASE_GAIN: f64 = 0.25;
+/// Least part of a bit that has to be covered by a tone for the bit to be decided
+const MIN_TONE_COVERAGE: f64 = 0.25;
+/// A period longer than this many bit lengths is a gap in the signal, the clock has to lock again after it
+const MAX_PERIOD_BITS: f64 = 2.0;
+
+/// Bit clock recovery transforms from frequencies -> bits
+///
+/// A replacement for `HiLowIdentifier` that does not count periods. A digital PLL keeps a bit clock running at the
+/// bit rate and pulls its phase towards the changes of tone. The bit rate follows the tape speed measured by its
+/// `FrequencyTracker`, so the clock also keeps time during long runs of the same bit.
+/// Each bit is decided by the tone that fills most of it, which is the tone at its centre, so a period that is
+/// dropped or split in two only affects one bit instead of shifting all later bits.
+/// The confidence in a bit is the lower of how much more of the bit is covered by its tone than by anything else,
+/// and how well the periods in it fit their tone on average.
+/// A period between the two tones, where the tone changed within the period, is split between them at the change.
+/// The first change of tone after a gap in the signal sets the phase directly.
+///
+/// ```
+/// use kcs_decoder::*;
+///
+/// let config = DecoderConfig::default();
+/// let mut clock = BitClockRecovery::new(&config, 48000).unwrap();
+/// // Two periods of 2400 Hz are a mark bit, one period of 1200 Hz a space bit
+/// let mut bits = vec![];
+/// let mut idx = 0;
+/// for frequency in [2400.0, 2400.0, 1200.0, 2400.0, 2400.0, 2400.0, 2400.0] {
+///     idx += 48000 / frequency as usize;
+///     bits.extend(clock.process((idx, frequency)));
+/// }
+/// assert_eq!(
+///     bits,
+///     vec![
+///         (40, SignalCondition::Mark),
+///         (80, SignalCondition::Space),
+///         (120, SignalCondition::Mark),
+///         (160, SignalCondition::Mark)
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitClockRecovery {
+    sample_rate: f64,
+    tracker: FrequencyTracker,
+    signals: [SignalCondition; 2],
+    nominal_bit_length: f64,
+    bit_length: f64,
+    /// Start of the current bit, none until the first period after a reset or gap
+    bit_start: Option<f64>,
+    /// Set by the first change of tone, the phase is only corrected partially after that
+    locked: bool,
+    /// Time covered by each tone and by periods of neither tone in the current bit
+    coverage: [f64; 3],
+    /// Time covered in the current bit weighted by how well each period fits its tone,
+    /// and the confidence in the last returned bit
+    fit: f64,
+    confidence: f32,
+    last_end: Option<f64>,
+    /// Tone and end of the last period that matched one of the tones
+    last_tone: Option<(usize, f64)>,
+    /// Bits that are decided but not returned yet with their confidence, when one period completes more than one bit
+    pending: VecDeque<(usize, SignalCondition, f32)>,
+}
+
+impl BitClockRecovery {
+    pub fn new(config: &DecoderConfig, sample_rate: u32) -> Option<Self> {
+        let tracker = FrequencyTracker::new(
+            config.symbols.map(|symbol| symbol.frequency as u32),
+            config.frequency_tolerance,
+            config.track_speed,
+        )?;
+        // The bit clock times whole periods
+        if config.symbols[0].signal == config.symbols[1].signal || config.half_period_symbols() {
+            return None;
+        }
+        let bit_length = config
+            .symbols
+            .iter()
+            .map(Symbol::length)
+            .fold(0.0, f64::max)
+            * sample_rate as f64;
+        Some(Self {
+            sample_rate: sample_rate as f64,
+            tracker,
+            signals: config.symbols.map(|symbol| symbol.signal),
+            nominal_bit_length: bit_length,
+            bit_length,
+            bit_start: None,
+            locked: false,
+            coverage: [0.0; 3],
+            fit: 0.0,
+            confidence: 1.0,
+            last_end: None,
+            last_tone: None,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Current bit length in samples, follows the tape speed
+    pub fn bit_length(&self) -> f64 {
+        self.bit_length
+    }
+
+    /// Pulls the clock towards a change of tone, which should be on a bit boundary
+    fn correct(&mut self, transition: f64) {
+        let Some(bit_start) = self.bit_start else {
+            return;
+        };
+        let bits = ((transition - bit_start) / self.bit_length).round();
+        let error = transition - (bit_start + bits * self.bit_length);
+        match self.locked {
+            true => {
+                self.bit_start = Some(bit_start + PHASE_GAIN * error);
+            }
+            false => {
+                self.bit_start = Some(bit_start + error);
+                self.locked = true;
+            }
+        }
+    }
+
+    /// Adds the time from `start` to `end` with `tone` to the bits, deciding every bit that ends before `end`.
+    /// `confidence` is how well the period fits its tone.
+    fn cover(&mut self, start: f64, end: f64, tone: Option<usize>, confidence: f32) {
+        let mut bit_start = *self.bit_start.get_or_insert(start);
+        let mut from = start.max(bit_start);
+        while end >= bit_start + self.bit_length {
+            let bit_end = bit_start + self.bit_length;
+            self.coverage[tone.unwrap_or(2)] += (bit_end - from).max(0.0);
+            self.fit += (bit_end - from).max(0.0) * confidence as f64;
+            let (bit, bit_confidence) = self.decide();
+            self.pending
+                .push_back((bit_end.round() as usize, bit, bit_confidence));
+            self.coverage = [0.0; 3];
+            self.fit = 0.0;
+            bit_start = bit_end;
+            from = bit_end;
+        }
+        self.coverage[tone.unwrap_or(2)] += (end - from).max(0.0);
+        self.fit += (end - from).max(0.0) * confidence as f64;
+        self.bit_start = Some(bit_start);
+    }
+
+    /// Where the tone changes within a period that starts with `tone` and ends with the other tone.
+    /// When the phase is continuous at the change, the period is part of a period of each tone, which gives the position.
+    fn transition(&self, tone: usize, start: f64, period: f64) -> Option<f64> {
+        let frequencies = self.tracker.frequencies();
+        let (last_period, next_period) = (
+            self.sample_rate / frequencies[tone] as f64,
+            self.sample_rate / frequencies[1 - tone] as f64,
+        );
+        let part = (next_period - period) / (next_period - last_period);
+        match part > 0.0 && part < 1.0 {
+            true => Some(start + part * last_period),
+            false => None,
+        }
+    }
+
+    fn decide(&self) -> (SignalCondition, f32) {
+        let tone = match self.coverage[0] >= self.coverage[1] {
+            true => 0,
+            false => 1,
+        };
+        if self.coverage[tone] < MIN_TONE_COVERAGE * self.bit_length {
+            return (SignalCondition::Error, 0.0);
+        }
+        let margin =
+            (self.coverage[tone] - self.coverage[1 - tone] - self.coverage[2]) / self.bit_length;
+        let fit = self.fit / self.coverage.iter().sum::<f64>();
+        (self.signals[tone], margin.min(fit).clamp(0.0, 1.0) as f32)
+    }
+
+    /// Returns the next decided bit and keeps its confidence
+    fn next_bit(&mut self) -> Option<(usize, SignalCondition)> {
+        let (idx, bit, confidence) = self.pending.pop_front()?;
+        self.confidence = confidence;
+        Some((idx, bit))
+    }
+
+    /// Waits for the signal to come back after a gap
+    fn unlock(&mut self) {
+        self.bit_start = None;
+        self.locked = false;
+        self.coverage = [0.0; 3];
+        self.fit = 0.0;
+        self.last_tone = None;
+    }
+}
+
+impl Stage for BitClockRecovery {
+    type Input = (usize, f32);
+    type Output = (usize, SignalCondition);
+
+    fn process(&mut self, input: Self::Input) -> Option<Self::Output> {
+        let (sample_index, frequency) = input;
+        let period = self.sample_rate / frequency as f64;
+        // The periods follow each other, so adding them up keeps the precision of interpolated crossings
+        let end = match self.last_end {
+            Some(last_end) if (last_end + period - sample_index as f64).abs() < 2.0 => {
+                last_end + period
+            }
+            _ => sample_index as f64,
+        };
+        self.last_end = Some(end);
+        let start = end - period;
+
+        if period > MAX_PERIOD_BITS * self.bit_length {
+            self.unlock();
+            self.pending
+                .push_back((sample_index, SignalCondition::Error, 0.0));
+            return self.next_bit();
+        }
+
+        let tone = self.tracker.tone(frequency);
+        let confidence = tone.map_or(1.0, |tone| self.tracker.confidence(tone, frequency));
+        self.bit_length = self.nominal_bit_length / self.tracker.speed() as f64;
+        match (tone, self.last_tone) {
+            (Some(tone), Some((last_tone, last_end))) if tone != last_tone => {
+                // Periods of neither tone between the two tones are split between them
+                match start - last_end < 1.0 {
+                    true => self.correct(start),
+                    false => self.correct((last_end + start) / 2.0),
+                }
+                self.cover(start, end, Some(tone), confidence);
+                self.last_tone = Some((tone, end));
+            }
+            (Some(tone), _) => {
+                self.cover(start, end, Some(tone), confidence);
+                self.last_tone = Some((tone, end));
+            }
+            (None, Some((last_tone, last_end))) if start - last_end < 1.0 => {
+                match self.transition(last_tone, start, period) {
+                    Some(boundary) => {
+                        let next_tone = 1 - last_tone;
+                        self.correct(boundary);
+                        self.cover(start, boundary, Some(last_tone), 1.0);
+                        self.cover(boundary, end, Some(next_tone), 1.0);
+                        self.last_tone = Some((next_tone, end));
+                    }
+                    None => self.cover(start, end, None, 1.0),
+                }
+            }
+            (None, _) => self.cover(start, end, None, 1.0),
+        }
+        self.next_bit()
+    }
+
+    fn reset(&mut self) {
+        self.unlock();
+        self.tracker.reset();
+        self.bit_length = self.nominal_bit_length;
+        self.last_end = None;
+        self.confidence = 1.0;
+        self.pending.clear();
+    }
+
+    fn flush(&mut self) -> Vec<Self::Output> {
+        if let Some((_, _, confidence)) = self.pending.back() {
+            self.confidence = *confidence;
+        }
+        self.pending
+            .drain(..)
+            .map(|(idx, bit, _)| (idx, bit))
+            .collect()
+    }
+
+    fn speed(&self) -> Option<f32> {
+        Some(self.tracker.speed())
+    }
+
+    fn confidence(&self) -> Option<f32> {
+        Some(self.confidence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    /// Length of a bit at the nominal speed, in samples at 48 kHz
+    fn bit_length(config: &DecoderConfig) -> f64 {
+        let symbol = config.symbols[0];
+        symbol.length() * 48000.0
+    }
+
+    /// Frequencies measured between the rising zero crossings of phase continuous FSK, as `FrequencyIdentifier` reports them
+    fn frequencies(
+        config: &DecoderConfig,
+        bits: &[SignalCondition],
+        phase: f64,
+        speed: f64,
+    ) -> Vec<(usize, f32)> {
+        let bit_length = bit_length(config) / speed;
+        let mut output = vec![];
+        let (mut cycles, mut last_crossing) = (phase, None);
+        for (idx, bit) in bits.iter().enumerate() {
+            let symbol = config.symbols.iter().find(|s| s.signal == *bit).unwrap();
+            let cycles_per_sample = symbol.frequency as f64 * speed / 48000.0;
+            let end_cycles = cycles + cycles_per_sample * bit_length;
+            for cycle in (cycles.floor() as usize + 1)..=(end_cycles.floor() as usize) {
+                let crossing =
+                    (idx as f64 + (cycle as f64 - cycles) / (end_cycles - cycles)) * bit_length;
+                if let Some(last_crossing) = last_crossing {
+                    output.push((
+                        crossing as usize,
+                        (48000.0 / (crossing - last_crossing)) as f32,
+                    ));
+                }
+                last_crossing = Some(crossing);
+            }
+            cycles = end_cycles;
+        }
+        output
+    }
+
+    /// Leader, then the frames of "KCS" with one start and one stop bit, then trailer
+    fn test_bits() -> (Vec<SignalCondition>, Vec<SignalCondition>) {
+        let (mark, space) = (SignalCondition::Mark, SignalCondition::Space);
+        let frames = b"KCS"
+            .iter()
+            .flat_map(|byte| {
+                std::iter::once(space)
+                    .chain((0..8).map(move |bit| match byte >> bit & 1 {
+                        1 => mark,
+                        _ => space,
+                    }))
+                    .chain(std::iter::once(mark))
+            })
+            .collect::<Vec<_>>();
+        let bits = [vec![mark; 10], frames.clone(), vec![mark; 4]].concat();
+        (bits, frames)
+    }
+
+    fn run(
+        stage: &mut impl Stage<Input = (usize, f32), Output = (usize, SignalCondition)>,
+        input: &[(usize, f32)],
+    ) -> Vec<SignalCondition> {
+        let mut bits = input
+            .iter()
+            .filter_map(|val| stage.process(*val))
+            .collect::<Vec<_>>();
+        bits.extend(stage.flush());
+        bits.into_iter().map(|(_, bit)| bit).collect()
+    }
+
+    fn contains(bits: &[SignalCondition], frames: &[SignalCondition]) -> bool {
+        bits.windows(frames.len()).any(|window| window == frames)
+    }
+
+    #[test]
+    fn bit_clock_recovery_phase_continuous() {
+        let (bits, frames) = test_bits();
+        for preset in [Preset::Std, Preset::NASCOM, Preset::MSX2400] {
+            let config = DecoderConfig::get_preset(&preset);
+            for phase in [0.0, 0.3, 0.8] {
+                for speed in [0.96, 1.0, 1.04] {
+                    let input = frequencies(&config, &bits, phase, speed);
+                    let mut clock = BitClockRecovery::new(&config, 48000).unwrap();
+                    let output = run(&mut clock, &input);
+                    assert!(
+                        contains(&output, &frames),
+                        "{preset} {phase} {speed}: {output:?}"
+                    );
+                    let error = clock.bit_length() * speed / bit_length(&config) - 1.0;
+                    assert!(error.abs() < 0.01, "{preset} {phase} {speed}: {error}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bit_clock_recovery_cycle_slip() {
+        let (bits, frames) = test_bits();
+        let config = DecoderConfig::get_preset(&Preset::Std);
+        let mut input = frequencies(&config, &bits, 0.0, 1.0);
+        // A crossing in the first data bit, a mark, is lost, so two periods of 2400 Hz look like one of 1200 Hz.
+        // The leader gives 8 periods per bit without the first, then 4 periods of the start bit.
+        let idx = 10 * 8 - 1 + 4 + 2;
+        assert_eq!(input[idx].1, 2400.0);
+        input[idx + 1].1 = 1200.0;
+        input.remove(idx);
+
+        let mut clock = BitClockRecovery::new(&config, 48000).unwrap();
+        assert!(contains(&run(&mut clock, &input), &frames));
+
+        // Counting periods, the lost period shifts all later bits
+        let mut hi_low = HiLowIdentifier::new(
+            2400,
+            1200,
+            10,
+            (4, SignalCondition::Space),
+            (8, SignalCondition::Mark),
+        )
+        .unwrap();
+        assert!(!contains(&run(&mut hi_low, &input), &frames));
+    }
+}
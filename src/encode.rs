@@ -0,0 +1,366 @@
+use crate::{BitOrder, DecoderConfig, DecoderError, Parity, SignalCondition, Symbol, Word};
+use riff_wave::WaveWriter;
+use std::io::{Seek, Write};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EncoderConfig {
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    /// Peak amplitude relative to full scale, 0.0 - 1.0
+    pub amplitude: f32,
+    /// Length of the idle tone before the data in seconds
+    pub leader_length: f32,
+    /// Length of the idle tone after the data in seconds
+    pub trailer_length: f32,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            amplitude: 0.5,
+            leader_length: 2.0,
+            trailer_length: 1.0,
+        }
+    }
+}
+
+/// Encoder transforms from bytes -> samples
+///
+/// Each bit is sent as the configured number of half periods of the symbol frequency for its level, a half stop bit as half of them.
+/// Each bit starts at a zero crossing, rising unless the bit before it ended after an odd number of half periods,
+/// and the leader and trailer use the stop bit level as idle tone.
+/// With more than 8 data bits, each word is read from two bytes, see `DecoderConfig::word_bytes`.
+///
+/// ```
+/// use kcs_decoder::*;
+///
+/// let encoder_config = EncoderConfig {
+///     sample_rate: 48000,
+///     ..Default::default()
+/// };
+/// let mut encoder = Encoder::new(DecoderConfig::default(), encoder_config).unwrap();
+/// let samples = encoder.encode_word(0x55);
+/// // 1 start bit, 8 data bits and 1 stop bit at 1200 baud
+/// assert_eq!(samples.len(), 48000 * 10 / 1200);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    config: DecoderConfig,
+    encoder_config: EncoderConfig,
+    /// Symbols of the space and mark levels
+    levels: [Symbol; 2],
+    /// Time in samples, kept as a fraction to avoid drift when a bit is not a whole number of samples
+    time: f64,
+    /// -1.0 after an odd number of half periods, so the next bit continues with a falling crossing
+    polarity: f64,
+    num_samples: usize,
+}
+
+impl Encoder {
+    /// Returns `None` if the configuration is invalid, or if the start or stop bit is not a level that has a symbol
+    pub fn new(config: DecoderConfig, encoder_config: EncoderConfig) -> Option<Self> {
+        if encoder_config.sample_rate == 0
+            || !(0.0..=1.0).contains(&encoder_config.amplitude)
+            || !matches!(encoder_config.bits_per_sample, 8 | 16 | 24 | 32)
+            || [config.startbits.1, config.stopbits.1].contains(&SignalCondition::Error)
+        {
+            return None;
+        }
+        let symbol = |level| config.symbols.iter().find(|s| s.signal == level).copied();
+        Some(Self {
+            config: config.validate()?,
+            encoder_config,
+            levels: [
+                symbol(SignalCondition::Space)?,
+                symbol(SignalCondition::Mark)?,
+            ],
+            time: 0.0,
+            polarity: 1.0,
+            num_samples: 0,
+        })
+    }
+
+    /// Encodes the data with leader and trailer tones
+    pub fn encode(&mut self, data: &[u8]) -> Vec<f32> {
+        let mut samples = self.tone(self.encoder_config.leader_length);
+        for val in self.config.bytes_to_words(data) {
+            samples.extend(self.encode_word(val));
+        }
+        samples.extend(self.tone(self.encoder_config.trailer_length));
+        samples
+    }
+
+    /// Idle tone of the given length in seconds
+    pub fn tone(&mut self, seconds: f32) -> Vec<f32> {
+        let idle = self.level(self.config.stopbits.1);
+        let num_bits = (seconds * self.bit_rate()).round() as usize;
+        (0..num_bits)
+            .flat_map(|_| self.encode_symbol(idle, 1.0))
+            .collect()
+    }
+
+    /// Samples of one frame with the data bits of a byte, see `encode_word`
+    pub fn encode_byte(&mut self, val: u8) -> Vec<f32> {
+        self.encode_word(Word::from(val))
+    }
+
+    pub fn encode_word(&mut self, val: Word) -> Vec<f32> {
+        let num_databits = self.config.num_databits;
+        let [space, mark] = self.levels;
+        let (start, stop) = (
+            self.level(self.config.startbits.1),
+            self.level(self.config.stopbits.1),
+        );
+        let mut bits = vec![start; self.config.startbits.0];
+        let data_bits = (0..num_databits).map(|i| {
+            let bit = match self.config.bit_order {
+                BitOrder::LsbFirst => i,
+                BitOrder::MsbFirst => num_databits - 1 - i,
+            };
+            match (val >> bit) & 1 {
+                1 => mark,
+                _ => space,
+            }
+        });
+        bits.extend(data_bits);
+
+        let data_marks_odd =
+            (val & (Word::MAX >> (Word::BITS as usize - num_databits))).count_ones() % 2 == 1;
+        match self.config.parity {
+            Parity::NONE => {}
+            Parity::EVEN if data_marks_odd => bits.push(mark),
+            Parity::ODD if !data_marks_odd => bits.push(mark),
+            Parity::EVEN | Parity::ODD | Parity::SPACE => bits.push(space),
+            Parity::MARK => bits.push(mark),
+        }
+        bits.extend(vec![stop; self.config.stopbits.0]);
+
+        let mut samples = bits
+            .into_iter()
+            .flat_map(|symbol| self.encode_symbol(symbol, 1.0))
+            .collect::<Vec<_>>();
+        if self.config.half_stopbit {
+            samples.extend(self.encode_symbol(stop, 0.5));
+        }
+        samples
+    }
+
+    /// Samples of one bit, an error cannot be sent
+    pub fn encode_bit(&mut self, level: SignalCondition) -> Result<Vec<f32>, DecoderError> {
+        match level {
+            SignalCondition::Space | SignalCondition::Mark => {
+                Ok(self.encode_symbol(self.level(level), 1.0))
+            }
+            SignalCondition::Error => Err(DecoderError::Signal),
+        }
+    }
+
+    /// Symbol of a start or stop bit or of a level, `new` makes sure these are never an error
+    fn level(&self, level: SignalCondition) -> Symbol {
+        match level {
+            SignalCondition::Space => self.levels[0],
+            SignalCondition::Mark => self.levels[1],
+            SignalCondition::Error => {
+                unreachable!("Encoder::new rejects start and stop bits that are errors")
+            }
+        }
+    }
+
+    /// Samples of `length` bits of `symbol`
+    fn encode_symbol(&mut self, symbol: Symbol, length: f64) -> Vec<f32> {
+        let sample_rate = self.encoder_config.sample_rate as f64;
+        let start = self.time;
+        self.time += length * symbol.length() * sample_rate;
+        let phase_step = 2.0 * std::f64::consts::PI * symbol.frequency as f64 / sample_rate;
+
+        // Every bit starts at a zero crossing, also when it does not start on a sample
+        let mut samples = vec![];
+        while (self.num_samples as f64) < self.time {
+            let phase = (self.num_samples as f64 - start) * phase_step;
+            samples
+                .push((self.polarity * phase.sin() * self.encoder_config.amplitude as f64) as f32);
+            self.num_samples += 1;
+        }
+        if (length * symbol.half_periods as f64).round() as usize % 2 == 1 {
+            self.polarity = -self.polarity;
+        }
+        samples
+    }
+
+    fn bit_rate(&self) -> f32 {
+        let symbol = self.config.symbols[0];
+        1.0 / symbol.length() as f32
+    }
+}
+
+/// Writes normalized samples to a mono PCM wave file
+pub fn write_wav<T: Write + Seek>(
+    writer: T,
+    samples: &[f32],
+    sample_rate: u32,
+    bits_per_sample: u16,
+) -> Result<(), DecoderError> {
+    let to_io_error = |error: riff_wave::WriteError| DecoderError::IO(error.to_string());
+    let mut wavewriter =
+        WaveWriter::new(1, sample_rate, bits_per_sample, writer).map_err(to_io_error)?;
+    let scale = (u32::pow(2, bits_per_sample as u32 - 1) - 1) as f32;
+    for &sample in samples {
+        let val = (sample.clamp(-1.0, 1.0) * scale).round();
+        match bits_per_sample {
+            8 => wavewriter.write_sample_u8((val as i16 + 128) as u8),
+            16 => wavewriter.write_sample_i16(val as i16),
+            24 => wavewriter.write_sample_i24(val as i32),
+            32 => wavewriter.write_sample_i32(val as i32),
+            _ => return Err(DecoderError::Config),
+        }
+        .map_err(to_io_error)?;
+    }
+    wavewriter.sync_header()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    /// Decodes the samples with the zero crossing demodulator, counting periods
+    fn decode(
+        config: DecoderConfig,
+        samples: Vec<f32>,
+        sample_rate: u32,
+    ) -> Vec<Result<Word, DecoderError>> {
+        let mut zc_detector = ZeroCrossingDetector::new(0.0);
+        let mut frq_calculator = FrequencyIdentifier::new(ZeroCrossingDirection::Pos, sample_rate);
+        let mut hi_low_identifier = HiLowIdentifier::new(
+            config.symbols[0].frequency as u32,
+            config.symbols[1].frequency as u32,
+            config.frequency_tolerance as u8,
+            (
+                config.symbols[1].count(false) as u8,
+                config.symbols[1].signal,
+            ),
+            (
+                config.symbols[0].count(false) as u8,
+                config.symbols[0].signal,
+            ),
+        )
+        .unwrap();
+        let mut decoder = Decoder::new(config).unwrap();
+        samples
+            .into_iter()
+            .enumerate()
+            .filter_map(|val| zc_detector.process(val))
+            .filter_map(|val| frq_calculator.process(val))
+            .filter_map(|val| hi_low_identifier.process(val))
+            .filter_map(|(_, val)| match decoder.process(val) {
+                Ok(val) => Some(Ok(val)),
+                Err(error) => error.map(Err),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn encoder_roundtrip() {
+        let encoder_config = EncoderConfig {
+            sample_rate: 44100,
+            ..Default::default()
+        };
+        let data = b"Kansas City Standard \x00\xFF\x55\xAA";
+        for preset in [Preset::Std, Preset::NASCOM] {
+            let config = DecoderConfig::get_preset(&preset);
+            let samples = Encoder::new(config, encoder_config).unwrap().encode(data);
+            let output = decode(config, samples, encoder_config.sample_rate)
+                .into_iter()
+                .filter_map(Result::ok)
+                .collect::<Vec<_>>();
+
+            assert_eq!(config.words_to_bytes(&output), data, "{preset}");
+        }
+    }
+
+    #[test]
+    fn encoder_parity_roundtrip() {
+        let encoder_config = EncoderConfig {
+            sample_rate: 44100,
+            ..Default::default()
+        };
+        let data = (0..128).collect::<Vec<u8>>();
+        for (parity, other) in [(Parity::EVEN, Parity::ODD), (Parity::ODD, Parity::EVEN)] {
+            let config = DecoderConfig {
+                num_databits: 7,
+                parity,
+                ..Default::default()
+            };
+            let samples = Encoder::new(config, encoder_config).unwrap().encode(&data);
+            let output = decode(config, samples.clone(), encoder_config.sample_rate);
+            let expected = data.iter().map(|&val| Ok(val as Word)).collect::<Vec<_>>();
+            assert_eq!(output, expected, "{parity}");
+
+            // Every word fails the check of the opposite parity
+            let output = decode(
+                DecoderConfig {
+                    parity: other,
+                    ..config
+                },
+                samples,
+                encoder_config.sample_rate,
+            );
+            assert_eq!(output.len(), data.len(), "{parity}");
+            assert!(
+                output.iter().all(|val| *val == Err(DecoderError::Parity)),
+                "{parity}"
+            );
+        }
+    }
+
+    #[test]
+    fn encoder_parity() {
+        let mut config = DecoderConfig {
+            num_databits: 7,
+            parity: Parity::EVEN,
+            ..Default::default()
+        };
+        let encoder_config = EncoderConfig {
+            sample_rate: 48000,
+            ..Default::default()
+        };
+        let mut encoder = Encoder::new(config, encoder_config).unwrap();
+        // Start bit, 7 data bits, parity and stop bit
+        assert_eq!(encoder.encode_word(0x01).len(), 10 * 40);
+        assert_eq!(encoder.encode_byte(0x01).len(), 10 * 40);
+
+        assert_eq!(
+            encoder.encode_bit(SignalCondition::Error),
+            Err(DecoderError::Signal)
+        );
+        assert!(Encoder::new(
+            DecoderConfig {
+                startbits: (1, SignalCondition::Error),
+                ..config
+            },
+            encoder_config
+        )
+        .is_none());
+
+        // Start bit, 9 data bits and 1.5 stop bits
+        config.num_databits = 9;
+        config.parity = Parity::NONE;
+        config.half_stopbit = true;
+        let mut encoder = Encoder::new(config, encoder_config).unwrap();
+        assert_eq!(encoder.encode_word(0x1ff).len(), 11 * 40 + 20);
+
+        config.num_databits = 8;
+        config.half_stopbit = false;
+        assert!(Encoder::new(
+            config,
+            EncoderConfig {
+                amplitude: 1.5,
+                ..encoder_config
+            }
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,223 @@
+use crate::{DecoderConfig, Filter, Stage};
+
+/// Cut-off of the DC blocking filter in Hz
+const DC_BLOCK_CUTOFF: f64 = 20.0;
+/// Highest cut-off relative to the sample rate, a low-pass above this is left out
+const MAX_RELATIVE_CUTOFF: f32 = 0.45;
+
+/// DC blocking filter
+///
+/// First order high-pass, y[n] = x[n] - x[n-1] + r * y[n-1], with a cut-off of a few Hz.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DcBlocker {
+    r: f64,
+    last_input: f64,
+    last_output: f64,
+}
+
+impl DcBlocker {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            r: 1.0 - 2.0 * std::f64::consts::PI * DC_BLOCK_CUTOFF / sample_rate as f64,
+            last_input: 0.0,
+            last_output: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let input = sample as f64;
+        self.last_output = input - self.last_input + self.r * self.last_output;
+        self.last_input = input;
+        self.last_output as f32
+    }
+
+    pub fn reset(&mut self) {
+        self.last_input = 0.0;
+        self.last_output = 0.0;
+    }
+}
+
+/// Second order IIR filter section
+///
+/// Butterworth low- and high-pass sections with coefficients from the RBJ audio EQ cookbook.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    input: [f64; 2],
+    output: [f64; 2],
+}
+
+impl Biquad {
+    pub fn low_pass(cutoff: f32, sample_rate: u32) -> Self {
+        let (cos, alpha) = Self::prototype(cutoff, sample_rate);
+        Self::normalized(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            cos,
+            alpha,
+        )
+    }
+
+    pub fn high_pass(cutoff: f32, sample_rate: u32) -> Self {
+        let (cos, alpha) = Self::prototype(cutoff, sample_rate);
+        Self::normalized(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            cos,
+            alpha,
+        )
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let x = sample as f64;
+        let y = self.b[0] * x + self.b[1] * self.input[0] + self.b[2] * self.input[1]
+            - self.a[0] * self.output[0]
+            - self.a[1] * self.output[1];
+        self.input = [x, self.input[0]];
+        self.output = [y, self.output[0]];
+        y as f32
+    }
+
+    pub fn reset(&mut self) {
+        self.input = [0.0; 2];
+        self.output = [0.0; 2];
+    }
+
+    fn prototype(cutoff: f32, sample_rate: u32) -> (f64, f64) {
+        let omega = 2.0 * std::f64::consts::PI * cutoff as f64 / sample_rate as f64;
+        (omega.cos(), omega.sin() / std::f64::consts::SQRT_2)
+    }
+
+    fn normalized(b: [f64; 3], cos: f64, alpha: f64) -> Self {
+        let a0 = 1.0 + alpha;
+        Self {
+            b: b.map(|val| val / a0),
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            input: [0.0; 2],
+            output: [0.0; 2],
+        }
+    }
+}
+
+/// Pre-filter transforms from samples -> filtered samples
+///
+/// Removes DC offset, hum and hiss before the zero crossing detection, as selected by `DecoderConfig::filter`.
+/// The band-pass is a second order high-pass and a second order low-pass at the cut-offs from `DecoderConfig::filter_band`.
+///
+/// ```
+/// use kcs_decoder::*;
+///
+/// let config = DecoderConfig::get_preset(&Preset::NASCOM);
+/// assert_eq!(config.filter_band(), Some((150.0, 4800.0)));
+///
+/// // A constant offset is removed
+/// let mut filter = PreFilter::new(&config, 48000);
+/// let output = (0..48000).filter_map(|idx| filter.process((idx, 0.5))).last();
+/// assert!(output.unwrap().1.abs() < 0.001);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreFilter {
+    dc_blocker: Option<DcBlocker>,
+    sections: Vec<Biquad>,
+}
+
+impl PreFilter {
+    pub fn new(config: &DecoderConfig, sample_rate: u32) -> Self {
+        let mut sections = vec![];
+        if let Some((low, high)) = config.filter_band() {
+            if low > 0.0 {
+                sections.push(Biquad::high_pass(low, sample_rate));
+            }
+            if high < sample_rate as f32 * MAX_RELATIVE_CUTOFF {
+                sections.push(Biquad::low_pass(high, sample_rate));
+            }
+        }
+        Self {
+            dc_blocker: (config.filter != Filter::Off).then(|| DcBlocker::new(sample_rate)),
+            sections,
+        }
+    }
+}
+
+impl Stage for PreFilter {
+    type Input = (usize, f32);
+    type Output = (usize, f32);
+
+    fn process(&mut self, input: Self::Input) -> Option<Self::Output> {
+        let (idx, mut sample) = input;
+        if let Some(dc_blocker) = &mut self.dc_blocker {
+            sample = dc_blocker.process(sample);
+        }
+        for section in &mut self.sections {
+            sample = section.process(sample);
+        }
+        Some((idx, sample))
+    }
+
+    fn reset(&mut self) {
+        self.dc_blocker.iter_mut().for_each(DcBlocker::reset);
+        self.sections.iter_mut().for_each(Biquad::reset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Peak amplitude of the filtered sine after the filter has settled
+    fn response(filter: &mut PreFilter, frequency: f32, sample_rate: u32) -> f32 {
+        filter.reset();
+        (0..sample_rate as usize / 10)
+            .map(|n| {
+                let phase = 2.0 * std::f32::consts::PI * frequency * n as f32 / sample_rate as f32;
+                filter.process((n, phase.sin())).unwrap().1
+            })
+            .skip(sample_rate as usize / 20)
+            .fold(0.0, |peak, val| val.abs().max(peak))
+    }
+
+    #[test]
+    fn filter_band_pass_response() {
+        let config = DecoderConfig::default();
+        let mut filter = PreFilter::new(&config, 48000);
+        for frequency in [1200.0, 2400.0] {
+            let gain = response(&mut filter, frequency, 48000);
+            assert!(gain > 0.85, "{frequency} Hz: {gain}");
+        }
+        for frequency in [20.0, 20000.0] {
+            let gain = response(&mut filter, frequency, 48000);
+            assert!(gain < 0.1, "{frequency} Hz: {gain}");
+        }
+    }
+
+    #[test]
+    fn filter_modes() {
+        let mut config = DecoderConfig {
+            filter: Filter::Off,
+            ..Default::default()
+        };
+        assert_eq!(
+            PreFilter::new(&config, 48000),
+            PreFilter {
+                dc_blocker: None,
+                sections: vec![]
+            }
+        );
+        assert!(response(&mut PreFilter::new(&config, 48000), 50.0, 48000) > 0.999);
+
+        config.filter = Filter::DcBlock;
+        assert_eq!(config.filter_band(), None);
+        let gain = response(&mut PreFilter::new(&config, 48000), 50.0, 48000);
+        assert!(gain > 0.9, "{gain}");
+
+        // The low-pass is left out if it would be too close to the Nyquist frequency
+        config.filter = Filter::Band(300, 6000);
+        assert_eq!(PreFilter::new(&config, 48000).sections.len(), 2);
+        assert_eq!(PreFilter::new(&config, 12000).sections.len(), 1);
+
+        assert_eq!(Filter::from("off"), Filter::Off);
+        assert_eq!(Filter::from("dc"), Filter::DcBlock);
+        assert_eq!(Filter::from("300-6000"), Filter::Band(300, 6000));
+        assert_eq!(Filter::from("6000-300"), Filter::Auto);
+        assert_eq!(Filter::from("auto"), Filter::Auto);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::{DecoderError, SampleFormat, WaveFormat};
+use std::io::Read;
+
+/// FLAC stream reader
+///
+/// Reads the interleaved samples of all channels normalized to -1.0 - 1.0, like `WaveReader` does for wave files.
+pub struct FlacReader<T: Read> {
+    reader: claxon::FlacReader<T>,
+    pub format: WaveFormat,
+    scale: f32,
+    /// Interleaved samples of the current block
+    samples: Vec<f32>,
+    idx: usize,
+    buffer: Vec<i32>,
+}
+
+impl<T: Read> FlacReader<T> {
+    pub fn new(reader: T) -> Result<Self, DecoderError> {
+        let reader = claxon::FlacReader::new(reader)
+            .map_err(|error| DecoderError::IO(format!("FLAC: {error}")))?;
+        let streaminfo = reader.streaminfo();
+        let format = WaveFormat {
+            num_channels: streaminfo.channels as u16,
+            sample_rate: streaminfo.sample_rate,
+            bits_per_sample: streaminfo.bits_per_sample as u16,
+            sample_format: SampleFormat::Int,
+        };
+        Ok(Self {
+            reader,
+            format,
+            scale: u32::pow(2, streaminfo.bits_per_sample - 1) as f32,
+            samples: vec![],
+            idx: 0,
+            buffer: vec![],
+        })
+    }
+
+    /// Reads the next sample, of the next channel for multi-channel files
+    pub fn read_sample(&mut self) -> Option<f32> {
+        while self.idx >= self.samples.len() {
+            let buffer = std::mem::take(&mut self.buffer);
+            let block = self.reader.blocks().read_next_or_eof(buffer).ok()??;
+            self.samples.clear();
+            for idx in 0..block.duration() {
+                for channel in 0..block.channels() {
+                    self.samples
+                        .push(block.sample(channel, idx) as f32 / self.scale);
+                }
+            }
+            self.idx = 0;
+            self.buffer = block.into_buffer();
+        }
+        self.idx += 1;
+        Some(self.samples[self.idx - 1])
+    }
+}
+
+/// Reads one channel from a FLAC stream, like `WaveReaderIteratorMono` does for wave files
+pub struct FlacReaderIteratorMono<T: Read> {
+    reader: FlacReader<T>,
+    channel: u16,
+}
+
+impl<T: Read> FlacReaderIteratorMono<T> {
+    pub fn new(reader: FlacReader<T>, channel_idx: u8) -> Result<Self, DecoderError> {
+        if channel_idx as u16 >= reader.format.num_channels {
+            return Err(DecoderError::Signal);
+        }
+        Ok(Self {
+            reader,
+            channel: channel_idx as u16,
+        })
+    }
+}
+
+impl<T: Read> Iterator for FlacReaderIteratorMono<T> {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut sample = None;
+        for channel in 0..self.reader.format.num_channels {
+            let val = self.reader.read_sample()?;
+            if channel == self.channel {
+                sample = Some(val);
+            }
+        }
+        sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crc8(data: &[u8]) -> u8 {
+        data.iter().fold(0u8, |crc, &byte| {
+            (0..8).fold(crc ^ byte, |crc, _| match crc & 0x80 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x07,
+            })
+        })
+    }
+
+    fn crc16(data: &[u8]) -> u16 {
+        data.iter().fold(0u16, |crc, &byte| {
+            (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x8005,
+            })
+        })
+    }
+
+    /// 44.1kHz 16 bit FLAC stream with uncompressed (verbatim) subframes
+    fn flac_stream(channels: &[Vec<i16>], block_size: usize) -> Vec<u8> {
+        let num_samples = channels[0].len();
+        let mut stream = b"fLaC".to_vec();
+        stream.extend([0x80, 0, 0, 34]);
+        stream.extend((block_size as u16).to_be_bytes());
+        stream.extend((block_size as u16).to_be_bytes());
+        stream.extend([0; 6]);
+        let info =
+            44100 << 44 | ((channels.len() as u64 - 1) << 41) | (15 << 36) | num_samples as u64;
+        stream.extend(info.to_be_bytes());
+        stream.extend([0; 16]);
+
+        for (frame_no, start) in (0..num_samples).step_by(block_size).enumerate() {
+            let len = block_size.min(num_samples - start);
+            let mut frame = vec![0xFF, 0xF8, 0x79, ((channels.len() as u8 - 1) << 4) | 0x08];
+            frame.push(frame_no as u8);
+            frame.extend((len as u16 - 1).to_be_bytes());
+            frame.push(crc8(&frame));
+            for channel in channels {
+                frame.push(0x02);
+                frame.extend(
+                    channel[start..start + len]
+                        .iter()
+                        .flat_map(|val| val.to_be_bytes()),
+                );
+            }
+            frame.extend(crc16(&frame).to_be_bytes());
+            stream.extend(frame);
+        }
+        stream
+    }
+
+    #[test]
+    fn flac_reader_channels() {
+        let left = (0..1000).map(|n| (n * 30) as i16).collect::<Vec<_>>();
+        let right = left.iter().map(|val| -val).collect::<Vec<_>>();
+        let stream = flac_stream(&[left.clone(), right.clone()], 256);
+
+        let reader = FlacReader::new(stream.as_slice()).unwrap();
+        assert_eq!(
+            reader.format,
+            WaveFormat {
+                num_channels: 2,
+                sample_rate: 44100,
+                bits_per_sample: 16,
+                sample_format: SampleFormat::Int,
+            }
+        );
+        let samples = FlacReaderIteratorMono::new(reader, 1)
+            .unwrap()
+            .collect::<Vec<_>>();
+        let expected = right
+            .iter()
+            .map(|val| *val as f32 / 32768.0)
+            .collect::<Vec<_>>();
+        assert_eq!(samples, expected);
+
+        let mut reader = FlacReader::new(stream.as_slice()).unwrap();
+        assert_eq!(reader.read_sample(), Some(0.0));
+        assert_eq!(reader.read_sample(), Some(0.0));
+        assert_eq!(reader.read_sample(), Some(30.0 / 32768.0));
+        assert_eq!(reader.read_sample(), Some(-30.0 / 32768.0));
+
+        let reader = FlacReader::new(stream.as_slice()).unwrap();
+        assert!(FlacReaderIteratorMono::new(reader, 2).is_err());
+        assert!(FlacReader::new(&b"RIFF"[..]).is_err());
+    }
+}
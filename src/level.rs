@@ -0,0 +1,259 @@
+use crate::Stage;
+use std::fmt::Display;
+
+/// Release time of the envelope in seconds, long enough to hold the level between the peaks of the lowest tone
+const ENVELOPE_RELEASE: f32 = 0.01;
+/// Release time of the signal level in seconds, holds the level over short gaps between blocks
+const SIGNAL_RELEASE: f32 = 1.0;
+/// The noise floor is the lowest envelope in this many seconds
+const NOISE_FLOOR_WINDOW: f32 = 2.0;
+/// Number of parts the window is split into, the minimum of each part is kept
+const NOISE_FLOOR_PARTS: usize = 8;
+/// Lowest level tracked, -100 dBFS
+const MIN_LEVEL: f32 = 1e-5;
+/// Signal level the AGC normalizes to
+const AGC_TARGET: f32 = 0.5;
+const AGC_MAX_GAIN: f32 = 1000.0;
+/// Hysteresis relative to the noise floor
+const HYSTERESIS_NOISE_FACTOR: f32 = 3.0;
+/// Highest hysteresis relative to the signal level, so the crossings of the signal are not delayed noticeably
+const HYSTERESIS_MAX_FRACTION: f32 = 0.1;
+
+/// Signal levels measured by `Agc`, relative to its output
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SignalLevels {
+    /// Peak level over the last few periods
+    pub envelope: f32,
+    /// Peak level of the recording, held over gaps between blocks
+    pub signal: f32,
+    /// Lowest recent envelope, i.e. the level of the noise between blocks
+    pub noise_floor: f32,
+    pub gain: f32,
+}
+
+impl SignalLevels {
+    /// Hysteresis that keeps the noise between blocks from causing zero crossings
+    pub fn hysteresis(&self) -> f32 {
+        (HYSTERESIS_NOISE_FACTOR * self.noise_floor).min(HYSTERESIS_MAX_FRACTION * self.signal)
+    }
+}
+
+/// Automatic gain control
+///
+/// Follows the envelope, the signal level and the noise floor of the input, and normalizes the signal level if enabled.
+/// When disabled, the levels are still measured and the samples pass through unchanged.
+///
+/// ```
+/// use kcs_decoder::*;
+///
+/// let mut agc = Agc::new(48000, true);
+/// let output = (0..48000)
+///     .filter_map(|idx| agc.process((idx, 0.01 * (idx as f32 * 0.3).sin())))
+///     .collect::<Vec<_>>();
+/// let peak = output[24000..].iter().fold(0.0f32, |peak, (_, val)| peak.max(val.abs()));
+/// assert!((peak - 0.5).abs() < 0.01);
+/// assert!((agc.levels().gain - 50.0).abs() < 1.0);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Agc {
+    enabled: bool,
+    envelope_decay: f32,
+    signal_decay: f32,
+    part_length: usize,
+    /// Samples before the envelope has risen from zero to the input level
+    settle_length: usize,
+    envelope: f32,
+    signal: f32,
+    /// Lowest envelope of the current part and of the previous parts in the window
+    part_minimum: f32,
+    part_minimums: [f32; NOISE_FLOOR_PARTS],
+    num_samples: usize,
+}
+
+impl Agc {
+    pub fn new(sample_rate: u32, enabled: bool) -> Self {
+        let sample_rate = sample_rate as f32;
+        Self {
+            enabled,
+            envelope_decay: (-1.0 / (ENVELOPE_RELEASE * sample_rate)).exp(),
+            signal_decay: (-1.0 / (SIGNAL_RELEASE * sample_rate)).exp(),
+            part_length: ((NOISE_FLOOR_WINDOW * sample_rate) as usize / NOISE_FLOOR_PARTS).max(1),
+            settle_length: (ENVELOPE_RELEASE * sample_rate) as usize,
+            envelope: 0.0,
+            signal: 0.0,
+            part_minimum: f32::MAX,
+            part_minimums: [f32::MAX; NOISE_FLOOR_PARTS],
+            num_samples: 0,
+        }
+    }
+
+    /// Current levels, relative to the output
+    pub fn levels(&self) -> SignalLevels {
+        let gain = self.gain();
+        SignalLevels {
+            envelope: self.envelope * gain,
+            signal: self.signal * gain,
+            noise_floor: self.noise_floor() * gain,
+            gain,
+        }
+    }
+
+    /// Whether the first part of the noise floor window is complete, before that the noise floor is not measured yet
+    pub fn noise_floor_measured(&self) -> bool {
+        self.num_samples >= self.part_length
+    }
+
+    fn noise_floor(&self) -> f32 {
+        self.part_minimums
+            .iter()
+            .fold(self.part_minimum, |min, val| min.min(*val))
+            .max(MIN_LEVEL)
+    }
+
+    fn gain(&self) -> f32 {
+        match self.enabled {
+            true => (AGC_TARGET / self.signal.max(MIN_LEVEL)).min(AGC_MAX_GAIN),
+            false => 1.0,
+        }
+    }
+}
+
+impl Stage for Agc {
+    type Input = (usize, f32);
+    type Output = (usize, f32);
+
+    fn process(&mut self, input: Self::Input) -> Option<Self::Output> {
+        let (idx, sample) = input;
+        self.envelope = (self.envelope * self.envelope_decay).max(sample.abs());
+        self.signal = (self.signal * self.signal_decay).max(self.envelope);
+        if self.num_samples >= self.settle_length {
+            self.part_minimum = self.part_minimum.min(self.envelope);
+        }
+        self.num_samples += 1;
+        if self.num_samples.is_multiple_of(self.part_length) {
+            self.part_minimums[self.num_samples / self.part_length % NOISE_FLOOR_PARTS] =
+                self.part_minimum;
+            self.part_minimum = f32::MAX;
+        }
+        Some((idx, sample * self.gain()))
+    }
+
+    fn reset(&mut self) {
+        self.envelope = 0.0;
+        self.signal = 0.0;
+        self.part_minimum = f32::MAX;
+        self.part_minimums = [f32::MAX; NOISE_FLOOR_PARTS];
+        self.num_samples = 0;
+    }
+}
+
+/// Range of the levels seen while decoding, for the diagnostic summary
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LevelSummary {
+    /// Highest signal level at the input
+    pub peak: f32,
+    /// Lowest noise floor at the input. The highest is not kept, during a long block it is the level of the tone
+    pub noise_floor: f32,
+    pub gain: (f32, f32),
+    /// Lowest and highest hysteresis of the zero crossing detector, relative to full scale
+    pub hysteresis: (f32, f32),
+}
+
+impl LevelSummary {
+    pub fn update(&mut self, levels: &SignalLevels, hysteresis: f32) {
+        let noise_floor = levels.noise_floor / levels.gain;
+        self.peak = self.peak.max(levels.signal / levels.gain);
+        self.noise_floor = self.noise_floor.min(noise_floor);
+        self.gain = (self.gain.0.min(levels.gain), self.gain.1.max(levels.gain));
+        self.hysteresis = (
+            self.hysteresis.0.min(hysteresis),
+            self.hysteresis.1.max(hysteresis),
+        );
+    }
+}
+
+impl Default for LevelSummary {
+    fn default() -> Self {
+        Self {
+            peak: 0.0,
+            noise_floor: f32::MAX,
+            gain: (f32::MAX, 0.0),
+            hysteresis: (f32::MAX, 0.0),
+        }
+    }
+}
+
+impl Display for LevelSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let db = |level: f32| 20.0 * level.max(MIN_LEVEL).log10();
+        write!(
+            f,
+            "peak {:.1} dBFS, noise floor {:.1} dBFS (SNR {:.1} dB), gain {:.1} to {:.1} dB, hysteresis {:.1}% to {:.1}%",
+            db(self.peak),
+            db(self.noise_floor),
+            db(self.peak) - db(self.noise_floor),
+            db(self.gain.0),
+            db(self.gain.1),
+            self.hysteresis.0 * 100.0,
+            self.hysteresis.1 * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agc_levels_and_hysteresis() {
+        // 0.5 seconds of noise at 0.001 followed by a tone at 0.1
+        let mut agc = Agc::new(48000, false);
+        let mut summary = LevelSummary::default();
+        for idx in 0..48000 {
+            let sample = match idx < 24000 {
+                true => 0.001 * if idx % 2 == 0 { 1.0 } else { -1.0 },
+                false => 0.1 * (idx as f32 * 0.3).sin(),
+            };
+            assert_eq!(agc.process((idx, sample)), Some((idx, sample)));
+            if agc.noise_floor_measured() {
+                summary.update(&agc.levels(), agc.levels().hysteresis());
+            }
+            if idx == 23999 {
+                // Noise only, the hysteresis is limited by the level of the noise itself
+                assert!((agc.levels().noise_floor - 0.001).abs() < 1e-5);
+                assert!((agc.levels().hysteresis() - 0.0001).abs() < 1e-6);
+            }
+        }
+        // The noise is still in the window, the hysteresis is above the noise
+        let levels = agc.levels();
+        assert!((levels.signal - 0.1).abs() < 0.001);
+        assert!((levels.noise_floor - 0.001).abs() < 1e-5);
+        assert!((levels.hysteresis() - 0.003).abs() < 1e-5);
+
+        // During a long tone, the noise floor rises to the tone and the hysteresis is limited by the signal level
+        for idx in 48000..192000 {
+            agc.process((idx, 0.1 * (idx as f32 * 0.3).sin()));
+        }
+        let levels = agc.levels();
+        assert!(levels.noise_floor > 0.09);
+        assert!((levels.hysteresis() - 0.01).abs() < 0.0001);
+
+        assert!((summary.peak - 0.1).abs() < 0.001);
+        assert_eq!(summary.gain, (1.0, 1.0));
+        assert_eq!(
+            summary.to_string(),
+            "peak -20.0 dBFS, noise floor -60.0 dBFS (SNR 40.0 dB), gain 0.0 to 0.0 dB, hysteresis 0.0% to 0.3%"
+        );
+
+        // A tone from the first sample, the envelope rising from zero is not taken as the noise floor
+        let mut agc = Agc::new(48000, false);
+        let mut summary = LevelSummary::default();
+        for idx in 0..48000 {
+            agc.process((idx, 0.1 * (idx as f32 * 0.3).sin()));
+            if agc.noise_floor_measured() {
+                summary.update(&agc.levels(), agc.levels().hysteresis());
+            }
+        }
+        assert!(summary.noise_floor > 0.09, "{}", summary.noise_floor);
+    }
+}
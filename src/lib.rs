@@ -1,56 +1,75 @@
 //#![allow(unused_imports, dead_code)]
 
 use core::fmt::Debug;
-use core::ops::Range;
-use riff_wave::WaveReader;
 use std::error::Error;
 use std::fmt::Display;
-use std::io::{Read, Seek};
-
-const MAX_NUM_STARTBITS: usize = 2;
+use std::io::Read;
+
+mod analyze;
+mod clock;
+mod encode;
+mod filter;
+#[cfg(feature = "flac")]
+mod flac;
+mod level;
+mod merge;
+pub mod nascom;
+mod pipeline;
+mod quadrature;
+mod resample;
+mod resync;
+pub mod sol20;
+mod speed;
+mod stage;
+mod wave;
+
+pub use analyze::{Analysis, Analyzer, Candidate};
+pub use clock::BitClockRecovery;
+pub use encode::{write_wav, Encoder, EncoderConfig};
+pub use filter::{Biquad, DcBlocker, PreFilter};
+#[cfg(feature = "flac")]
+pub use flac::{FlacReader, FlacReaderIteratorMono};
+pub use level::{Agc, LevelSummary, SignalLevels};
+pub use merge::{merge_streams, DecodedStream, MergedStream};
+pub use pipeline::{DecoderEvent, Demodulator, KcsDecoder};
+pub use quadrature::QuadratureDemodulator;
+pub use resample::Resampler;
+pub use resync::find_alignment;
+pub use speed::{FrequencyTracker, SpeedSummary};
+pub use stage::{Chain, Probe, Stage};
+pub use wave::{Endianness, SampleFormat, WaveError, WaveFormat, WaveReader};
+
+const MAX_NUM_STARTBITS: usize = 4;
 const MIN_NUM_STARTBITS: usize = 1;
-const MAX_NUM_STOPBITS: usize = 2;
+const MAX_NUM_STOPBITS: usize = 4;
 const MIN_NUM_STOPBITS: usize = 1;
-const MAX_NUM_DATABITS: usize = 8;
-const MIN_NUM_DATABITS: usize = 7;
-
-pub struct WaveReaderIteratorMono<T: Read + Seek> {
-    reader: riff_wave::WaveReader<T>,
+const MAX_NUM_DATABITS: usize = 16;
+const MIN_NUM_DATABITS: usize = 5;
+/// Sample rate used when resampling is enabled automatically
+const DEFAULT_RESAMPLE_RATE: u32 = 192000;
+/// Lowest number of samples per period of the highest symbol frequency before resampling is needed
+const MIN_SAMPLES_PER_PERIOD: usize = 32;
+/// Same as above when zero crossings are interpolated, the time resolution is then no longer limited by the sample rate
+const MIN_SAMPLES_PER_PERIOD_INTERPOLATED: usize = 4;
+
+/// The data bits of one frame, up to `MAX_NUM_DATABITS` wide
+pub type Word = u16;
+
+pub struct WaveReaderIteratorMono<T: Read> {
+    reader: WaveReader<T>,
 }
 
-pub struct WaveReaderIteratorStereo<T: Read + Seek> {
-    reader: riff_wave::WaveReader<T>,
-}
-
-fn read_sample(reader: &mut WaveReader<impl Read + Seek>) -> Result<f32, impl Error> {
-    match reader.pcm_format.bits_per_sample {
-        8 => reader
-            .read_sample_u8()
-            .and_then(|val| Ok((val as i16 - i16::pow(2, 7)) as f32))
-            .or_else(|_| Err(DecoderError::Signal)),
-        16 => reader
-            .read_sample_i16()
-            .and_then(|val| Ok(val as f32))
-            .or_else(|_| Err(DecoderError::Signal)),
-        24 => reader
-            .read_sample_i24()
-            .and_then(|val| Ok(val as f32))
-            .or_else(|_| Err(DecoderError::Signal)),
-        32 => reader
-            .read_sample_i32()
-            .and_then(|val| Ok(val as f32))
-            .or_else(|_| Err(DecoderError::Signal)),
-        _ => Err(DecoderError::Signal),
-    }
+pub struct WaveReaderIteratorStereo<T: Read> {
+    reader: WaveReader<T>,
 }
 
-impl<T: Read + Seek> WaveReaderIteratorMono<T> {
+impl<T: Read> WaveReaderIteratorMono<T> {
     pub fn new(mut reader: WaveReader<T>, first_channel_idx: u8) -> Result<Self, impl Error> {
-        if first_channel_idx as u16 >= reader.pcm_format.num_channels {
+        if first_channel_idx as u16 >= reader.format.num_channels {
             Err(DecoderError::Signal)
         } else {
             for _ in 0..first_channel_idx {
-                if read_sample(&mut reader).is_err() {
+                if reader.read_sample().is_err() {
                     return Err(DecoderError::Signal);
                 }
             }
@@ -59,51 +78,43 @@ impl<T: Read + Seek> WaveReaderIteratorMono<T> {
     }
 
     fn read_sample_mono_f32(&mut self) -> Result<<Self as IntoIterator>::Item, impl Error> {
-        match read_sample(&mut self.reader) {
-            Ok(val) => Ok(val as f32),
-            Err(e) => Err(e),
-        }
-        .and_then(|val| {
-            for _ in 1..self.reader.pcm_format.num_channels {
-                read_sample(&mut self.reader)?;
+        self.reader.read_sample().and_then(|val| {
+            for _ in 1..self.reader.format.num_channels {
+                self.reader.read_sample()?;
             }
             Ok(val)
         })
     }
 }
 
-impl<T: Read + Seek> WaveReaderIteratorStereo<T> {
+impl<T: Read> WaveReaderIteratorStereo<T> {
     pub fn new(reader: WaveReader<T>) -> Result<Self, impl Error> {
-        match reader.pcm_format.num_channels {
+        match reader.format.num_channels {
             2 => Ok(Self { reader }),
             _ => Err(DecoderError::Signal),
         }
     }
 
     fn read_sample_stereo_f32(&mut self) -> Result<<Self as IntoIterator>::Item, impl Error> {
-        match (read_sample(&mut self.reader), read_sample(&mut self.reader)) {
-            (Ok(lval), Ok(rval)) => Ok([lval as f32, rval as f32]),
+        match (self.reader.read_sample(), self.reader.read_sample()) {
+            (Ok(lval), Ok(rval)) => Ok([lval, rval]),
             (Err(e), _) => Err(e),
             (_, Err(e)) => Err(e),
         }
     }
 }
 
-impl<T: Read + Seek> Iterator for WaveReaderIteratorMono<T> {
+impl<T: Read> Iterator for WaveReaderIteratorMono<T> {
     type Item = f32;
     fn next(&mut self) -> Option<Self::Item> {
-        let sample = self.read_sample_mono_f32().ok()?;
-        let scale = u32::pow(2, (self.reader.pcm_format.bits_per_sample - 1) as u32) as Self::Item;
-        Some(sample / scale)
+        self.read_sample_mono_f32().ok()
     }
 }
 
-impl<T: Read + Seek> Iterator for WaveReaderIteratorStereo<T> {
+impl<T: Read> Iterator for WaveReaderIteratorStereo<T> {
     type Item = [f32; 2];
     fn next(&mut self) -> Option<Self::Item> {
-        let samples = self.read_sample_stereo_f32().ok()?;
-        let scale = u32::pow(2, (self.reader.pcm_format.bits_per_sample - 1) as u32) as f32;
-        Some(samples.map(|val| (val / scale) as f32))
+        self.read_sample_stereo_f32().ok()
     }
 }
 
@@ -125,7 +136,7 @@ impl Display for Channels {
 
 impl From<&str> for Channels {
     fn from(value: &str) -> Self {
-        match value.to_uppercase().chars().nth(0).or(Some('A')).unwrap() {
+        match value.to_uppercase().chars().nth(0).unwrap_or('A') {
             'A' => Channels::All,
             val => match val.to_digit(10) {
                 Some(x) if x < 256 => Channels::Specific(x as u8),
@@ -135,6 +146,207 @@ impl From<&str> for Channels {
     }
 }
 
+#[derive(Debug, PartialEq, Copy, Clone, Eq)]
+pub enum Resample {
+    Auto,
+    Off,
+    Rate(u32),
+}
+
+impl Display for Resample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let resample: String = match self {
+            Resample::Auto => "Auto".to_string(),
+            Resample::Off => "Off".to_string(),
+            Resample::Rate(rate) => format!("{rate} Hz"),
+        };
+        write!(f, "{}", resample)
+    }
+}
+
+impl From<&str> for Resample {
+    fn from(value: &str) -> Self {
+        match value.to_uppercase().chars().nth(0).unwrap_or('A') {
+            'O' | 'N' => Resample::Off,
+            _ => match value.parse::<u32>() {
+                Ok(rate) if rate > 0 => Resample::Rate(rate),
+                _ => Resample::Auto,
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Eq)]
+pub enum Filter {
+    /// DC block and a band-pass around the symbol frequencies of the preset
+    Auto,
+    Off,
+    DcBlock,
+    /// DC block and a band-pass with the given lower and upper cut-off in Hz
+    Band(u32, u32),
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let filter: String = match self {
+            Filter::Auto => "Auto".to_string(),
+            Filter::Off => "Off".to_string(),
+            Filter::DcBlock => "DC block".to_string(),
+            Filter::Band(low, high) => format!("{low}-{high} Hz"),
+        };
+        write!(f, "{}", filter)
+    }
+}
+
+impl From<&str> for Filter {
+    fn from(value: &str) -> Self {
+        match value.to_uppercase().chars().nth(0).unwrap_or('A') {
+            'O' | 'N' => Filter::Off,
+            'D' => Filter::DcBlock,
+            _ => match value
+                .split_once('-')
+                .map(|(low, high)| (low.parse(), high.parse()))
+            {
+                Some((Ok(low), Ok(high))) if low < high => Filter::Band(low, high),
+                _ => Filter::Auto,
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Eq)]
+pub enum Hysteresis {
+    /// Set from the noise floor measured by the `Agc`
+    Auto,
+    /// Percent of full scale
+    Percent(u8),
+}
+
+impl Display for Hysteresis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hysteresis: String = match self {
+            Hysteresis::Auto => "Auto".to_string(),
+            Hysteresis::Percent(percent) => format!("{percent}%"),
+        };
+        write!(f, "{}", hysteresis)
+    }
+}
+
+impl From<&str> for Hysteresis {
+    fn from(value: &str) -> Self {
+        match value.trim_end_matches('%').parse::<u8>() {
+            Ok(percent) if percent <= 100 => Hysteresis::Percent(percent),
+            _ => Hysteresis::Auto,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Eq)]
+pub enum Demodulation {
+    /// Measures the period between zero crossings
+    ZeroCrossing,
+    /// Compares the energy at the symbol frequencies over a bit length, see `QuadratureDemodulator`
+    Quadrature,
+}
+
+impl Display for Demodulation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let demodulation: &str = match self {
+            Demodulation::ZeroCrossing => "ZeroCrossing",
+            Demodulation::Quadrature => "Quadrature",
+        };
+        write!(f, "{}", demodulation)
+    }
+}
+
+impl From<&str> for Demodulation {
+    fn from(value: &str) -> Self {
+        match value.to_uppercase().chars().nth(0).unwrap_or('Z') {
+            'Q' | 'G' | 'I' => Demodulation::Quadrature,
+            _ => Demodulation::ZeroCrossing,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Eq)]
+pub enum ClockRecovery {
+    /// A bit is complete after the number of periods of its symbol, see `HiLowIdentifier`
+    Periods,
+    /// A bit clock that follows the changes of tone, see `BitClockRecovery`
+    Pll,
+}
+
+impl Display for ClockRecovery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let clock_recovery: &str = match self {
+            ClockRecovery::Periods => "Periods",
+            ClockRecovery::Pll => "PLL",
+        };
+        write!(f, "{}", clock_recovery)
+    }
+}
+
+impl From<&str> for ClockRecovery {
+    fn from(value: &str) -> Self {
+        // Both start with 'P'
+        match value.to_uppercase().starts_with("PL") {
+            true => ClockRecovery::Pll,
+            false => ClockRecovery::Periods,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Eq)]
+pub enum Framing {
+    Raw,
+    NASCOM,
+}
+
+impl Display for Framing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let framing: &str = match self {
+            Framing::Raw => "Raw",
+            Framing::NASCOM => "NASCOM",
+        };
+        write!(f, "{}", framing)
+    }
+}
+
+impl From<&str> for Framing {
+    fn from(value: &str) -> Self {
+        match value.to_uppercase().chars().nth(0).unwrap_or('R') {
+            'N' => Framing::NASCOM,
+            _ => Framing::Raw,
+        }
+    }
+}
+
+/// Order of the data bits in a frame
+#[derive(Debug, PartialEq, Copy, Clone, Eq)]
+pub enum BitOrder {
+    LsbFirst,
+    MsbFirst,
+}
+
+impl Display for BitOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bit_order: &str = match self {
+            BitOrder::LsbFirst => "LSB",
+            BitOrder::MsbFirst => "MSB",
+        };
+        write!(f, "{}", bit_order)
+    }
+}
+
+impl From<&str> for BitOrder {
+    fn from(value: &str) -> Self {
+        match value.to_uppercase().chars().nth(0).unwrap_or('L') {
+            'M' => BitOrder::MsbFirst,
+            _ => BitOrder::LsbFirst,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Copy, Clone, Eq)]
 pub enum Preset {
     Std,
@@ -142,6 +354,10 @@ pub enum Preset {
     Acorn,
     MSX1200,
     MSX2400,
+    /// Processor Technology CUTS (Sol-20) at 300 baud, the same tones as the Kansas City Standard
+    CUTS300,
+    /// Processor Technology CUTS (Sol-20) at 1200 baud, half a period of 600 Hz for a space or one period of 1200 Hz for a mark
+    CUTS1200,
 }
 
 impl Display for Preset {
@@ -152,6 +368,8 @@ impl Display for Preset {
             Preset::Acorn => "Acorn",
             Preset::MSX1200 => "MSX1200",
             Preset::MSX2400 => "MSX2400",
+            Preset::CUTS300 => "CUTS300",
+            Preset::CUTS1200 => "CUTS1200",
         };
         write!(f, "{}", parity)
     }
@@ -159,12 +377,14 @@ impl Display for Preset {
 
 impl From<&str> for Preset {
     fn from(value: &str) -> Self {
-        match value.to_uppercase().chars().nth(0).or(Some('S')).unwrap() {
+        match value.to_uppercase().chars().nth(0).unwrap_or('S') {
             'S' => Preset::Std,
             'N' => Preset::NASCOM,
             'A' => Preset::Acorn,
             'M' if value.contains("2400") => Preset::MSX2400,
             'M' => Preset::MSX1200,
+            'C' if value.contains("300") => Preset::CUTS300,
+            'C' => Preset::CUTS1200,
             _ => Preset::NASCOM,
         }
     }
@@ -194,7 +414,7 @@ impl Display for Parity {
 
 impl From<&str> for Parity {
     fn from(value: &str) -> Self {
-        match value.to_uppercase().chars().nth(0).or(Some('N')).unwrap() {
+        match value.to_uppercase().chars().nth(0).unwrap_or('N') {
             'E' => Parity::EVEN,
             'O' => Parity::ODD,
             'M' => Parity::MARK,
@@ -211,26 +431,114 @@ pub enum ZeroCrossingDirection {
     Neg,
 }
 
+#[derive(Debug, PartialEq, Copy, Clone, Eq)]
+pub enum ZeroCrossingInterpolation {
+    None,
+    Linear,
+    Cubic,
+}
+
+impl Display for ZeroCrossingInterpolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let interpolation: &str = match self {
+            ZeroCrossingInterpolation::None => "None",
+            ZeroCrossingInterpolation::Linear => "Linear",
+            ZeroCrossingInterpolation::Cubic => "Cubic",
+        };
+        write!(f, "{}", interpolation)
+    }
+}
+
+impl From<&str> for ZeroCrossingInterpolation {
+    fn from(value: &str) -> Self {
+        match value.to_uppercase().chars().nth(0).unwrap_or('N') {
+            'L' => ZeroCrossingInterpolation::Linear,
+            'C' => ZeroCrossingInterpolation::Cubic,
+            _ => ZeroCrossingInterpolation::None,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub struct ZeroCrossingDetector {
     last_sample: f32,
     hysteresis: f32,
+    interpolation: ZeroCrossingInterpolation,
+    history: [f32; 4],
+    pending: Option<(usize, ZeroCrossingDirection)>,
+    adaptive: bool,
 }
 
 /// Zero crossing detector
 ///
+/// Transforms from samples to the time stamps of zero crossings.
+/// Without interpolation, a crossing is reported at the index of the first sample after the crossing.
+/// With interpolation, the time of the crossing is estimated from the surrounding samples.
+/// Cubic interpolation needs the sample following the crossing, so its output is delayed by one sample.
+/// With adaptive hysteresis, the hysteresis follows `SignalLevels::hysteresis` passed in by `Stage::adapt`.
+///
 /// ```
+/// use kcs_decoder::*;
+///
+/// let mut zc = ZeroCrossingDetector::with_interpolation(0.0, ZeroCrossingInterpolation::Linear);
+/// assert_eq!(zc.process((0, 1.0)), None);
+/// assert_eq!(zc.process((1, -3.0)), Some((0.25, ZeroCrossingDirection::Neg)));
 /// ```
 impl ZeroCrossingDetector {
     pub fn new(hysteresis: f32) -> Self {
+        Self::with_interpolation(hysteresis, ZeroCrossingInterpolation::None)
+    }
+
+    pub fn with_interpolation(hysteresis: f32, interpolation: ZeroCrossingInterpolation) -> Self {
         ZeroCrossingDetector {
             last_sample: 0.0,
             hysteresis: hysteresis.abs(),
+            interpolation,
+            history: [0.0; 4],
+            pending: None,
+            adaptive: false,
+        }
+    }
+
+    pub fn with_adaptive_hysteresis(interpolation: ZeroCrossingInterpolation) -> Self {
+        ZeroCrossingDetector {
+            adaptive: true,
+            ..Self::with_interpolation(0.0, interpolation)
         }
     }
 
-    pub fn process(&mut self, input: (usize, f32)) -> Option<(usize, ZeroCrossingDirection)> {
-        let (last_sample_index, sample) = input;
+    pub fn hysteresis(&self) -> f32 {
+        self.hysteresis
+    }
+
+    pub fn process(&mut self, input: (usize, f32)) -> Option<(f64, ZeroCrossingDirection)> {
+        let (sample_index, sample) = input;
+        self.history = [self.history[1], self.history[2], self.history[3], sample];
+
+        // A crossing found on the previous sample can be resolved now that the sample after it is known
+        let delayed = self
+            .pending
+            .take()
+            .map(|(idx, direction)| (self.cubic_crossing(idx), direction));
+
+        match self.detect(sample_index, sample) {
+            Some((idx, direction)) => match self.interpolation {
+                ZeroCrossingInterpolation::None => Some((idx as f64, direction)),
+                ZeroCrossingInterpolation::Linear => Some((self.linear_crossing(idx), direction)),
+                ZeroCrossingInterpolation::Cubic => {
+                    self.pending = Some((idx, direction));
+                    delayed
+                }
+            },
+            None => delayed,
+        }
+    }
+
+    fn detect(
+        &mut self,
+        sample_index: usize,
+        sample: f32,
+    ) -> Option<(usize, ZeroCrossingDirection)> {
         if sample.abs() < self.hysteresis {
             return None;
         }
@@ -238,44 +546,112 @@ impl ZeroCrossingDetector {
         self.last_sample = sample;
         if (sample >= 0.0) != (last_sample >= 0.0) {
             match sample >= 0.0 {
-                true => Some((last_sample_index, ZeroCrossingDirection::Pos)),
-                false => Some((last_sample_index, ZeroCrossingDirection::Neg)),
+                true => Some((sample_index, ZeroCrossingDirection::Pos)),
+                false => Some((sample_index, ZeroCrossingDirection::Neg)),
             }
         } else {
             None
         }
     }
+
+    /// Crossing time between the two most recent samples.
+    /// With hysteresis, the sign may have changed one sample earlier, within the hysteresis band.
+    /// If neither pair straddles zero, the sample index is used.
+    fn linear_crossing(&self, sample_index: usize) -> f64 {
+        for (offset, [x0, x1]) in [
+            [self.history[2], self.history[3]],
+            [self.history[1], self.history[2]],
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            if (x0 >= 0.0) != (x1 >= 0.0) {
+                return sample_index as f64 - 1.0 - offset as f64 + (x0 / (x0 - x1)) as f64;
+            }
+        }
+        sample_index as f64
+    }
+
+    /// Crossing time from a cubic through the two samples on either side of the crossing.
+    /// Called one sample late, so the crossing is between `history[1]` and `history[2]`.
+    fn cubic_crossing(&self, sample_index: usize) -> f64 {
+        let [p0, p1, p2, p3] = self.history.map(|val| val as f64);
+        if (p1 >= 0.0) == (p2 >= 0.0) {
+            return sample_index as f64;
+        }
+
+        // Lagrange polynomial through x = -1, 0, 1, 2, root searched for in 0..1
+        let b = -p0 / 3.0 - p1 / 2.0 + p2 - p3 / 6.0;
+        let c = p0 / 2.0 - p1 + p2 / 2.0;
+        let d = -p0 / 6.0 + p1 / 2.0 - p2 / 2.0 + p3 / 6.0;
+        let linear = p1 / (p1 - p2);
+        let mut x = linear;
+        for _ in 0..4 {
+            let value = p1 + x * (b + x * (c + x * d));
+            let slope = b + x * (2.0 * c + x * 3.0 * d);
+            if slope == 0.0 {
+                break;
+            }
+            x -= value / slope;
+        }
+        if !(0.0..=1.0).contains(&x) {
+            x = linear;
+        }
+        sample_index as f64 - 1.0 + x
+    }
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub struct FrequencyIdentifier {
-    start_direction: ZeroCrossingDirection,
-    last_sample_idx: Option<usize>,
-    sample_frequency: f32,
+    /// Direction of the crossings that start a period, `None` to measure every half period
+    start_direction: Option<ZeroCrossingDirection>,
+    last_sample_idx: Option<f64>,
+    sample_frequency: f64,
 }
 
+/// Frequency identifier
+///
+/// Transforms from zero crossing time stamps to the frequency of each full period.
+/// The output is tagged with the sample index of the crossing that completed the period.
 impl FrequencyIdentifier {
     pub fn new(start_direction: ZeroCrossingDirection, sample_frequency: u32) -> Self {
         FrequencyIdentifier {
-            start_direction,
+            start_direction: Some(start_direction),
             last_sample_idx: None,
-            sample_frequency: sample_frequency as f32,
+            sample_frequency: sample_frequency as f64,
         }
     }
 
-    pub fn process(&mut self, input: (usize, ZeroCrossingDirection)) -> Option<(usize, f32)> {
+    /// Identifier for symbols of half a period, see `Symbol`.
+    /// Measures from every crossing to the next and reports the frequency of a period twice as long.
+    pub fn with_half_periods(sample_frequency: u32) -> Self {
+        FrequencyIdentifier {
+            start_direction: None,
+            last_sample_idx: None,
+            sample_frequency: sample_frequency as f64,
+        }
+    }
+
+    pub fn process(&mut self, input: (f64, ZeroCrossingDirection)) -> Option<(usize, f32)> {
         let (sample_index, direction) = input;
-        if direction != self.start_direction {
+        if self
+            .start_direction
+            .is_some_and(|start_direction| direction != start_direction)
+        {
             return None;
         }
+        let periods = match self.start_direction {
+            Some(_) => 1.0,
+            None => 0.5,
+        };
 
         let idx = self.last_sample_idx;
         self.last_sample_idx = Some(sample_index);
-        idx.and_then(|idx| {
-            Some((
-                sample_index,
-                self.sample_frequency / ((sample_index - idx) as f32),
-            ))
+        idx.map(|idx| {
+            (
+                sample_index as usize,
+                (periods * self.sample_frequency / (sample_index - idx)) as f32,
+            )
         })
     }
 }
@@ -300,7 +676,7 @@ impl Display for SignalCondition {
 
 impl From<&str> for SignalCondition {
     fn from(value: &str) -> Self {
-        match value.to_uppercase().chars().nth(0).or(Some('N')).unwrap() {
+        match value.to_uppercase().chars().nth(0).unwrap_or('N') {
             'M' => SignalCondition::Mark,
             'S' => SignalCondition::Space,
             _ => SignalCondition::Error,
@@ -310,11 +686,15 @@ impl From<&str> for SignalCondition {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct HiLowIdentifier {
-    high_level_bounds: Range<f32>,
-    low_level_bounds: Range<f32>,
+    /// Tells the high (0) and low (1) frequency apart
+    tracker: FrequencyTracker,
     low_symbol: (u8, SignalCondition),
     high_symbol: (u8, SignalCondition),
     bitcount: u8,
+    /// Sum of the confidence of the periods counted for the current bit, and the mean confidence of the last bit.
+    /// A period of neither tone adds nothing, so it lowers the confidence in the next bit.
+    confidence: f32,
+    bit_confidence: f32,
 }
 
 impl HiLowIdentifier {
@@ -325,26 +705,32 @@ impl HiLowIdentifier {
         low_symbol: (u8, SignalCondition),
         high_symbol: (u8, SignalCondition),
     ) -> Option<Self> {
-        let high_f32 = frequency_high as f32;
-        let low_f32 = frequency_low as f32;
-        let tolerance = tolerance_percent as f32 / 100.0;
-        let high_level_bounds = high_f32 - high_f32 * tolerance..high_f32 + high_f32 * tolerance;
-        let low_level_bounds = low_f32 - low_f32 * tolerance..low_f32 + low_f32 * tolerance;
-
-        if high_level_bounds.contains(&low_f32)
-            || low_level_bounds.contains(&high_f32)
-            || tolerance_percent > 50
-            || low_symbol.1 == high_symbol.1
-        {
+        let tracker = FrequencyTracker::new(
+            [frequency_high, frequency_low],
+            tolerance_percent as usize,
+            false,
+        )?;
+        Self::with_tracker(tracker, low_symbol, high_symbol)
+    }
+
+    /// Identifier with the bounds of `tracker`, which has the high frequency first.
+    /// With an adaptive tracker, the bounds follow the tape speed.
+    pub fn with_tracker(
+        tracker: FrequencyTracker,
+        low_symbol: (u8, SignalCondition),
+        high_symbol: (u8, SignalCondition),
+    ) -> Option<Self> {
+        if low_symbol.1 == high_symbol.1 {
             return None;
         }
 
         Some(Self {
-            high_level_bounds,
-            low_level_bounds,
+            tracker,
             low_symbol,
             high_symbol,
             bitcount: 0,
+            confidence: 0.0,
+            bit_confidence: 1.0,
         })
     }
 
@@ -352,23 +738,23 @@ impl HiLowIdentifier {
         let (sample_index, frequency) = input;
         self.bitcount += 1;
 
-        if self.high_level_bounds.contains(&frequency) {
-            if self.bitcount < self.high_symbol.0 {
-                None
-            } else {
-                self.bitcount = 0;
-                Some((sample_index, self.high_symbol.1))
-            }
-        } else if self.low_level_bounds.contains(&frequency) {
-            if self.bitcount < self.low_symbol.0 {
-                None
-            } else {
-                self.bitcount = 0;
-                Some((sample_index, self.low_symbol.1))
+        let tone = self.tracker.tone(frequency);
+        let symbol = match tone {
+            Some(0) => self.high_symbol,
+            Some(_) => self.low_symbol,
+            None => {
+                self.bit_confidence = 0.0;
+                return Some((sample_index, SignalCondition::Error));
             }
-        } else {
-            Some((sample_index, SignalCondition::Error))
+        };
+        self.confidence += self.tracker.confidence(tone?, frequency);
+        if self.bitcount < symbol.0 {
+            return None;
         }
+        self.bit_confidence = self.confidence / self.bitcount as f32;
+        self.bitcount = 0;
+        self.confidence = 0.0;
+        Some((sample_index, symbol.1))
     }
 }
 
@@ -378,7 +764,7 @@ enum DecoderState {
     WaitForDataBit(DecoderStateDataBit),
     WaitForParity(DecoderStateParity),
     WaitForStopBit(DecoderStateStopBit),
-    DataOut(u8),
+    DataOut(Word),
 }
 
 impl Eq for DecoderState {}
@@ -405,6 +791,7 @@ pub enum DecoderError {
     Other(String),
 }
 
+impl Eq for DecoderError {}
 impl PartialEq for DecoderError {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -494,7 +881,7 @@ struct DecoderStateDataBit {
     idx: usize,
 }
 
-impl<'a> DecoderStateDataBit {
+impl DecoderStateDataBit {
     fn new(config: DecoderConfig) -> Self {
         DecoderStateDataBit {
             config,
@@ -543,8 +930,14 @@ impl DecoderStateParity {
     }
 
     fn process(&mut self, level: SignalCondition) -> Result<DecoderState, DecoderError> {
-        let mut marks_count_is_even = bool_vec_to_u8(&self.data).count_ones() % 2 == 0;
-        if level == SignalCondition::Space {
+        let mut marks_count_is_even = self
+            .data
+            .iter()
+            .filter(|bit| **bit)
+            .count()
+            .is_multiple_of(2);
+        // The parity bit counts as a mark too, even parity makes the total number of marks even
+        if level == SignalCondition::Mark {
             marks_count_is_even = !marks_count_is_even;
         }
 
@@ -591,7 +984,10 @@ impl DecoderStateStopBit {
             if self.num_stopbits_received < self.config.stopbits.0 {
                 Ok(DecoderState::WaitForStopBit(*self))
             } else {
-                Ok(DecoderState::DataOut(bool_vec_to_u8(&self.data)))
+                Ok(DecoderState::DataOut(bool_vec_to_word(
+                    &self.data,
+                    &self.config,
+                )))
             }
         } else if level == SignalCondition::Error {
             Err(DecoderError::Signal)
@@ -601,120 +997,329 @@ impl DecoderStateStopBit {
     }
 }
 
-fn bool_vec_to_u8(data: &[bool; MAX_NUM_DATABITS]) -> u8 {
-    let mut out = 0u8;
-    for i in 0..MAX_NUM_DATABITS {
-        if data[i] {
-            out += 1u8 << i;
-        }
+/// Assembles the data bits in the order they were received into a word
+fn bool_vec_to_word(data: &[bool; MAX_NUM_DATABITS], config: &DecoderConfig) -> Word {
+    let data = &data[..config.num_databits];
+    match config.bit_order {
+        BitOrder::LsbFirst => data
+            .iter()
+            .rev()
+            .fold(0, |out, &bit| out << 1 | Word::from(bit)),
+        BitOrder::MsbFirst => data.iter().fold(0, |out, &bit| out << 1 | Word::from(bit)),
     }
-    out
 }
 
+/// A bit of one level, sent as a number of half periods of a tone
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct Symbol {
     pub frequency: usize,
-    pub periods: usize,
+    /// Number of half periods per bit, odd for a symbol that is not a whole number of periods
+    pub half_periods: usize,
     pub signal: SignalCondition,
 }
 
+impl Symbol {
+    /// Length of one bit in seconds
+    pub fn length(&self) -> f64 {
+        self.half_periods as f64 / (2.0 * self.frequency as f64)
+    }
+
+    /// Number of periods per bit, or of half periods if `half_periods` are measured, see `FrequencyIdentifier`
+    pub fn count(&self, half_periods: bool) -> usize {
+        match half_periods {
+            true => self.half_periods,
+            false => self.half_periods / 2,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct DecoderConfig {
     pub startbits: (usize, SignalCondition),
     pub num_databits: usize,
+    pub bit_order: BitOrder,
     pub parity: Parity,
     pub stopbits: (usize, SignalCondition),
+    /// Half a stop bit more than `stopbits`, e.g. 1.5 stop bits. The decoder takes it as idle
+    pub half_stopbit: bool,
     pub channels: Channels,
     pub symbols: [Symbol; 2],
     pub frequency_tolerance: usize,
+    pub resample: Resample,
+    pub filter: Filter,
+    pub agc: bool,
+    pub hysteresis: Hysteresis,
+    pub interpolation: ZeroCrossingInterpolation,
+    pub demodulation: Demodulation,
+    pub clock_recovery: ClockRecovery,
+    /// Re-centre the frequency bounds on the measured frequencies, see `FrequencyTracker`
+    pub track_speed: bool,
+    pub framing: Framing,
+    /// Keep decoding through framing errors with a best guess of each damaged byte, see `Decoder`
+    pub salvage: bool,
+    /// Number of frames that have to decode without errors to take up a new frame alignment found after a sync error,
+    /// see `find_alignment`. 0 waits for the next start bit instead
+    pub resync: usize,
 }
 
 impl DecoderConfig {
     pub fn get_preset(preset: &Preset) -> DecoderConfig {
         match preset {
-            Preset::Std => Self {
+            Preset::Std | Preset::CUTS300 => Self {
                 startbits: (1, SignalCondition::Space),
                 num_databits: 8,
+                bit_order: BitOrder::LsbFirst,
                 parity: Parity::NONE,
                 stopbits: (2, SignalCondition::Mark),
+                half_stopbit: false,
                 channels: Channels::All,
                 symbols: [
                     Symbol {
                         frequency: 1200,
-                        periods: 4,
+                        half_periods: 8,
                         signal: SignalCondition::Space,
                     },
                     Symbol {
                         frequency: 2400,
-                        periods: 8,
+                        half_periods: 16,
                         signal: SignalCondition::Mark,
                     },
                 ],
                 frequency_tolerance: 10,
+                resample: Resample::Auto,
+                filter: Filter::Auto,
+                agc: false,
+                hysteresis: Hysteresis::Percent(0),
+                interpolation: ZeroCrossingInterpolation::None,
+                demodulation: Demodulation::ZeroCrossing,
+                clock_recovery: ClockRecovery::Periods,
+                track_speed: false,
+                framing: Framing::Raw,
+                salvage: false,
+                resync: 0,
             },
             Preset::NASCOM | Preset::Acorn => Self {
                 startbits: (1, SignalCondition::Space),
                 num_databits: 8,
+                bit_order: BitOrder::LsbFirst,
                 parity: Parity::NONE,
                 stopbits: (1, SignalCondition::Mark),
+                half_stopbit: false,
                 channels: Channels::All,
                 symbols: [
                     Symbol {
                         frequency: 1200,
-                        periods: 1,
+                        half_periods: 2,
                         signal: SignalCondition::Space,
                     },
                     Symbol {
                         frequency: 2400,
-                        periods: 2,
+                        half_periods: 4,
                         signal: SignalCondition::Mark,
                     },
                 ],
                 frequency_tolerance: 10,
+                resample: Resample::Auto,
+                filter: Filter::Auto,
+                agc: false,
+                hysteresis: Hysteresis::Percent(0),
+                interpolation: ZeroCrossingInterpolation::None,
+                demodulation: Demodulation::ZeroCrossing,
+                clock_recovery: ClockRecovery::Periods,
+                track_speed: false,
+                framing: Framing::Raw,
+                salvage: false,
+                resync: 0,
             },
             Preset::MSX1200 => Self {
                 startbits: (1, SignalCondition::Space),
                 num_databits: 8,
+                bit_order: BitOrder::LsbFirst,
                 parity: Parity::NONE,
                 stopbits: (2, SignalCondition::Mark),
+                half_stopbit: false,
                 channels: Channels::All,
                 symbols: [
                     Symbol {
                         frequency: 1200,
-                        periods: 1,
+                        half_periods: 2,
                         signal: SignalCondition::Space,
                     },
                     Symbol {
                         frequency: 2400,
-                        periods: 2,
+                        half_periods: 4,
                         signal: SignalCondition::Mark,
                     },
                 ],
                 frequency_tolerance: 10,
+                resample: Resample::Auto,
+                filter: Filter::Auto,
+                agc: false,
+                hysteresis: Hysteresis::Percent(0),
+                interpolation: ZeroCrossingInterpolation::None,
+                demodulation: Demodulation::ZeroCrossing,
+                clock_recovery: ClockRecovery::Periods,
+                track_speed: false,
+                framing: Framing::Raw,
+                salvage: false,
+                resync: 0,
+            },
+            Preset::CUTS1200 => Self {
+                startbits: (1, SignalCondition::Space),
+                num_databits: 8,
+                bit_order: BitOrder::LsbFirst,
+                parity: Parity::NONE,
+                stopbits: (2, SignalCondition::Mark),
+                half_stopbit: false,
+                channels: Channels::All,
+                symbols: [
+                    Symbol {
+                        frequency: 600,
+                        half_periods: 1,
+                        signal: SignalCondition::Space,
+                    },
+                    Symbol {
+                        frequency: 1200,
+                        half_periods: 2,
+                        signal: SignalCondition::Mark,
+                    },
+                ],
+                // A half period is timed between crossings of both directions, which the filter and a DC offset move apart
+                frequency_tolerance: 25,
+                resample: Resample::Auto,
+                filter: Filter::Auto,
+                agc: false,
+                hysteresis: Hysteresis::Percent(0),
+                interpolation: ZeroCrossingInterpolation::None,
+                demodulation: Demodulation::ZeroCrossing,
+                clock_recovery: ClockRecovery::Periods,
+                track_speed: false,
+                framing: Framing::Raw,
+                salvage: false,
+                resync: 0,
             },
             Preset::MSX2400 => Self {
                 startbits: (1, SignalCondition::Space),
                 num_databits: 8,
+                bit_order: BitOrder::LsbFirst,
                 parity: Parity::NONE,
                 stopbits: (2, SignalCondition::Mark),
+                half_stopbit: false,
                 channels: Channels::All,
                 symbols: [
                     Symbol {
                         frequency: 2400,
-                        periods: 1,
+                        half_periods: 2,
                         signal: SignalCondition::Space,
                     },
                     Symbol {
                         frequency: 4800,
-                        periods: 2,
+                        half_periods: 4,
                         signal: SignalCondition::Mark,
                     },
                 ],
                 frequency_tolerance: 10,
+                resample: Resample::Auto,
+                filter: Filter::Auto,
+                agc: false,
+                hysteresis: Hysteresis::Percent(0),
+                interpolation: ZeroCrossingInterpolation::None,
+                demodulation: Demodulation::ZeroCrossing,
+                clock_recovery: ClockRecovery::Periods,
+                track_speed: false,
+                framing: Framing::Raw,
+                salvage: false,
+                resync: 0,
             },
         }
     }
 
+    /// Returns the sample rate the input should be converted to before decoding, if any.
+    /// In automatic mode, resampling is enabled when the input has too few samples per period of the highest symbol frequency.
+    /// The quadrature demodulator does not depend on the timing of single samples, like interpolated zero crossings.
+    pub fn resample_rate(&self, input_sample_rate: u32) -> Option<u32> {
+        let max_frequency = self.symbols.iter().map(|s| s.frequency).max().unwrap_or(0);
+        let min_samples_per_period = match (self.demodulation, self.interpolation) {
+            (Demodulation::ZeroCrossing, ZeroCrossingInterpolation::None) => MIN_SAMPLES_PER_PERIOD,
+            _ => MIN_SAMPLES_PER_PERIOD_INTERPOLATED,
+        };
+        match self.resample {
+            Resample::Off => None,
+            Resample::Rate(rate) if rate == input_sample_rate => None,
+            Resample::Rate(rate) => Some(rate),
+            Resample::Auto
+                if (input_sample_rate as usize) < max_frequency * min_samples_per_period =>
+            {
+                Some(DEFAULT_RESAMPLE_RATE)
+            }
+            Resample::Auto => None,
+        }
+    }
+
+    /// Lower and upper cut-off in Hz of the band-pass in front of the demodulator, if enabled.
+    /// In automatic mode the high-pass is kept three octaves below the lowest symbol frequency,
+    /// as its phase shift differs between the two tones and moves the zero crossings where the bits change.
+    /// The low-pass is an octave above the highest symbol frequency.
+    pub fn filter_band(&self) -> Option<(f32, f32)> {
+        let frequencies = self.symbols.iter().map(|s| s.frequency as f32);
+        match self.filter {
+            Filter::Off | Filter::DcBlock => None,
+            Filter::Band(low, high) => Some((low as f32, high as f32)),
+            Filter::Auto => Some((
+                frequencies.clone().fold(f32::MAX, f32::min) / 8.0,
+                frequencies.fold(0.0, f32::max) * 2.0,
+            )),
+        }
+    }
+
+    /// Whether a symbol is not a whole number of periods, the zero crossing demodulator then measures half periods
+    pub fn half_period_symbols(&self) -> bool {
+        self.symbols
+            .iter()
+            .any(|symbol| !symbol.half_periods.is_multiple_of(2))
+    }
+
+    /// Number of whole bits in one frame, start, data, parity and stop bits
+    pub fn frame_bits(&self) -> usize {
+        self.startbits.0
+            + self.num_databits
+            + usize::from(self.parity != Parity::NONE)
+            + self.stopbits.0
+    }
+
+    /// Length of one frame (start, data, parity and stop bits) in samples
+    pub fn frame_length(&self, sample_rate: u32) -> usize {
+        let bit_length =
+            self.symbols.iter().map(Symbol::length).fold(0.0, f64::max) * sample_rate as f64;
+        let half_stopbit = if self.half_stopbit { 0.5 } else { 0.0 };
+        (bit_length * (self.frame_bits() as f64 + half_stopbit)).round() as usize
+    }
+
+    /// Number of bytes a word is stored in, little endian if more than one
+    pub fn word_bytes(&self) -> usize {
+        self.num_databits.div_ceil(8)
+    }
+
+    /// Stores words as bytes, see `word_bytes`
+    pub fn words_to_bytes(&self, words: &[Word]) -> Vec<u8> {
+        words
+            .iter()
+            .flat_map(|word| word.to_le_bytes().into_iter().take(self.word_bytes()))
+            .collect()
+    }
+
+    /// Reads words stored as bytes, see `word_bytes`. An incomplete word at the end is padded with zeros
+    pub fn bytes_to_words(&self, data: &[u8]) -> Vec<Word> {
+        data.chunks(self.word_bytes())
+            .map(|bytes| {
+                bytes
+                    .iter()
+                    .rev()
+                    .fold(0, |word, &byte| word << 8 | Word::from(byte))
+            })
+            .collect()
+    }
+
     fn validate(&self) -> Option<Self> {
         if self.num_databits >= MIN_NUM_DATABITS
             && self.num_databits <= MAX_NUM_DATABITS
@@ -722,6 +1327,8 @@ impl DecoderConfig {
             && self.startbits.0 <= MAX_NUM_STARTBITS
             && self.stopbits.0 >= MIN_NUM_STOPBITS
             && self.stopbits.0 <= MAX_NUM_STOPBITS
+            // NASCOM blocks are made of bytes
+            && !(self.framing == Framing::NASCOM && self.num_databits > 8)
         {
             Some(*self)
         } else {
@@ -741,31 +1348,67 @@ impl Display for DecoderConfig {
         write!(
             f,
             "\
-Channels:  {}
-Startbits: {} ({})
-Databits:  {}
-Parity:    {}
-Stopbits:  {} ({})",
+Channels:       {}
+Startbits:      {} ({})
+Databits:       {} ({} first)
+Parity:         {}
+Stopbits:       {}{} ({})
+Resample:       {}
+Filter:         {}
+AGC:            {}
+Hysteresis:     {}
+Interpolation:  {}
+Demodulator:    {}
+Clock recovery: {}
+Track speed:    {}
+Framing:        {}
+Salvage:        {}
+Resync:         {}",
             self.channels,
             self.startbits.0,
             self.startbits.1,
             self.num_databits,
+            self.bit_order,
             self.parity,
             self.stopbits.0,
-            self.stopbits.1
+            if self.half_stopbit { ".5" } else { "" },
+            self.stopbits.1,
+            self.resample,
+            self.filter,
+            if self.agc { "On" } else { "Off" },
+            self.hysteresis,
+            self.interpolation,
+            self.demodulation,
+            self.clock_recovery,
+            if self.track_speed { "On" } else { "Off" },
+            self.framing,
+            if self.salvage { "On" } else { "Off" },
+            match self.resync {
+                0 => "Off".to_string(),
+                frames => format!("{frames} frames"),
+            }
         )
     }
 }
 
 /// Decoder transforms from SignalCondition -> DecoderState
+///
+/// The data bits of a frame are returned as a word in the configured bit order.
+/// With `config.salvage`, a frame is decoded to the end even if a data or parity bit could not be read (it is taken as
+/// space) or the parity is wrong. The first error of the frame is still returned, but if the frame was complete
+/// the best guess of its word is kept for `salvaged`.
 #[derive(Debug, Clone)]
 pub struct Decoder {
     config: DecoderConfig,
     state: Result<DecoderState, DecoderError>,
+    /// First error in the current frame, in salvage mode
+    frame_error: Option<DecoderError>,
+    salvaged: Option<Word>,
 }
 
 impl Decoder {
-    pub fn process(&mut self, input: SignalCondition) -> Result<u8, Option<DecoderError>> {
+    pub fn process(&mut self, input: SignalCondition) -> Result<Word, Option<DecoderError>> {
+        self.salvaged = None;
         let mut state = self.state.clone()?;
         match &mut state {
             DecoderState::WaitForStartBit(state) => {
@@ -780,6 +1423,7 @@ impl Decoder {
                 }
             }
             DecoderState::WaitForDataBit(state) => {
+                let input = self.salvage_bit(input);
                 self.state = state.process(input);
                 match &self.state {
                     Err(error) => {
@@ -791,7 +1435,15 @@ impl Decoder {
                 }
             }
             DecoderState::WaitForParity(state) => {
+                let input = self.salvage_bit(input);
                 self.state = state.process(input);
+                if let (true, Err(error)) = (self.config.salvage, &self.state) {
+                    self.frame_error.get_or_insert(error.clone());
+                    self.state = Ok(DecoderState::WaitForStopBit(DecoderStateStopBit::new(
+                        self.config,
+                        &state.data,
+                    )));
+                }
                 match &self.state {
                     Err(error) => {
                         let error = error.clone();
@@ -805,13 +1457,23 @@ impl Decoder {
                 self.state = state.process(input);
                 match &self.state {
                     Ok(DecoderState::DataOut(val)) => {
-                        let val = val.clone();
+                        let val = *val;
+                        let frame_error = self.frame_error.take();
                         self.reset();
-                        Ok(val)
+                        match frame_error {
+                            Some(error) => {
+                                self.salvaged = Some(val);
+                                Err(Some(error))
+                            }
+                            None => Ok(val),
+                        }
                     }
                     Err(error) => {
-                        let error = error.clone();
+                        let error = self.frame_error.take().unwrap_or(error.clone());
                         self.reset();
+                        if self.config.salvage {
+                            self.salvaged = Some(bool_vec_to_word(&state.data, &self.config));
+                        }
                         Err(Some(error))
                     }
                     _ => Err(None),
@@ -829,6 +1491,8 @@ impl Decoder {
             state: Ok(DecoderState::WaitForStartBit(DecoderStateStartBit::new(
                 config,
             ))),
+            frame_error: None,
+            salvaged: None,
         };
         new.reset();
         Some(new)
@@ -838,6 +1502,22 @@ impl Decoder {
         self.state = Ok(DecoderState::WaitForStartBit(DecoderStateStartBit::new(
             self.config,
         )));
+        self.frame_error = None;
+        self.salvaged = None;
+    }
+
+    /// In salvage mode, the best guess of the word of the frame the last error was returned for
+    pub fn salvaged(&self) -> Option<Word> {
+        self.salvaged
+    }
+
+    /// In salvage mode, an unreadable bit within a frame is taken as space and its error is kept for the end of the frame
+    fn salvage_bit(&mut self, input: SignalCondition) -> SignalCondition {
+        if self.config.salvage && input == SignalCondition::Error {
+            self.frame_error.get_or_insert(DecoderError::Signal);
+            return SignalCondition::Space;
+        }
+        input
     }
 }
 
@@ -845,6 +1525,15 @@ impl Decoder {
 mod tests {
     use super::*;
 
+    /// Repeatable uniform noise from -1.0 to 1.0, from a linear congruential generator
+    pub(crate) fn noise() -> impl Iterator<Item = f32> {
+        let mut state = 1u32;
+        std::iter::from_fn(move || {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            Some(state as f32 / u32::MAX as f32 * 2.0 - 1.0)
+        })
+    }
+
     #[test]
     fn zerocrossingdetector_transition_both_ways() {
         let mut zc = ZeroCrossingDetector::new(0.0);
@@ -857,9 +1546,9 @@ mod tests {
         assert_eq!(
             output,
             vec![
-                (1, ZeroCrossingDirection::Neg),
-                (3, ZeroCrossingDirection::Pos),
-                (6, ZeroCrossingDirection::Neg)
+                (1.0, ZeroCrossingDirection::Neg),
+                (3.0, ZeroCrossingDirection::Pos),
+                (6.0, ZeroCrossingDirection::Neg)
             ]
         );
     }
@@ -875,7 +1564,7 @@ mod tests {
             .filter_map(|val| zc.process(val))
             .collect::<Vec<_>>();
 
-        assert_eq!(output, vec![(8, ZeroCrossingDirection::Neg)]);
+        assert_eq!(output, vec![(8.0, ZeroCrossingDirection::Neg)]);
     }
 
     #[test]
@@ -889,13 +1578,13 @@ mod tests {
         // When looking from neg->neg:
         // 4, 80
         let data = [
-            (0, ZeroCrossingDirection::Pos),
-            (2, ZeroCrossingDirection::Neg),
-            (4, ZeroCrossingDirection::Pos),
-            (6, ZeroCrossingDirection::Neg),
-            (9, ZeroCrossingDirection::Pos),
-            (86, ZeroCrossingDirection::Neg),
-            (109, ZeroCrossingDirection::Pos),
+            (0.0, ZeroCrossingDirection::Pos),
+            (2.0, ZeroCrossingDirection::Neg),
+            (4.0, ZeroCrossingDirection::Pos),
+            (6.0, ZeroCrossingDirection::Neg),
+            (9.0, ZeroCrossingDirection::Pos),
+            (86.0, ZeroCrossingDirection::Neg),
+            (109.0, ZeroCrossingDirection::Pos),
         ];
         let output = data
             .into_iter()
@@ -924,6 +1613,26 @@ mod tests {
             output,
             vec![(6, sample_frequency / 4.0), (86, sample_frequency / 80.0)]
         );
+
+        // Half periods, measured from every crossing to the next
+        let mut frq_identifier = FrequencyIdentifier::with_half_periods(sample_frequency as u32);
+
+        let output = data
+            .into_iter()
+            .filter_map(|val| frq_identifier.process(val))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            output,
+            vec![
+                (2, sample_frequency / 4.0),
+                (4, sample_frequency / 4.0),
+                (6, sample_frequency / 4.0),
+                (9, sample_frequency / 6.0),
+                (86, sample_frequency / 154.0),
+                (109, sample_frequency / 46.0)
+            ]
+        );
     }
 
     #[test]
@@ -935,13 +1644,13 @@ mod tests {
             FrequencyIdentifier::new(ZeroCrossingDirection::Pos, sample_frequency as u32);
 
         let data = [
-            (0, ZeroCrossingDirection::Pos),
-            (2, ZeroCrossingDirection::Pos),
-            (6, ZeroCrossingDirection::Neg),
-            (600, ZeroCrossingDirection::Neg),
-            (6, ZeroCrossingDirection::Neg),
-            (6, ZeroCrossingDirection::Neg),
-            (7, ZeroCrossingDirection::Pos),
+            (0.0, ZeroCrossingDirection::Pos),
+            (2.0, ZeroCrossingDirection::Pos),
+            (6.0, ZeroCrossingDirection::Neg),
+            (600.0, ZeroCrossingDirection::Neg),
+            (6.0, ZeroCrossingDirection::Neg),
+            (6.0, ZeroCrossingDirection::Neg),
+            (7.0, ZeroCrossingDirection::Pos),
         ];
 
         let output = data
@@ -1062,4 +1771,255 @@ mod tests {
 
         assert_eq!(output, vec![(15, Err(Some(DecoderError::Signal))),]);
     }
+
+    #[test]
+    fn decoder_config_resample_rate() {
+        let mut config = DecoderConfig::get_preset(&Preset::NASCOM);
+        assert_eq!(config.resample_rate(16000), Some(192000));
+        assert_eq!(config.resample_rate(44100), Some(192000));
+        assert_eq!(config.resample_rate(96000), None);
+
+        config.resample = Resample::Off;
+        assert_eq!(config.resample_rate(16000), None);
+
+        config.resample = Resample::Rate(96000);
+        assert_eq!(config.resample_rate(16000), Some(96000));
+        assert_eq!(config.resample_rate(96000), None);
+    }
+
+    #[test]
+    fn zerocrossingdetector_interpolation() {
+        // 2400Hz at 16kHz is 6.67 samples per period, the interpolated period must still be accurate
+        let sample_rate = 16000.0f32;
+        let period = sample_rate / 2400.0;
+        let data = (0..200)
+            .map(|n| (2.0 * std::f32::consts::PI * (n as f32 + 0.3) / period).sin())
+            .collect::<Vec<_>>();
+
+        for (interpolation, tolerance) in [
+            (ZeroCrossingInterpolation::Linear, 0.02),
+            (ZeroCrossingInterpolation::Cubic, 0.005),
+        ] {
+            let mut zc = ZeroCrossingDetector::with_interpolation(0.0, interpolation);
+            let output = data
+                .iter()
+                .copied()
+                .enumerate()
+                .filter_map(|val| zc.process(val))
+                .filter(|(_, direction)| *direction == ZeroCrossingDirection::Pos)
+                .map(|(idx, _)| idx)
+                .collect::<Vec<_>>();
+
+            assert!(output.len() > 20);
+            for (n, crossing) in output.iter().enumerate() {
+                let expected = (n + 1) as f64 * period as f64 - 0.3;
+                assert!(
+                    (crossing - expected).abs() < tolerance,
+                    "{interpolation}: {crossing} != {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decoder_config_display_aligned() {
+        let config = DecoderConfig::default().to_string();
+        for line in config.lines() {
+            let (label, value) = line.split_once(':').unwrap();
+            assert_eq!(
+                value.len() - value.trim_start().len() + label.len(),
+                15,
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn decoder_config_cuts_presets() {
+        assert_eq!(Preset::from("cuts300"), Preset::CUTS300);
+        assert_eq!(Preset::from("CUTS1200"), Preset::CUTS1200);
+        assert_eq!(Preset::from("cuts"), Preset::CUTS1200);
+        assert_eq!(
+            Preset::from(Preset::CUTS300.to_string().as_str()),
+            Preset::CUTS300
+        );
+
+        // 300 baud with 4 or 8 periods per bit, 1200 baud with half a period of 600 Hz or a period of 1200 Hz
+        for (preset, baud_rate) in [(Preset::CUTS300, 300), (Preset::CUTS1200, 1200)] {
+            let config = DecoderConfig::get_preset(&preset);
+            for symbol in config.symbols {
+                assert_eq!(
+                    2 * symbol.frequency / symbol.half_periods,
+                    baud_rate,
+                    "{preset}"
+                );
+            }
+            assert_eq!(config.frame_bits(), 11, "{preset}");
+        }
+    }
+
+    #[test]
+    fn decoder_config_resample_rate_interpolated() {
+        let mut config = DecoderConfig::get_preset(&Preset::NASCOM);
+        config.interpolation = ZeroCrossingInterpolation::Linear;
+        assert_eq!(config.resample_rate(16000), None);
+        assert_eq!(config.resample_rate(8000), Some(192000));
+
+        config.interpolation = ZeroCrossingInterpolation::None;
+        config.demodulation = Demodulation::Quadrature;
+        assert_eq!(config.resample_rate(16000), None);
+        assert_eq!(Demodulation::from("goertzel"), Demodulation::Quadrature);
+        assert_eq!(
+            Demodulation::from("ZeroCrossing"),
+            Demodulation::ZeroCrossing
+        );
+    }
+
+    #[test]
+    fn decoder_parity() {
+        use SignalCondition::{Mark, Space};
+        let config = DecoderConfig {
+            num_databits: 7,
+            ..DecoderConfig::default()
+        };
+        // 0x03 has two marks, the parity bit makes the total even or odd
+        let frame = |parity_bit| {
+            [
+                vec![Space, Mark, Mark],
+                vec![Space; 5],
+                vec![parity_bit, Mark],
+            ]
+            .concat()
+        };
+        for (parity, parity_bit, valid) in [
+            (Parity::EVEN, Space, true),
+            (Parity::EVEN, Mark, false),
+            (Parity::ODD, Mark, true),
+            (Parity::ODD, Space, false),
+        ] {
+            let mut decoder = Decoder::new(DecoderConfig { parity, ..config }).unwrap();
+            let result = frame(parity_bit)
+                .into_iter()
+                .map(|bit| decoder.process(bit))
+                .find(|result| result != &Err(None))
+                .unwrap();
+            match valid {
+                true => assert_eq!(result, Ok(0x03), "{parity}"),
+                false => assert_eq!(result, Err(Some(DecoderError::Parity)), "{parity}"),
+            }
+        }
+    }
+
+    #[test]
+    fn decoder_word_widths() {
+        use SignalCondition::{Mark, Space};
+        // 0b10011 in 5 bits, 0x10f in 9 bits and 0x8001 in 16 bits, as sent
+        for (num_databits, bits, lsb_first, msb_first) in [
+            (5, vec![Mark, Mark, Space, Space, Mark], 0b10011, 0b11001),
+            (
+                9,
+                vec![Mark, Mark, Mark, Mark, Space, Space, Space, Space, Mark],
+                0x10f,
+                0x1e1,
+            ),
+            (
+                16,
+                [vec![Mark], vec![Space; 14], vec![Mark]].concat(),
+                0x8001,
+                0x8001,
+            ),
+        ] {
+            for (bit_order, expected) in [
+                (BitOrder::LsbFirst, lsb_first),
+                (BitOrder::MsbFirst, msb_first),
+            ] {
+                let config = DecoderConfig {
+                    num_databits,
+                    bit_order,
+                    parity: Parity::ODD,
+                    ..DecoderConfig::default()
+                };
+                let mut decoder = Decoder::new(config).unwrap();
+                let marks = bits.iter().filter(|bit| **bit == Mark).count();
+                let parity_bit = if marks % 2 == 1 { Space } else { Mark };
+                let result = [vec![Space], bits.clone(), vec![parity_bit, Mark]]
+                    .concat()
+                    .into_iter()
+                    .map(|bit| decoder.process(bit))
+                    .find(|result| result != &Err(None));
+                assert_eq!(result, Some(Ok(expected)), "{num_databits} {bit_order}");
+            }
+        }
+
+        for num_databits in [4, 17] {
+            assert!(Decoder::new(DecoderConfig {
+                num_databits,
+                ..DecoderConfig::default()
+            })
+            .is_none());
+        }
+        let config = DecoderConfig {
+            num_databits: 9,
+            ..DecoderConfig::default()
+        };
+        assert!(Decoder::new(DecoderConfig {
+            framing: Framing::NASCOM,
+            ..config
+        })
+        .is_none());
+        assert_eq!(config.word_bytes(), 2);
+        assert_eq!(
+            config.words_to_bytes(&[0x1ff, 0x002]),
+            vec![0xff, 0x01, 0x02, 0x00]
+        );
+        assert_eq!(
+            config.bytes_to_words(&[0xff, 0x01, 0x02]),
+            vec![0x1ff, 0x002]
+        );
+    }
+
+    #[test]
+    fn decoder_salvage() {
+        use SignalCondition::{Error, Mark, Space};
+        let config = DecoderConfig {
+            num_databits: 7,
+            parity: Parity::EVEN,
+            ..DecoderConfig::default()
+        };
+        // 0x03 with a wrong parity bit, with an unreadable data bit and with a wrong stop bit
+        let frames = [
+            vec![
+                Space, Mark, Mark, Space, Space, Space, Space, Space, Mark, Mark,
+            ],
+            vec![
+                Space, Mark, Error, Space, Space, Space, Space, Space, Space, Mark,
+            ],
+            vec![
+                Space, Mark, Mark, Space, Space, Space, Space, Space, Space, Space,
+            ],
+        ];
+        let decode = |salvage: bool, bits: Vec<SignalCondition>| {
+            let mut decoder = Decoder::new(DecoderConfig { salvage, ..config }).unwrap();
+            bits.into_iter()
+                .map(|bit| (decoder.process(bit), decoder.salvaged()))
+                .filter(|(result, _)| result != &Err(None))
+                .collect::<Vec<_>>()
+        };
+        for (frame, error) in frames.iter().zip([
+            DecoderError::Parity,
+            DecoderError::Signal,
+            DecoderError::Sync,
+        ]) {
+            assert_eq!(decode(false, frame.clone())[0], (Err(Some(error)), None));
+        }
+        // The frames are decoded to the end, the unreadable bit is taken as space
+        assert_eq!(
+            decode(true, frames.concat()),
+            vec![
+                (Err(Some(DecoderError::Parity)), Some(0x03)),
+                (Err(Some(DecoderError::Signal)), Some(0x01)),
+                (Err(Some(DecoderError::Sync)), Some(0x03)),
+            ]
+        );
+    }
 }
@@ -1,50 +1,60 @@
 //#![allow(unused_imports, dead_code)]
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use kcs_decoder::nascom;
 use kcs_decoder::*;
-use riff_wave::WaveReader;
 use std::error::Error;
 use std::fmt::Debug;
 use std::fmt::Display;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::BufReader;
 use std::io::Write;
+use std::io::{BufRead, BufReader, BufWriter, Read};
+use std::iter;
 use std::ops::Deref;
+use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time;
 
 const MINIMUM_OUTPUT_FILE_SIZE: usize = 10;
+/// Number of samples per channel sent to the decoders at a time
+const BLOCK_LENGTH: usize = 16384;
+/// Number of blocks a decoder can lag behind before reading the input waits for it
+const BLOCK_QUEUE_LENGTH: usize = 16;
+/// Number of seconds at the start of the input that are analyzed to find the tape format
+const ANALYSIS_LENGTH: usize = 60;
+/// Bytes with a lower quality are flagged for review in the quality files
+const LOW_QUALITY: f32 = 0.5;
 
-fn decode_file(
-    input_filename: &str,
+type Samples = Box<dyn Iterator<Item = f32>>;
+
+/// Decodes the blocks of samples of one channel, looking at the zero crossings in one direction.
+/// The decoded stream is only collected if `keep_stream` or it is needed for NASCOM framing, otherwise it is returned empty.
+#[allow(clippy::too_many_arguments)]
+fn decode_channel(
+    blocks: impl Iterator<Item = Arc<[f32]>>,
+    input_sample_rate: u32,
     prefix: &str,
     config: &DecoderConfig,
     channel: u8,
     zc_direction: ZeroCrossingDirection,
-) -> Result<usize, Box<dyn Error>> {
-    let wavereader =
-        riff_wave::WaveReader::new(BufReader::new(File::open(input_filename)?)).unwrap();
-    let mut zc_detector = ZeroCrossingDetector::new(0.0);
-    let mut frq_calculator =
-        FrequencyIdentifier::new(zc_direction, wavereader.pcm_format.sample_rate);
-    let mut hi_low_identifier = HiLowIdentifier::new(
-        config.symbols[0].frequency as u32,
-        config.symbols[1].frequency as u32,
-        config.frequency_tolerance as u8,
-        (config.symbols[1].periods as u8, config.symbols[1].signal),
-        (config.symbols[0].periods as u8, config.symbols[0].signal),
-    )
-    .unwrap();
-    let mut decoder = Decoder::new(*config).ok_or(DecoderError::Config)?;
+    write_files: bool,
+    keep_stream: bool,
+) -> Result<(usize, DecodedStream), Box<dyn Error>> {
+    let mut kcs_decoder =
+        KcsDecoder::new(*config, input_sample_rate, zc_direction).ok_or(DecoderError::Config)?;
+    let keep_stream = keep_stream || (write_files && config.framing == Framing::NASCOM);
 
     let mut output_prev_idx: usize = 0;
-    let mut output_data: Vec<u8> = Vec::with_capacity(100000);
+    let mut output_data: Vec<Word> = Vec::with_capacity(100000);
+    let mut stream = DecodedStream::default();
 
     let mut files_written: usize = 0;
-    let samplerate = wavereader.pcm_format.sample_rate as usize;
-    let mut write_vector_to_disk = |idx: usize, data: &mut Vec<u8>| -> Result<(), std::io::Error> {
-        if data.len() >= MINIMUM_OUTPUT_FILE_SIZE {
+    let samplerate = kcs_decoder.sample_rate() as usize;
+    let mut write_vector_to_disk = |idx: usize,
+                                    data: &mut Vec<Word>|
+     -> Result<(), std::io::Error> {
+        if write_files && config.framing == Framing::Raw && data.len() >= MINIMUM_OUTPUT_FILE_SIZE {
             let filename = format!(
                 "{prefix}-ch{channel}-{}-{}.dat",
                 numsamples_to_timestring(output_prev_idx, samplerate),
@@ -56,7 +66,7 @@ fn decode_file(
             );
             println!("Writing file '{filename}'");
             let mut file = File::create(filename)?;
-            file.write_all(data)?;
+            file.write_all(&config.words_to_bytes(data))?;
             files_written += 1;
         }
         data.clear();
@@ -64,63 +74,376 @@ fn decode_file(
         Ok(())
     };
 
-    WaveReaderIteratorMono::new(wavereader, channel)
-        .unwrap()
-        .enumerate()
-        .filter_map(|val| (zc_detector.process(val)))
-        .filter_map(|val| frq_calculator.process(val))
-        .filter_map(|val| hi_low_identifier.process(val))
-        .chain([(0, SignalCondition::Mark)]) // To make sure we clock out the last data byte
-        .map(|(idx, val)| (idx, decoder.process(val)))
-        .filter(|(_idx, val)| {
-            val.is_ok() || (val.is_err() && val.as_ref().err().unwrap().is_some())
-        })
-        .for_each(|(idx, val)| {
-            match val {
-                Err(Some(DecoderError::Parity)) => {
+    let mut handle_event = |idx: usize, event: DecoderEvent| match event {
+        DecoderEvent::FramingError(error) => {
+            if keep_stream {
+                stream.errors.push((idx, error.clone()));
+            }
+            match &error {
+                DecoderError::Parity => {
                     eprintln!(
                         "Channel {}: Parity error at {}",
                         channel,
                         numsamples_to_timestring(idx, samplerate)
                     );
-                    write_vector_to_disk(idx, &mut output_data).unwrap();
                 }
-                Err(Some(DecoderError::Signal)) => {
+                DecoderError::Signal => {
                     //eprintln!("Signal error at sample {idx}");
-                    write_vector_to_disk(idx, &mut output_data).unwrap();
                 }
-                Err(Some(DecoderError::Sync)) => {
+                DecoderError::Sync => {
                     eprintln!(
                         "Channel {}: Sync error at {}",
                         channel,
                         numsamples_to_timestring(idx, samplerate)
                     );
-                    write_vector_to_disk(idx, &mut output_data).unwrap();
                 }
-                Err(Some(DecoderError::IO(val))) => {
+                DecoderError::IO(val) => {
                     eprintln!(
                         "Channel {}: IO error '{val}' at {}",
                         channel,
                         numsamples_to_timestring(idx, samplerate)
                     );
                 }
-                Err(Some(DecoderError::Other(val))) => {
+                DecoderError::Other(val) => {
                     eprintln!(
                         "Channel {}: Error '{val}' at {}",
                         channel,
                         numsamples_to_timestring(idx, samplerate)
                     );
                 }
-                Ok(val) => {
-                    output_data.push(val);
-                }
-                _ => {}
-            };
-        });
+                DecoderError::Config => {}
+            }
+            // In salvage mode the data continues in the same file
+            let framing_error = matches!(
+                error,
+                DecoderError::Parity | DecoderError::Signal | DecoderError::Sync
+            );
+            if framing_error && !config.salvage {
+                write_vector_to_disk(idx, &mut output_data).unwrap();
+            }
+        }
+        DecoderEvent::Byte(val, quality) => {
+            output_data.push(val);
+            if keep_stream {
+                stream.bytes.push((idx, val));
+                stream.quality.push(quality);
+            }
+        }
+        DecoderEvent::SuspectByte(val, quality, error) => {
+            eprintln!(
+                "Channel {}: Suspect byte 0x{val:02x} ({error} error) at {}",
+                channel,
+                numsamples_to_timestring(idx, samplerate)
+            );
+            output_data.push(val);
+            if keep_stream {
+                stream.bytes.push((idx, val));
+                stream.quality.push(quality);
+                stream.suspect.push((idx, error));
+            }
+        }
+        DecoderEvent::CarrierFound | DecoderEvent::CarrierLost => {}
+    };
+    for block in blocks {
+        kcs_decoder.process_with(&block, &mut handle_event);
+    }
+    kcs_decoder.finish_with(&mut handle_event);
+    // Both directions see the same levels, which only change the decoding with AGC or automatic hysteresis
+    let levels_used = config.agc || config.hysteresis == Hysteresis::Auto;
+    if levels_used
+        && (zc_direction == ZeroCrossingDirection::Neg
+            || config.demodulation == Demodulation::Quadrature)
+    {
+        println!("Channel {channel}: {}", kcs_decoder.level_summary());
+    }
+    let speed_summary = kcs_decoder.speed_summary();
+    // The speed is only used to adapt the tone bounds with '--track-speed'
+    if config.track_speed
+        && zc_direction == ZeroCrossingDirection::Neg
+        && speed_summary.mean().is_some()
+    {
+        let intervals = speed_summary
+            .intervals
+            .iter()
+            .map(|(idx, speed)| {
+                format!(
+                    "{} {:+.1}%",
+                    numsamples_to_timestring(*idx, samplerate),
+                    SpeedSummary::deviation(*speed)
+                )
+            })
+            .collect::<Vec<_>>();
+        println!(
+            "Channel {channel}: {}, over time: {}",
+            speed_summary,
+            intervals.join(", ")
+        );
+    }
     write_vector_to_disk(0, &mut output_data)?;
+    if write_files && config.framing == Framing::NASCOM {
+        let direction = match zc_direction {
+            ZeroCrossingDirection::Neg => "neg",
+            ZeroCrossingDirection::Pos => "pos",
+        };
+        files_written += write_programs(
+            &stream,
+            &format!("Channel {channel}"),
+            |idx| {
+                format!(
+                    "{prefix}-ch{channel}-{}-{direction}.cas",
+                    numsamples_to_timestring(idx, samplerate)
+                )
+            },
+            samplerate,
+        )?;
+    }
+    Ok((files_written, stream))
+}
+
+/// Input file or stdin, with the sample format for headerless input
+#[derive(Debug)]
+struct Input {
+    filename: String,
+    raw: Option<(WaveFormat, Endianness)>,
+}
+
+impl Input {
+    fn new(filename: String, raw: Option<(WaveFormat, Endianness)>) -> Self {
+        Self { filename, raw }
+    }
+
+    /// Opens the input and returns the format and the normalized, interleaved samples of all channels.
+    /// Stdin is streamed like a file, the samples are read as the decoders consume them.
+    fn open(&self) -> Result<(WaveFormat, Samples), Box<dyn Error>> {
+        let mut reader: BufReader<Box<dyn Read>> = BufReader::new(match self.filename.as_str() {
+            "-" => Box::new(io::stdin().lock()),
+            filename => Box::new(File::open(filename)?),
+        });
+        let is_flac = reader.fill_buf()?.starts_with(b"fLaC");
+        let mut wavereader = match self.raw {
+            Some((format, endianness)) => WaveReader::new_raw(reader, format, endianness)?,
+            #[cfg(feature = "flac")]
+            None if is_flac => {
+                let mut flacreader = FlacReader::new(reader)?;
+                let format = flacreader.format;
+                return Ok((
+                    format,
+                    Box::new(iter::from_fn(move || flacreader.read_sample())),
+                ));
+            }
+            #[cfg(not(feature = "flac"))]
+            None if is_flac => {
+                return Err("Reading FLAC input requires building with '--features flac'".into())
+            }
+            None => WaveReader::new(reader)?,
+        };
+        let format = wavereader.format;
+        Ok((
+            format,
+            Box::new(iter::from_fn(move || wavereader.read_sample().ok())),
+        ))
+    }
+}
+
+impl Display for Input {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.filename.as_str() {
+            "-" => write!(f, "<stdin>"),
+            filename => write!(f, "{filename}"),
+        }
+    }
+}
+
+/// Frames the stream into NASCOM blocks and writes one .cas file per program.
+/// A program starts at a block following the pilot tone and ends with block 0.
+/// The file of a program with missing blocks is named '-incomplete.cas'.
+fn write_programs(
+    stream: &DecodedStream,
+    name: &str,
+    filename: impl Fn(usize) -> String,
+    samplerate: usize,
+) -> Result<usize, io::Error> {
+    let mut framer = nascom::BlockFramer::new();
+    let mut program: Vec<nascom::TapeBlock> = vec![];
+    let mut program_idx: usize = 0;
+    let mut files_written: usize = 0;
+    let mut write_program =
+        |program: &mut Vec<nascom::TapeBlock>, program_idx: usize| -> Result<(), io::Error> {
+            if !program.is_empty() {
+                let complete = nascom::is_complete(program);
+                let filename = match complete {
+                    true => filename(program_idx),
+                    false => format!(
+                        "{}-incomplete.cas",
+                        filename(program_idx).trim_end_matches(".cas")
+                    ),
+                };
+                println!(
+                    "Writing file '{filename}' ({} blocks{})",
+                    program.len(),
+                    if complete { "" } else { ", incomplete" }
+                );
+                let missing = nascom::missing_blocks(program);
+                if !missing.is_empty() {
+                    eprintln!("{name}: Missing blocks {missing:?} in '{filename}'");
+                }
+                nascom::write_cas(program, &mut File::create(filename)?)?;
+                files_written += 1;
+            }
+            program.clear();
+            Ok(())
+        };
+
+    let events = stream.events();
+    let mut framer_events = events
+        .into_iter()
+        // NASCOM framing is only valid with up to 8 data bits, see `DecoderConfig`
+        .filter_map(|(idx, val)| framer.process((idx, val.map(|val| val as u8))))
+        .collect::<Vec<_>>();
+    framer_events.extend(framer.finish());
+    for (idx, event) in framer_events {
+        match event {
+            nascom::BlockEvent::Block(block) => {
+                if block.is_first_block || program.is_empty() {
+                    write_program(&mut program, program_idx)?;
+                    program_idx = idx;
+                }
+                let is_last_block = block.header.block_no == 0;
+                program.push(block);
+                if is_last_block {
+                    write_program(&mut program, program_idx)?;
+                }
+            }
+            nascom::BlockEvent::BadBlock(header, error) => {
+                eprintln!(
+                    "{name}: Bad block{} at {}: {error}",
+                    header
+                        .map(|header| format!(" no={}", header.block_no))
+                        .unwrap_or_default(),
+                    numsamples_to_timestring(idx, samplerate)
+                );
+            }
+        }
+    }
+    write_program(&mut program, program_idx)?;
     Ok(files_written)
 }
 
+/// Writes the position, value and quality of each byte of a stream as CSV, flagging the bytes below `LOW_QUALITY`
+fn write_quality_file(
+    filename: &str,
+    stream: &DecodedStream,
+    samplerate: usize,
+) -> Result<usize, Box<dyn Error>> {
+    let mut file = BufWriter::new(File::create(filename)?);
+    writeln!(file, "sample,seconds,value,quality,low")?;
+    let mut num_low = 0;
+    for (position, (idx, val)) in stream.bytes.iter().enumerate() {
+        let quality = stream.byte_quality(position);
+        let low = quality < LOW_QUALITY;
+        num_low += low as usize;
+        writeln!(
+            file,
+            "{idx},{:.4},0x{val:02x},{quality:.3},{}",
+            *idx as f64 / samplerate as f64,
+            low as u8
+        )?;
+    }
+    file.flush()?;
+    println!(
+        "Writing file '{filename}', {num_low} of {} bytes below quality {LOW_QUALITY}",
+        stream.bytes.len()
+    );
+    Ok(1)
+}
+
+/// Writes the offset in the stream, position, value and error of each suspect byte of a stream as CSV
+fn write_error_map(
+    filename: &str,
+    stream: &DecodedStream,
+    samplerate: usize,
+) -> Result<usize, Box<dyn Error>> {
+    let mut file = BufWriter::new(File::create(filename)?);
+    writeln!(file, "offset,sample,seconds,value,error")?;
+    for (offset, (idx, val)) in stream.bytes.iter().enumerate() {
+        if let Some(error) = stream.suspect_error(*idx) {
+            writeln!(
+                file,
+                "{offset},{idx},{:.4},0x{val:02x},{error}",
+                *idx as f64 / samplerate as f64
+            )?;
+        }
+    }
+    file.flush()?;
+    println!(
+        "Writing file '{filename}', {} of {} bytes suspect",
+        stream.suspect.len(),
+        stream.bytes.len()
+    );
+    Ok(1)
+}
+
+fn write_merged_file(
+    streams: &[DecodedStream],
+    prefix: &str,
+    config: &DecoderConfig,
+    sample_rate: u32,
+    quality: bool,
+) -> Result<usize, Box<dyn Error>> {
+    let merged = merge_streams(streams, config.frame_length(sample_rate) / 2);
+    let samplerate = sample_rate as usize;
+    println!(
+        "Merged {} streams: {} bytes, {} bytes filled in from other streams, {} conflicting bytes.",
+        streams.len(),
+        merged.bytes.len(),
+        merged.filled,
+        merged.conflicts
+    );
+    for idx in &merged.unresolved {
+        eprintln!(
+            "Unresolved error at {}, no stream could decode the data",
+            numsamples_to_timestring(*idx, samplerate)
+        );
+    }
+    let mut files_written = 0;
+    if quality {
+        files_written += write_quality_file(
+            &format!("{prefix}-merged-quality.csv"),
+            &DecodedStream::from(&merged),
+            samplerate,
+        )?;
+    }
+    if config.salvage {
+        files_written += write_error_map(
+            &format!("{prefix}-merged-errors.csv"),
+            &DecodedStream::from(&merged),
+            samplerate,
+        )?;
+    }
+    if config.framing == Framing::NASCOM {
+        return Ok(files_written
+            + write_programs(
+                &DecodedStream::from(&merged),
+                "Merged",
+                |idx| {
+                    format!(
+                        "{prefix}-merged-{}.cas",
+                        numsamples_to_timestring(idx, samplerate)
+                    )
+                },
+                samplerate,
+            )?);
+    }
+    if merged.bytes.len() < MINIMUM_OUTPUT_FILE_SIZE {
+        return Ok(files_written);
+    }
+    let filename = format!("{prefix}-merged.dat");
+    println!("Writing file '{filename}'");
+    let data = merged.bytes.iter().map(|(_, val)| *val).collect::<Vec<_>>();
+    File::create(filename)?.write_all(&config.words_to_bytes(&data))?;
+    Ok(files_written + 1)
+}
+
 #[derive(Debug)]
 struct BoxError<T: Error> {
     pub inner: Box<T>,
@@ -160,6 +483,259 @@ impl<T: Error> Deref for BoxError<T> {
     }
 }
 
+fn verify_file(input_filename: &str, output_filename: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let data = std::fs::read(input_filename)?;
+    if !nascom::find_header(&data).is_some_and(|offset| nascom::has_pilot(&data, offset)) {
+        println!("Missing pilot tone");
+    }
+
+    let (blocks, error) = nascom::read_blocks(&data);
+    if let Some(error) = error {
+        println!("Stopped reading: {error}");
+    }
+    println!("Got {} valid blocks:", blocks.len());
+    for block in &blocks {
+        println!("{block}");
+    }
+    if blocks.is_empty() {
+        println!("Found no data");
+        return Ok(());
+    }
+
+    let complete = nascom::is_complete(&blocks);
+    if complete {
+        println!("All blocks appear to be accounted for");
+    }
+    let missing = nascom::missing_blocks(&blocks);
+    if !missing.is_empty() {
+        println!("Missing blocks: {missing:?}");
+    }
+    if !blocks[0].is_first_block {
+        println!("Missing beginning of file");
+    }
+    if blocks[blocks.len() - 1].header.block_no != 0 {
+        println!("Missing end of file");
+    }
+
+    // Incomplete data is only written if an output file was explicitly given
+    let output_filename = match output_filename {
+        Some(filename) => filename.to_string(),
+        None if complete => format!(
+            "{}_cleaned.cas",
+            &input_filename[..input_filename.rfind('.').unwrap_or(input_filename.len())]
+        ),
+        None => return Ok(()),
+    };
+    let mut file = if blocks[0].is_first_block {
+        println!("Creating output file: {output_filename}");
+        File::create(&output_filename)?
+    } else {
+        println!("Appending to file: {output_filename}");
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&output_filename)?
+    };
+    nascom::write_cas(&blocks, &mut file)?;
+    Ok(())
+}
+
+fn verify_sol20_file(input_filename: &str) -> Result<(), Box<dyn Error>> {
+    let data = std::fs::read(input_filename)?;
+    let (files, error) = sol20::read_files(&data);
+    println!("Got {} valid files:", files.len());
+    for file in &files {
+        println!("{file}");
+    }
+    match error {
+        Some(error) => println!("Stopped reading: {error}"),
+        None if files.is_empty() => println!("Found no data"),
+        None => println!("All files are valid"),
+    }
+    Ok(())
+}
+
+fn assemble_files(
+    input_filenames: &[String],
+    output_filename: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let sources = input_filenames
+        .iter()
+        .map(std::fs::read)
+        .collect::<Result<Vec<_>, _>>()?;
+    let assembly = nascom::assemble(
+        &sources
+            .iter()
+            .map(|source| source.as_slice())
+            .collect::<Vec<_>>(),
+    );
+
+    println!("Assembled {} blocks:", assembly.blocks.len());
+    for (source_idx, block) in &assembly.blocks {
+        println!("{block} (from '{}')", input_filenames[*source_idx]);
+    }
+    if assembly.blocks.is_empty() {
+        println!("Found no data");
+        return Ok(());
+    }
+    if !assembly.has_first_block() {
+        println!("Missing beginning of file");
+    }
+    for block_no in &assembly.missing {
+        println!("Missing block no={block_no}");
+    }
+
+    // Incomplete data is only written if an output file was explicitly given
+    let complete = assembly.is_complete();
+    if complete {
+        println!("All blocks appear to be accounted for");
+    }
+    let output_filename = match output_filename {
+        Some(filename) => filename.to_string(),
+        None if complete => format!(
+            "{}_assembled.cas",
+            &input_filenames[0][..input_filenames[0]
+                .rfind('.')
+                .unwrap_or(input_filenames[0].len())]
+        ),
+        None => return Ok(()),
+    };
+    println!("Creating output file: {output_filename}");
+    let blocks = assembly
+        .blocks
+        .into_iter()
+        .map(|(_, block)| block)
+        .collect::<Vec<_>>();
+    nascom::write_cas(&blocks, &mut File::create(&output_filename)?)?;
+    Ok(())
+}
+
+fn encode_file(
+    input_filename: &str,
+    output_filename: Option<&str>,
+    config: &DecoderConfig,
+    encoder_config: EncoderConfig,
+    nascom_load_address: Option<u16>,
+) -> Result<(), Box<dyn Error>> {
+    let mut data = std::fs::read(input_filename)?;
+    if let Some(load_address) = nascom_load_address {
+        let blocks = nascom::split_blocks(&data, load_address)?;
+        println!(
+            "Writing {} NASCOM blocks from address {load_address:#06x}",
+            blocks.len()
+        );
+        data.clear();
+        nascom::write_cas(&blocks, &mut data)?;
+    }
+
+    let output_filename = match output_filename {
+        Some(filename) => filename.to_string(),
+        None => format!(
+            "{}.wav",
+            &input_filename[..input_filename.rfind('.').unwrap_or(input_filename.len())]
+        ),
+    };
+    let samples = Encoder::new(*config, encoder_config)
+        .ok_or(DecoderError::Config)?
+        .encode(&data);
+    println!(
+        "Creating output file: {output_filename} ({} bytes, {})",
+        data.len(),
+        numsamples_to_timestring(samples.len(), encoder_config.sample_rate as usize)
+    );
+    write_wav(
+        BufWriter::new(File::create(&output_filename)?),
+        &samples,
+        encoder_config.sample_rate,
+        encoder_config.bits_per_sample,
+    )?;
+    Ok(())
+}
+
+/// Analyzes the first `ANALYSIS_LENGTH` seconds of a channel of the input
+fn analyze_input(input: &Input, channel: u8) -> Result<Analysis, Box<dyn Error>> {
+    let (format, samples) = input
+        .open()
+        .map_err(|error| format!("Cannot read '{input}': {error}"))?;
+    if channel as u16 >= format.num_channels {
+        return Err(format!(
+            "Cannot analyze channel {channel}, '{input}' has {} channel(s)",
+            format.num_channels
+        )
+        .into());
+    }
+    let num_channels = format.num_channels as usize;
+    let length = format.sample_rate as usize * ANALYSIS_LENGTH * num_channels;
+    let samples = samples
+        .take(length)
+        .skip(channel as usize)
+        .step_by(num_channels)
+        .collect::<Vec<_>>();
+    let mut analyzer = Analyzer::new(format.sample_rate);
+    analyzer.process(&samples);
+    let analysis = analyzer
+        .finish()
+        .ok_or_else(|| format!("Cannot find a tape format in channel {channel} of '{input}'"))?;
+    println!("Analysis of channel {channel} of '{input}':\n{analysis}\n");
+    match format_arguments(&analysis) {
+        Some(arguments) => println!("Recommended options: {arguments}\n"),
+        None => println!(
+            "The tones do not match a preset or '--baud-rate', they can only be used from the library\n"
+        ),
+    }
+    Ok(analysis)
+}
+
+/// Command line options for the tape format found by the analysis, none if the tones cannot be given as options
+fn format_arguments(analysis: &Analysis) -> Option<String> {
+    let config = &analysis.config;
+    let (mut arguments, base) = match analysis.preset {
+        Some(preset) => (
+            vec![format!("--preset {preset}")],
+            DecoderConfig::get_preset(&preset),
+        ),
+        None => {
+            let baud_rate = analysis.baud_rate.round() as u16;
+            let base = DecoderConfig {
+                symbols: baud_rate_symbols(baud_rate),
+                ..DecoderConfig::get_preset(&Preset::Std)
+            };
+            let fits = base.symbols.iter().zip(&config.symbols).all(|(a, b)| {
+                a.half_periods == b.half_periods
+                    && a.signal == b.signal
+                    && (b.frequency as f32 / a.frequency as f32 - 1.0).abs() <= 0.05
+            });
+            if !fits {
+                return None;
+            }
+            (vec![format!("--baud-rate {baud_rate}")], base)
+        }
+    };
+    if config.num_databits != base.num_databits {
+        arguments.push(format!("--num-databits {}", config.num_databits));
+    }
+    if config.parity != base.parity {
+        arguments.push(format!("--parity {}", config.parity));
+    }
+    if config.startbits.1 != base.startbits.1 {
+        arguments.push(format!("--startbit {}", config.startbits.1));
+    }
+    if config.bit_order != base.bit_order {
+        arguments.push(format!("--bit-order {}", config.bit_order));
+    }
+    if (config.stopbits.0, config.half_stopbit) != (base.stopbits.0, base.half_stopbit) {
+        let half = if config.half_stopbit { ".5" } else { "" };
+        arguments.push(format!("--num-stopbits {}{half}", config.stopbits.0));
+    }
+    if config.stopbits.1 != base.stopbits.1 {
+        arguments.push(format!("--stopbit {}", config.stopbits.1));
+    }
+    if config.framing != base.framing {
+        arguments.push(format!("--framing {}", config.framing));
+    }
+    Some(arguments.join(" "))
+}
+
 fn numsamples_to_timestring(samples: usize, samplerate: usize) -> String {
     let seconds = samples / samplerate;
     format!("{:0>2}m{:0>2}s", seconds / 60, seconds % 60)
@@ -167,47 +743,67 @@ fn numsamples_to_timestring(samples: usize, samplerate: usize) -> String {
 
 fn parse_command_line_arguments(
     args: Args,
-) -> Result<(DecoderConfig, String, String), Box<dyn Error>> {
-    let mut config = DecoderConfig::get_preset(&args.preset);
-
-    config.parity = args.parity.unwrap_or(config.parity);
-    config.num_databits = usize::from(args.num_databits.unwrap_or(config.num_databits as u8));
-    config.startbits = (
-        usize::from(args.num_startbits.unwrap_or(config.startbits.0 as u8)),
-        args.startbit.unwrap_or(config.startbits.1),
-    );
-    config.stopbits = (
-        usize::from(args.num_stopbits.unwrap_or(config.stopbits.0 as u8)),
-        args.stopbit.unwrap_or(config.stopbits.1),
-    );
+) -> Result<(DecoderConfig, Input, String), Box<dyn Error>> {
+    let mut config = args.format.to_config();
     config.channels = args.channel;
-    if args.baud_rate.is_some() {
-        config.symbols = [
-            Symbol {
-                frequency: args.baud_rate.unwrap() as usize,
-                periods: 1,
-                signal: SignalCondition::Space,
-            },
-            Symbol {
-                frequency: 2 * args.baud_rate.unwrap() as usize,
-                periods: 2,
-                signal: SignalCondition::Mark,
-            },
-        ];
+    config.resample = args.resample;
+    config.interpolation = args.interpolation;
+    config.filter = args.filter;
+    config.agc = args.agc;
+    config.hysteresis = args.hysteresis;
+    config.demodulation = args.demodulator;
+    config.clock_recovery = args.clock_recovery;
+    config.track_speed = args.track_speed;
+    config.salvage = args.salvage;
+    config.resync = args.resync;
+    config.framing = args.framing;
+
+    let inputfile = args.inputfile.unwrap_or_default();
+    let extension_idx = inputfile.rfind('.').unwrap_or(inputfile.len());
+    match inputfile[extension_idx..].to_lowercase().as_str() {
+        _ if args.raw || inputfile == "-" => {}
+        ".wav" => {}
+        ".flac" if cfg!(feature = "flac") => {}
+        ".flac" => {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Reading .flac files requires building with '--features flac'",
+            )))
+        }
+        _ => {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Input file must be .wav or .flac, use '--raw' for headerless PCM",
+            )))
+        }
     }
+    let prefix = args.prefix.unwrap_or(match inputfile.as_str() {
+        "-" => "stdin".to_string(),
+        _ => inputfile[..extension_idx].to_string(),
+    });
+    let raw = args.raw.then_some((
+        WaveFormat {
+            num_channels: args.raw_channels,
+            sample_rate: args.raw_rate,
+            bits_per_sample: args.raw_bits,
+            sample_format: args.raw_format,
+        },
+        args.raw_endian,
+    ));
+    Ok((config, Input::new(inputfile, raw), prefix))
+}
 
-    if !args.inputfile.contains(".wav") {
-        return Err(Box::new(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Input file must be .wav",
-        )));
+/// Whole stop bits and whether there is a half more, e.g. 1.5
+fn parse_stopbits(value: &str) -> Result<(u8, bool), std::num::ParseIntError> {
+    match value.strip_suffix(".5") {
+        Some(whole) => Ok((whole.parse()?, true)),
+        None => Ok((value.parse()?, false)),
     }
-    Ok((
-        config,
-        args.inputfile.clone(),
-        args.prefix
-            .unwrap_or(args.inputfile[0usize..args.inputfile.find(".wav").unwrap()].to_string()),
-    ))
+}
+
+fn parse_load_address(value: &str) -> Result<u16, std::num::ParseIntError> {
+    let value = value.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(value, 16)
 }
 
 #[derive(Parser, Debug)]
@@ -218,9 +814,14 @@ fn parse_command_line_arguments(
 /// When an error is found in the stream, the state machine is reset and a new file will be started so all generated files can be assumed to be without detectable errors.
 ///
 /// The NASCOM preset is the only one that has been tested so far.
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Input .wav file (PCM format only)
-    inputfile: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input .wav file (integer PCM or IEEE float), or .flac file if built with the 'flac' feature. Use '-' to read from stdin
+    #[arg(required = true)]
+    inputfile: Option<String>,
 
     /// Optional output file prefix, default will use the name from the input file
     #[arg(long)]
@@ -231,7 +832,101 @@ struct Args {
     #[arg(short, long, default_value_t = Channels::All)]
     channel: Channels,
 
-    /// Base config. Use the options below to adjust the preset. (Standard|NASCOM|Acorn|MSX1200|MSX2400)
+    /// Resample the input before decoding. 'Auto' resamples to 192kHz if the input sample rate is too low for the preset (Auto|Off|<rate in Hz>)
+    #[arg(short, long, default_value_t = Resample::Auto)]
+    resample: Resample,
+
+    /// Filter the input before decoding. 'Auto' removes DC offset, hum and hiss with a band-pass from the tones of the preset,
+    /// 'DC' only removes DC offset. A custom band should reach well below the lowest tone (Auto|Off|DC|<low>-<high> in Hz)
+    #[arg(long, default_value_t = Filter::Auto)]
+    filter: Filter,
+
+    /// Normalize the signal level before decoding, for recordings with quiet passages
+    #[arg(long)]
+    agc: bool,
+
+    /// Hysteresis of the zero crossing detector. 'Auto' sets it from the measured noise floor, so the noise between blocks does not cause crossings (Auto|<percent of full scale>)
+    #[arg(long, default_value_t = Hysteresis::Percent(0))]
+    hysteresis: Hysteresis,
+
+    /// Estimate the time of each zero crossing between samples, reduces the need for resampling (None|Linear|Cubic)
+    #[arg(short, long, default_value_t = ZeroCrossingInterpolation::None)]
+    interpolation: ZeroCrossingInterpolation,
+
+    /// Demodulator. 'ZeroCrossing' measures each period of the tones, 'Quadrature' compares the energy of the mark and space tones over each bit, which is more robust against noise and jitter on worn tapes (ZeroCrossing|Quadrature)
+    #[arg(long, default_value_t = Demodulation::ZeroCrossing)]
+    demodulator: Demodulation,
+
+    /// Bit clock of the zero crossing demodulator. 'Periods' counts the periods of each bit, 'PLL' runs a bit clock that follows the changes of tone and the tape speed, so a dropped or extra period only affects one bit (Periods|PLL)
+    #[arg(long, default_value_t = ClockRecovery::Periods)]
+    clock_recovery: ClockRecovery,
+
+    /// Follow the tape speed. The mark and space frequency bounds are re-centred on the measured frequencies, for decks running fast or slow and for wow and flutter
+    #[arg(long)]
+    track_speed: bool,
+
+    /// Merge the output from all channels and zero crossing directions into a single file instead of writing one file per error.
+    /// Bytes are lined up by their position in the recording and gaps after errors in one stream are filled from the others.
+    #[arg(short, long)]
+    merge: bool,
+
+    /// Write the position, value and quality of each decoded byte to a CSV file per stream, or for the merged output.
+    /// The quality is from 0 to 1, bytes below 0.5 are flagged for review or for decoding from another pass
+    #[arg(long)]
+    quality: bool,
+
+    /// Keep decoding through framing errors. Bytes with a wrong parity or stop bit, or an unreadable bit, are kept with their best-guess value
+    /// instead of starting a new file, and listed with their offset and time in an error map CSV file next to the output
+    #[arg(long)]
+    salvage: bool,
+
+    /// After a sync error, search the following bits for the frame alignment that decodes this many frames without errors,
+    /// instead of waiting for the next start bit, which may be a data bit in a long run of data without gaps. 0 turns the search off
+    #[arg(long, default_value_t = 0)]
+    resync: usize,
+
+    /// Output framing. 'Raw' starts a new .dat file at every error, 'NASCOM' keeps the bytes of each block together,
+    /// reports damaged blocks and writes one .cas file per program (Raw|NASCOM)
+    #[arg(short, long, default_value_t = Framing::Raw)]
+    framing: Framing,
+
+    #[command(flatten)]
+    format: FormatArgs,
+
+    /// Detect the tape format from the first minute of the input and decode with it, in place of the preset and the tape format options.
+    /// Tapes with NASCOM headers are decoded with NASCOM framing. Cannot read from stdin
+    #[arg(long)]
+    auto: bool,
+
+    /// Read the input as headerless interleaved PCM samples in the format given by the --raw-* options
+    #[arg(long)]
+    raw: bool,
+
+    /// Sample rate of raw input in Hz
+    #[arg(long, default_value_t = 44100)]
+    raw_rate: u32,
+
+    /// Bits per sample of raw input (8|16|24|32 for integer, 32|64 for float). 8 bit samples are unsigned
+    #[arg(long, default_value_t = 16)]
+    raw_bits: u16,
+
+    /// Number of channels in raw input
+    #[arg(long, default_value_t = 1)]
+    raw_channels: u16,
+
+    /// Byte order of raw input (Little|Big)
+    #[arg(long, default_value_t = Endianness::Little)]
+    raw_endian: Endianness,
+
+    /// Sample encoding of raw input (Int|Float)
+    #[arg(long, default_value_t = SampleFormat::Int)]
+    raw_format: SampleFormat,
+}
+
+/// Tape format options shared by decoding and encoding
+#[derive(clap::Args, Debug)]
+struct FormatArgs {
+    /// Base config. Use the options below to adjust the preset. (Standard|NASCOM|Acorn|MSX1200|MSX2400|CUTS300|CUTS1200)
     #[arg(short, long, default_value_t = Preset::Std)]
     preset: Preset,
 
@@ -239,17 +934,21 @@ struct Args {
     #[arg(long)]
     baud_rate: Option<u16>,
 
-    /// Number of start bits (1|2)
+    /// Number of start bits (1-4)
     #[arg(long)]
     num_startbits: Option<u8>,
 
-    /// Number of data bits (7|8)
+    /// Number of data bits (5-16), more than 8 are written as two bytes per word, little endian
     #[arg(long)]
     num_databits: Option<u8>,
 
-    /// Number of stop bits (1|2)
+    /// Order of the data bits (LSB|MSB)
     #[arg(long)]
-    num_stopbits: Option<u8>,
+    bit_order: Option<BitOrder>,
+
+    /// Number of stop bits (1-4, also 1.5)
+    #[arg(long, value_parser = parse_stopbits)]
+    num_stopbits: Option<(u8, bool)>,
 
     /// Parity (None|Even|Odd|Space|Mark)
     #[arg(long)]
@@ -264,18 +963,236 @@ struct Args {
     stopbit: Option<SignalCondition>,
 }
 
+impl FormatArgs {
+    fn to_config(&self) -> DecoderConfig {
+        let mut config = DecoderConfig::get_preset(&self.preset);
+
+        config.parity = self.parity.unwrap_or(config.parity);
+        config.num_databits = usize::from(self.num_databits.unwrap_or(config.num_databits as u8));
+        config.bit_order = self.bit_order.unwrap_or(config.bit_order);
+        config.startbits = (
+            usize::from(self.num_startbits.unwrap_or(config.startbits.0 as u8)),
+            self.startbit.unwrap_or(config.startbits.1),
+        );
+        let (num_stopbits, half_stopbit) = self
+            .num_stopbits
+            .unwrap_or((config.stopbits.0 as u8, config.half_stopbit));
+        config.stopbits = (
+            usize::from(num_stopbits),
+            self.stopbit.unwrap_or(config.stopbits.1),
+        );
+        config.half_stopbit = half_stopbit;
+        if let Some(baud_rate) = self.baud_rate {
+            config.symbols = baud_rate_symbols(baud_rate);
+        }
+        config
+    }
+}
+
+/// Symbols of '--baud-rate', one period of the baud rate for space and two periods of twice the baud rate for mark
+fn baud_rate_symbols(baud_rate: u16) -> [Symbol; 2] {
+    [
+        Symbol {
+            frequency: baud_rate as usize,
+            half_periods: 2,
+            signal: SignalCondition::Space,
+        },
+        Symbol {
+            frequency: 2 * baud_rate as usize,
+            half_periods: 4,
+            signal: SignalCondition::Mark,
+        },
+    ]
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Verify a decoded NASCOM .dat file and write the valid blocks to a cleaned .cas file.
+    ///
+    /// Checks the pilot tone, headers and checksums and reports if all blocks were correct and accounted for.
+    /// If everything was accounted for, the cleaned file is written.
+    /// If something was missing, the good blocks are only written if an output file is given, so they can be manually recovered.
+    Verify {
+        /// Decoded .dat file
+        inputfile: String,
+
+        /// Output .cas file, default will use the name from the input file with '_cleaned' added
+        outputfile: Option<String>,
+
+        /// Verify the files of a Sol-20 tape instead, checking the header and block CRCs. No output is written
+        #[arg(long, conflicts_with = "outputfile")]
+        sol20: bool,
+    },
+
+    /// Assemble a complete NASCOM program from several decoded .dat files.
+    ///
+    /// Collects every block that passes its checksums from all input files, e.g. different tape passes, channels or directions,
+    /// and writes the program as a .cas file. Blocks that could not be found in any of the files are reported.
+    /// If blocks are missing, the output is only written if an output file is given.
+    Assemble {
+        /// Decoded .dat files
+        #[arg(required = true)]
+        inputfiles: Vec<String>,
+
+        /// Output .cas file, default will use the name from the first input file with '_assembled' added
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Analyze a recording and recommend the options to decode it.
+    ///
+    /// Finds the two tones and the baud rate from the frequencies of the periods in the first minute,
+    /// then decodes the bits with every combination of 7 or 8 data bits, parity and 1 or 2 stop bits
+    /// and ranks them by their rate of framing errors.
+    Analyze {
+        /// Input .wav file (integer PCM or IEEE float), or .flac file if built with the 'flac' feature
+        inputfile: String,
+
+        /// Channel to analyze if inputfile is multi-channel
+        #[arg(short, long, default_value_t = 0)]
+        channel: u8,
+    },
+
+    /// Encode a binary file as a KCS tape recording in a .wav file.
+    ///
+    /// The data is framed and modulated with the same options as used for decoding, preceded and followed by an idle tone.
+    /// With '--nascom' the file is split into NASCOM blocks with a pilot tone, so it can be loaded with the NAS-SYS 'R' command.
+    Encode {
+        /// Binary input file
+        inputfile: String,
+
+        /// Output .wav file, default will use the name from the input file
+        outputfile: Option<String>,
+
+        #[command(flatten)]
+        format: FormatArgs,
+
+        /// Sample rate of the output in Hz
+        #[arg(short, long, default_value_t = EncoderConfig::default().sample_rate)]
+        sample_rate: u32,
+
+        /// Bits per sample (8|16|24|32)
+        #[arg(long, default_value_t = EncoderConfig::default().bits_per_sample)]
+        bits_per_sample: u16,
+
+        /// Peak amplitude relative to full scale (0.0 - 1.0)
+        #[arg(short, long, default_value_t = EncoderConfig::default().amplitude)]
+        amplitude: f32,
+
+        /// Length of the leader tone in seconds
+        #[arg(long, default_value_t = EncoderConfig::default().leader_length)]
+        leader: f32,
+
+        /// Length of the trailer tone in seconds
+        #[arg(long, default_value_t = EncoderConfig::default().trailer_length)]
+        trailer: f32,
+
+        /// Write the data as NASCOM blocks loaded from this address, e.g. 0x1000 (hexadecimal)
+        #[arg(long, value_parser = parse_load_address)]
+        nascom: Option<u16>,
+    },
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     //let myboxerror2: Result<DecoderState, _> = Err(DecoderError::Parity);
     //let _fg = myboxerror2?;
 
     let start = time::Instant::now();
 
-    let config = parse_command_line_arguments(Args::parse()).expect("Parsing config");
-    let pcm_format = WaveReader::new(File::open(config.1.clone())?)
-        .unwrap()
-        .pcm_format;
+    let args = Args::parse();
+    match &args.command {
+        Some(Command::Verify {
+            inputfile,
+            sol20: true,
+            ..
+        }) => return verify_sol20_file(inputfile),
+        Some(Command::Verify {
+            inputfile,
+            outputfile,
+            ..
+        }) => return verify_file(inputfile, outputfile.as_deref()),
+        Some(Command::Assemble { inputfiles, output }) => {
+            return assemble_files(inputfiles, output.as_deref())
+        }
+        Some(Command::Analyze { inputfile, channel }) => {
+            return analyze_input(&Input::new(inputfile.clone(), None), *channel).map(|_| ())
+        }
+        Some(Command::Encode {
+            inputfile,
+            outputfile,
+            format,
+            sample_rate,
+            bits_per_sample,
+            amplitude,
+            leader,
+            trailer,
+            nascom,
+        }) => {
+            let encoder_config = EncoderConfig {
+                sample_rate: *sample_rate,
+                bits_per_sample: *bits_per_sample,
+                amplitude: *amplitude,
+                leader_length: *leader,
+                trailer_length: *trailer,
+            };
+            return encode_file(
+                inputfile,
+                outputfile.as_deref(),
+                &format.to_config(),
+                encoder_config,
+                *nascom,
+            );
+        }
+        None => {}
+    }
+
+    let merge = args.merge;
+    let quality = args.quality;
+    let auto = args.auto;
+    let mut config = parse_command_line_arguments(args).expect("Parsing config");
+    if auto {
+        // The input is read once for the analysis and again for decoding
+        if config.1.filename == "-" {
+            return Err("'--auto' cannot read from stdin".into());
+        }
+        let channel = match config.0.channels {
+            Channels::Specific(ch) => ch,
+            Channels::All => 0,
+        };
+        let detected = analyze_input(&config.1, channel)?.config;
+        config.0.symbols = detected.symbols;
+        config.0.startbits = detected.startbits;
+        config.0.num_databits = detected.num_databits;
+        config.0.parity = detected.parity;
+        config.0.stopbits = detected.stopbits;
+        if detected.framing == Framing::NASCOM {
+            config.0.framing = Framing::NASCOM;
+        }
+    }
+    if Decoder::new(config.0).is_none() {
+        return Err(
+            "Invalid format, use 5-16 data bits (at most 8 with NASCOM framing) and 1-4 start and stop bits".into(),
+        );
+    }
+    if config.0.clock_recovery == ClockRecovery::Pll
+        && config.0.demodulation == Demodulation::ZeroCrossing
+        && config.0.half_period_symbols()
+    {
+        return Err("'--clock-recovery PLL' needs symbols of whole periods, use 'Periods'".into());
+    }
+    let (format, mut samples) = config
+        .1
+        .open()
+        .map_err(|error| format!("Cannot read '{}': {error}", config.1))?;
     let channelbounds = match config.0.channels {
-        Channels::All => 0..pcm_format.num_channels as u8,
+        Channels::All => 0..format.num_channels as u8,
+        Channels::Specific(ch) if ch as u16 >= format.num_channels => {
+            return Err(format!(
+                "Cannot decode channel {ch}, '{}' has {} channel(s)",
+                config.1, format.num_channels
+            )
+            .into())
+        }
         Channels::Specific(ch) => ch..ch + 1,
     };
 
@@ -283,24 +1200,109 @@ fn main() -> Result<(), Box<dyn Error>> {
         "Processing '{}', using output file prefix '{}'.\nActive decoder config:\n{}\n",
         config.1, config.2, config.0
     );
+    if let Some(rate) = config.0.resample_rate(format.sample_rate) {
+        println!(
+            "Resampling input from {} Hz to {rate} Hz.\n",
+            format.sample_rate
+        );
+    }
+
+    // One decoder thread per channel and zero crossing direction, each fed with blocks of its channel.
+    // The quadrature demodulator does not look at zero crossings, so it needs one thread per channel.
+    let zc_directions = match config.0.demodulation {
+        Demodulation::ZeroCrossing => vec![ZeroCrossingDirection::Neg, ZeroCrossingDirection::Pos],
+        Demodulation::Quadrature => vec![ZeroCrossingDirection::Pos],
+    };
     let mut threadpool = vec![];
+    let mut senders = vec![];
     for i in channelbounds {
-        let (config1, filename, prefix) = config.clone();
-        threadpool.push(thread::spawn(move || -> Result<usize, io::Error> {
-            decode_file(&filename, &prefix, &config1, i, ZeroCrossingDirection::Neg).or(Err(
-                io::Error::new(io::ErrorKind::Other, "Error reported during decoding"),
-            ))
-        }));
-        let (config2, filename, prefix) = config.clone();
-        threadpool.push(thread::spawn(move || -> Result<usize, io::Error> {
-            decode_file(&filename, &prefix, &config2, i, ZeroCrossingDirection::Pos).or(Err(
-                io::Error::new(io::ErrorKind::Other, "Error reported during decoding"),
-            ))
-        }));
+        for &zc_direction in &zc_directions {
+            let (sender, receiver) = mpsc::sync_channel::<Arc<[f32]>>(BLOCK_QUEUE_LENGTH);
+            senders.push((i as usize, sender));
+            let (config1, prefix) = (config.0, config.2.clone());
+            // Only the merged output and the CSV files need the decoded stream after decoding
+            let keep_stream = merge || quality || config1.salvage;
+            threadpool.push((
+                i,
+                zc_direction,
+                thread::spawn(move || -> Result<(usize, DecodedStream), io::Error> {
+                    decode_channel(
+                        receiver.into_iter(),
+                        format.sample_rate,
+                        &prefix,
+                        &config1,
+                        i,
+                        zc_direction,
+                        !merge,
+                        keep_stream,
+                    )
+                    .or(Err(io::Error::other("Error reported during decoding")))
+                }),
+            ));
+        }
     }
+
+    // The input is read once and de-interleaved, a trailing incomplete frame is dropped
+    let num_channels = format.num_channels as usize;
+    let mut blocks = vec![Vec::with_capacity(BLOCK_LENGTH); num_channels];
+    let mut frame = Vec::with_capacity(num_channels);
+    loop {
+        frame.clear();
+        frame.extend(samples.by_ref().take(num_channels));
+        let end_of_input = frame.len() < num_channels;
+        if !end_of_input {
+            for (block, sample) in blocks.iter_mut().zip(&frame) {
+                block.push(*sample);
+            }
+        }
+        if end_of_input || blocks[0].len() == BLOCK_LENGTH {
+            let shared = blocks
+                .iter_mut()
+                .map(|block| Arc::from(std::mem::replace(block, Vec::with_capacity(BLOCK_LENGTH))))
+                .collect::<Vec<Arc<[f32]>>>();
+            for (channel, sender) in &senders {
+                // A decoder that has stopped does not need more samples
+                let _ = sender.send(shared[*channel].clone());
+            }
+        }
+        if end_of_input {
+            break;
+        }
+    }
+    drop(senders);
+
+    let sample_rate = config
+        .0
+        .resample_rate(format.sample_rate)
+        .unwrap_or(format.sample_rate);
     let mut files_written: usize = 0;
-    for handle in threadpool {
-        files_written += handle.join().unwrap().unwrap_or(0);
+    let mut streams = vec![];
+    for (channel, zc_direction, handle) in threadpool {
+        if let Ok((num_files, stream)) = handle.join().unwrap() {
+            files_written += num_files;
+            let direction = match zc_direction {
+                ZeroCrossingDirection::Neg => "neg",
+                ZeroCrossingDirection::Pos => "pos",
+            };
+            if quality && !merge {
+                files_written += write_quality_file(
+                    &format!("{}-ch{channel}-{direction}-quality.csv", config.2),
+                    &stream,
+                    sample_rate as usize,
+                )?;
+            }
+            if config.0.salvage && !merge {
+                files_written += write_error_map(
+                    &format!("{}-ch{channel}-{direction}-errors.csv", config.2),
+                    &stream,
+                    sample_rate as usize,
+                )?;
+            }
+            streams.push(stream);
+        }
+    }
+    if merge {
+        files_written += write_merged_file(&streams, &config.2, &config.0, sample_rate, quality)?;
     }
     println!(
         "Completed in {:.2} seconds, {files_written} files produced.",
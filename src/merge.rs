@@ -0,0 +1,351 @@
+use crate::{DecoderError, Word};
+
+/// Resolution of the byte quality when choosing between values
+const QUALITY_STEPS: f32 = 100.0;
+
+/// Output from one decoder, i.e. one channel and zero crossing direction.
+/// Bytes (words of the configured data bits) and errors are tagged with the sample index where they were found.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DecodedStream {
+    pub bytes: Vec<(usize, Word)>,
+    pub errors: Vec<(usize, DecoderError)>,
+    /// Quality of each byte, see `DecoderEvent::Byte`, empty if it was not measured
+    pub quality: Vec<f32>,
+    /// Bytes that were decoded from a frame with an error in salvage mode, with the error, see `DecoderEvent::SuspectByte`
+    pub suspect: Vec<(usize, DecoderError)>,
+}
+
+impl DecodedStream {
+    /// Bytes and errors combined in sample order
+    pub fn events(&self) -> Vec<(usize, Result<Word, DecoderError>)> {
+        let mut events = self
+            .bytes
+            .iter()
+            .map(|&(idx, val)| (idx, Ok(val)))
+            .chain(
+                self.errors
+                    .iter()
+                    .map(|(idx, error)| (*idx, Err(error.clone()))),
+            )
+            .collect::<Vec<_>>();
+        events.sort_by_key(|(idx, _)| *idx);
+        events
+    }
+
+    /// Quality of the byte at `position` in `bytes`, 1.0 if it was not measured
+    pub fn byte_quality(&self, position: usize) -> f32 {
+        self.quality.get(position).copied().unwrap_or(1.0)
+    }
+
+    /// Error of the byte at sample index `idx`, if it is a suspect byte
+    pub fn suspect_error(&self, idx: usize) -> Option<&DecoderError> {
+        self.suspect
+            .binary_search_by_key(&idx, |(suspect_idx, _)| *suspect_idx)
+            .ok()
+            .map(|position| &self.suspect[position].1)
+    }
+
+    /// Errors within `distance` samples of `idx`, the errors are expected to be in sample order
+    fn errors_near(&self, idx: usize, distance: usize) -> &[(usize, DecoderError)] {
+        let first = self
+            .errors
+            .partition_point(|(error_idx, _)| *error_idx + distance < idx);
+        let last = self
+            .errors
+            .partition_point(|(error_idx, _)| *error_idx <= idx + distance);
+        &self.errors[first..last.max(first)]
+    }
+
+    fn has_framing_error_near(&self, idx: usize, distance: usize) -> bool {
+        self.errors_near(idx, distance)
+            .iter()
+            .any(|(_, error)| matches!(error, DecoderError::Parity | DecoderError::Sync))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MergedStream {
+    pub bytes: Vec<(usize, Word)>,
+    /// Quality of each byte, the best quality of the streams that decoded the chosen value
+    pub quality: Vec<f32>,
+    /// Bytes that no stream decoded without an error, with the error of one of the streams
+    pub suspect: Vec<(usize, DecoderError)>,
+    /// Number of bytes taken from other streams where at least one stream had a framing error
+    pub filled: usize,
+    /// Number of bytes where the streams did not agree on the value
+    pub conflicts: usize,
+    /// Framing errors where none of the streams decoded a byte
+    pub unresolved: Vec<usize>,
+}
+
+impl From<&MergedStream> for DecodedStream {
+    /// The merged bytes, with the unresolved errors as sync errors
+    fn from(value: &MergedStream) -> Self {
+        Self {
+            bytes: value.bytes.clone(),
+            quality: value.quality.clone(),
+            suspect: value.suspect.clone(),
+            errors: value
+                .unresolved
+                .iter()
+                .map(|idx| (*idx, DecoderError::Sync))
+                .collect(),
+        }
+    }
+}
+
+/// Merges parallel decodings of the same recording into one stream.
+///
+/// Bytes from the different streams are lined up by sample index, bytes less than `tolerance` samples apart are
+/// considered the same byte. A byte is output if any stream decoded it, so the gaps after parity and sync errors
+/// in one stream are filled from the others. If the streams disagree, the majority wins, and on a tie the value
+/// from the stream with the fewest errors around the byte is used, then the value with the best quality.
+/// Suspect bytes only count if no stream decoded the byte without an error.
+/// `tolerance` should be about half the length of a frame.
+pub fn merge_streams(streams: &[DecodedStream], tolerance: usize) -> MergedStream {
+    let frame_length = 2 * tolerance;
+    let mut all = streams
+        .iter()
+        .enumerate()
+        .flat_map(|(stream_idx, stream)| {
+            stream
+                .bytes
+                .iter()
+                .enumerate()
+                .map(move |(position, &(idx, val))| {
+                    (idx, val, stream_idx, stream.byte_quality(position))
+                })
+        })
+        .collect::<Vec<_>>();
+    all.sort_by_key(|&(idx, _, stream_idx, _)| (idx, stream_idx));
+
+    let mut merged = MergedStream::default();
+    let mut start = 0;
+    while start < all.len() {
+        let cluster_idx = all[start].0;
+        let mut end = start;
+        while end < all.len()
+            && all[end].0 <= cluster_idx + tolerance
+            && !all[start..end].iter().any(|other| other.2 == all[end].2)
+        {
+            end += 1;
+        }
+        let cluster = &all[start..end];
+
+        // (votes, errors nearby, value, sample index, quality)
+        let mut candidates: Vec<(usize, usize, Word, usize, f32)> = vec![];
+        let suspect = |&(idx, _, stream_idx, _): &(usize, Word, usize, f32)| {
+            streams[stream_idx].suspect_error(idx)
+        };
+        let clean = cluster.iter().any(|byte| suspect(byte).is_none());
+        for byte @ &(idx, val, stream_idx, quality) in cluster {
+            if clean && suspect(byte).is_some() {
+                continue;
+            }
+            let errors = streams[stream_idx].errors_near(idx, 8 * frame_length).len();
+            match candidates.iter_mut().find(|candidate| candidate.2 == val) {
+                Some(candidate) => {
+                    candidate.0 += 1;
+                    candidate.1 = candidate.1.min(errors);
+                    candidate.4 = candidate.4.max(quality);
+                }
+                None => candidates.push((1, errors, val, idx, quality)),
+            }
+        }
+        let best = candidates
+            .iter()
+            .min_by_key(|(votes, errors, _, _, quality)| {
+                (
+                    usize::MAX - votes,
+                    *errors,
+                    ((1.0 - quality) * QUALITY_STEPS) as usize,
+                )
+            })
+            .unwrap();
+        merged.bytes.push((best.3, best.2));
+        merged.quality.push(best.4);
+        if !clean {
+            let error = cluster
+                .iter()
+                .find(|byte| byte.0 == best.3 && byte.1 == best.2)
+                .and_then(suspect);
+            merged
+                .suspect
+                .extend(error.map(|error| (best.3, error.clone())));
+        }
+        if candidates.len() > 1 {
+            merged.conflicts += 1;
+        }
+        let missing_with_error = streams.iter().enumerate().any(|(stream_idx, stream)| {
+            !cluster.iter().any(|other| other.2 == stream_idx)
+                && stream.has_framing_error_near(cluster_idx, frame_length)
+        });
+        if missing_with_error {
+            merged.filled += 1;
+        }
+        start = end;
+    }
+
+    // A framing error is resolved if any stream has a byte within a frame of it
+    let mut framing_errors = streams
+        .iter()
+        .flat_map(|stream| stream.errors.iter())
+        .filter(|(_, error)| matches!(error, DecoderError::Parity | DecoderError::Sync))
+        .map(|(idx, _)| *idx)
+        .collect::<Vec<_>>();
+    framing_errors.sort();
+    for idx in framing_errors {
+        let next = merged
+            .bytes
+            .partition_point(|(byte_idx, _)| *byte_idx + frame_length < idx);
+        let resolved = merged
+            .bytes
+            .get(next)
+            .is_some_and(|(byte_idx, _)| byte_idx.abs_diff(idx) <= frame_length);
+        let duplicate = merged
+            .unresolved
+            .last()
+            .is_some_and(|last| idx - last <= frame_length);
+        if !resolved && !duplicate {
+            merged.unresolved.push(idx);
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_fills_gaps() {
+        let streams = [
+            DecodedStream {
+                bytes: vec![(100, 1), (200, 2), (400, 4)],
+                errors: vec![(290, DecoderError::Parity)],
+                ..Default::default()
+            },
+            DecodedStream {
+                bytes: vec![(105, 1), (305, 3), (405, 4)],
+                errors: vec![(195, DecoderError::Sync)],
+                ..Default::default()
+            },
+        ];
+        let merged = merge_streams(&streams, 50);
+
+        assert_eq!(merged.bytes, vec![(100, 1), (200, 2), (305, 3), (400, 4)]);
+        assert_eq!(merged.filled, 2);
+        assert_eq!(merged.conflicts, 0);
+        assert!(merged.unresolved.is_empty());
+    }
+
+    #[test]
+    fn merge_majority_vote() {
+        let streams = [
+            DecodedStream {
+                bytes: vec![(100, 1), (200, 0xFF)],
+                errors: vec![],
+                ..Default::default()
+            },
+            DecodedStream {
+                bytes: vec![(101, 1), (201, 2)],
+                errors: vec![],
+                ..Default::default()
+            },
+            DecodedStream {
+                bytes: vec![(102, 1), (202, 2)],
+                errors: vec![],
+                ..Default::default()
+            },
+        ];
+        let merged = merge_streams(&streams, 50);
+
+        assert_eq!(merged.bytes, vec![(100, 1), (201, 2)]);
+        assert_eq!(merged.conflicts, 1);
+    }
+
+    #[test]
+    fn merge_tie_prefers_fewest_errors() {
+        let streams = [
+            DecodedStream {
+                bytes: vec![(100, 0xFF)],
+                errors: vec![(150, DecoderError::Signal)],
+                ..Default::default()
+            },
+            DecodedStream {
+                bytes: vec![(101, 1)],
+                errors: vec![],
+                ..Default::default()
+            },
+        ];
+        let merged = merge_streams(&streams, 50);
+        assert_eq!(merged.bytes, vec![(101, 1)]);
+    }
+
+    #[test]
+    fn merge_tie_prefers_quality() {
+        let streams = [
+            DecodedStream {
+                bytes: vec![(100, 0xFF), (200, 2)],
+                errors: vec![],
+                quality: vec![0.3, 0.9],
+                ..Default::default()
+            },
+            DecodedStream {
+                bytes: vec![(101, 1), (201, 2)],
+                errors: vec![],
+                quality: vec![0.8, 0.6],
+                ..Default::default()
+            },
+            // Quality not measured
+            DecodedStream {
+                bytes: vec![(300, 3)],
+                errors: vec![],
+                ..Default::default()
+            },
+        ];
+        let merged = merge_streams(&streams, 50);
+        assert_eq!(merged.bytes, vec![(101, 1), (200, 2), (300, 3)]);
+        assert_eq!(merged.quality, vec![0.8, 0.9, 1.0]);
+    }
+
+    #[test]
+    fn merge_prefers_clean_bytes() {
+        let streams = [
+            DecodedStream {
+                bytes: vec![(100, 0xFF), (200, 0xFE), (300, 3)],
+                suspect: vec![(100, DecoderError::Parity), (200, DecoderError::Sync)],
+                ..Default::default()
+            },
+            DecodedStream {
+                bytes: vec![(101, 1), (201, 0xFE), (301, 0xFD)],
+                suspect: vec![(201, DecoderError::Signal), (301, DecoderError::Parity)],
+                ..Default::default()
+            },
+        ];
+        let merged = merge_streams(&streams, 50);
+        assert_eq!(merged.bytes, vec![(101, 1), (200, 0xFE), (300, 3)]);
+        // Both streams had an error in the second byte
+        assert_eq!(merged.suspect, vec![(200, DecoderError::Sync)]);
+        assert_eq!(merged.conflicts, 0);
+    }
+
+    #[test]
+    fn merge_reports_unresolved_errors() {
+        let streams = [
+            DecodedStream {
+                bytes: vec![(100, 1), (400, 4)],
+                errors: vec![(250, DecoderError::Parity)],
+                ..Default::default()
+            },
+            DecodedStream {
+                bytes: vec![(100, 1), (400, 4)],
+                errors: vec![(255, DecoderError::Sync)],
+                ..Default::default()
+            },
+        ];
+        let merged = merge_streams(&streams, 50);
+        assert_eq!(merged.bytes, vec![(100, 1), (400, 4)]);
+        assert_eq!(merged.unresolved, vec![250]);
+    }
+}
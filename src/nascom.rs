@@ -0,0 +1,741 @@
+//! NASCOM tape data format
+//!
+//! See 'WRITE COMMAND' in <http://nascomhomepage.com/pdf/Nassys3.pdf>
+//!
+//! Pilot tone - before first block:
+//! 256 bytes of 0x00
+//!
+//! Then follows a number of blocks of up to 277 bytes each:
+//!
+//! Each block:
+//! - 10 bytes header
+//! - 1-256 bytes data
+//! - 1 byte checksum
+//! - 10 bytes nulls
+//!
+//! Block header:
+//! - 1 byte 0x00
+//! - 4 bytes 0xFF
+//! - 1 byte: Load address LSB
+//! - 1 byte: Load address MSB (first block is usually 0x10 here for BASIC programs)
+//! - 1 byte: Size of 'data' (0x00 means 256 bytes, which is max)
+//! - 1 byte: Block no / Number of blocks remaining (last block contains 0x00 here)
+//! - 1 byte: Header checksum
+//!
+//! The data checksum accumulates the data bytes with rollover.
+
+use crate::DecoderError;
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::fmt::Display;
+use std::io::Write;
+
+pub const PILOT_LENGTH: usize = 256;
+pub const HEADER_LENGTH: usize = 10;
+pub const HEADER_MAGIC: [u8; 5] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF];
+pub const TRAILER_LENGTH: usize = 10;
+pub const MAX_DATA_SIZE: usize = 256;
+/// Block numbers are one byte, so a program has at most 256 blocks
+pub const MAX_NUM_BLOCKS: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NascomError {
+    MissingHeader,
+    IncompleteHeader,
+    HeaderChecksum {
+        expected: u8,
+        calculated: u8,
+    },
+    IncompleteBlock(u8),
+    DataChecksum {
+        block_no: u8,
+        expected: u8,
+        calculated: u8,
+    },
+    Decoder(DecoderError),
+    /// The program needs this many blocks, more than `MAX_NUM_BLOCKS`
+    TooManyBlocks(usize),
+}
+
+impl Display for NascomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NascomError::MissingHeader => write!(f, "no block header found"),
+            NascomError::IncompleteHeader => write!(f, "incomplete header"),
+            NascomError::HeaderChecksum {
+                expected,
+                calculated,
+            } => write!(
+                f,
+                "mismatching header checksum found, expected {expected:#04x}, got {calculated:#04x}"
+            ),
+            NascomError::IncompleteBlock(block_no) => write!(f, "block {block_no} is incomplete"),
+            NascomError::DataChecksum {
+                block_no,
+                expected,
+                calculated,
+            } => write!(
+                f,
+                "mismatching data checksum found in block {block_no}, expected {expected:#04x}, got {calculated:#04x}"
+            ),
+            NascomError::Decoder(error) => write!(f, "{error} error in block"),
+            NascomError::TooManyBlocks(num_blocks) => write!(
+                f,
+                "program needs {num_blocks} blocks, at most {MAX_NUM_BLOCKS} can be numbered"
+            ),
+        }
+    }
+}
+
+impl Error for NascomError {}
+
+/// Accumulates bytes with rollover
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &val| sum.wrapping_add(val))
+}
+
+/// Returns the offset of the first block header in `buffer`
+pub fn find_header(buffer: &[u8]) -> Option<usize> {
+    buffer
+        .windows(HEADER_MAGIC.len())
+        .position(|window| window == HEADER_MAGIC)
+}
+
+/// Returns true if the header at `offset` is preceded by the pilot tone
+pub fn has_pilot(buffer: &[u8], offset: usize) -> bool {
+    offset >= PILOT_LENGTH
+        && buffer[offset - PILOT_LENGTH..offset]
+            .iter()
+            .all(|&val| val == 0)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub load_address: u16,
+    pub data_size: usize,
+    pub block_no: u8,
+}
+
+impl BlockHeader {
+    /// Parses and validates a header, `buffer` must begin with the header magic bytes
+    pub fn parse(buffer: &[u8]) -> Result<Self, NascomError> {
+        if buffer.len() < HEADER_LENGTH {
+            return Err(NascomError::IncompleteHeader);
+        }
+        if buffer[..HEADER_MAGIC.len()] != HEADER_MAGIC {
+            return Err(NascomError::MissingHeader);
+        }
+        let expected = buffer[HEADER_LENGTH - 1];
+        let calculated = checksum(&buffer[HEADER_MAGIC.len()..HEADER_LENGTH - 1]);
+        if expected != calculated {
+            return Err(NascomError::HeaderChecksum {
+                expected,
+                calculated,
+            });
+        }
+        Ok(Self {
+            load_address: u16::from_le_bytes([buffer[5], buffer[6]]),
+            data_size: match buffer[7] {
+                0 => MAX_DATA_SIZE,
+                size => size as usize,
+            },
+            block_no: buffer[8],
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_LENGTH] {
+        let [lsb, msb] = self.load_address.to_le_bytes();
+        let mut header = [0u8; HEADER_LENGTH];
+        header[..HEADER_MAGIC.len()].copy_from_slice(&HEADER_MAGIC);
+        header[5] = lsb;
+        header[6] = msb;
+        header[7] = (self.data_size % MAX_DATA_SIZE) as u8;
+        header[8] = self.block_no;
+        header[9] = checksum(&header[HEADER_MAGIC.len()..HEADER_LENGTH - 1]);
+        header
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapeBlock {
+    pub header: BlockHeader,
+    pub data: Vec<u8>,
+    /// True if the block was preceded by the pilot tone, i.e. it is the first block of a program
+    pub is_first_block: bool,
+}
+
+impl TapeBlock {
+    /// Creates a block from 1-256 bytes of data
+    pub fn new(load_address: u16, block_no: u8, data: &[u8], is_first_block: bool) -> Option<Self> {
+        if data.is_empty() || data.len() > MAX_DATA_SIZE {
+            return None;
+        }
+        Some(Self {
+            header: BlockHeader {
+                load_address,
+                data_size: data.len(),
+                block_no,
+            },
+            data: data.to_vec(),
+            is_first_block,
+        })
+    }
+
+    /// Locates and validates the first block in `buffer`.
+    /// Returns the block and the number of bytes consumed from `buffer`.
+    pub fn load(buffer: &[u8]) -> Result<(Self, usize), NascomError> {
+        let offset = find_header(buffer).ok_or(NascomError::MissingHeader)?;
+        let is_first_block = has_pilot(buffer, offset);
+        let block = &buffer[offset..];
+
+        let header = BlockHeader::parse(block)?;
+        let data_end = HEADER_LENGTH + header.data_size;
+        if block.len() < data_end + 1 {
+            return Err(NascomError::IncompleteBlock(header.block_no));
+        }
+        let data = &block[HEADER_LENGTH..data_end];
+        let expected = block[data_end];
+        let calculated = checksum(data);
+        if expected != calculated {
+            return Err(NascomError::DataChecksum {
+                block_no: header.block_no,
+                expected,
+                calculated,
+            });
+        }
+
+        let consumed = usize::min(block.len(), data_end + 1 + TRAILER_LENGTH);
+        Ok((
+            Self {
+                header,
+                data: data.to_vec(),
+                is_first_block,
+            },
+            offset + consumed,
+        ))
+    }
+
+    pub fn checksum(&self) -> u8 {
+        checksum(&self.data)
+    }
+
+    /// The block as it is written to tape, including the trailing nulls
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH + self.data.len() + 1 + TRAILER_LENGTH);
+        bytes.extend_from_slice(&self.header.to_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes.push(self.checksum());
+        bytes.extend_from_slice(&[0u8; TRAILER_LENGTH]);
+        bytes
+    }
+}
+
+impl Display for TapeBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "address={:#06x}, block no={}, data size={}, raw size={}, checksum={:#04x}",
+            self.header.load_address,
+            self.header.block_no,
+            self.data.len(),
+            HEADER_LENGTH + self.data.len() + 1 + TRAILER_LENGTH,
+            self.checksum()
+        )
+    }
+}
+
+/// Reads consecutive valid blocks from `buffer`, stopping at the first invalid block.
+/// The error that stopped the reading is returned along with the blocks,
+/// a missing header after the last block is not considered an error.
+pub fn read_blocks(buffer: &[u8]) -> (Vec<TapeBlock>, Option<NascomError>) {
+    let mut blocks = vec![];
+    let mut offset = 0;
+    loop {
+        match TapeBlock::load(&buffer[offset..]) {
+            Ok((block, consumed)) => {
+                blocks.push(block);
+                offset += consumed;
+            }
+            Err(NascomError::MissingHeader) => return (blocks, None),
+            Err(error) => return (blocks, Some(error)),
+        }
+    }
+}
+
+/// Finds every valid block in `buffer`, skipping over damaged blocks
+pub fn scan_blocks(buffer: &[u8]) -> Vec<TapeBlock> {
+    let mut blocks = vec![];
+    let mut offset = 0;
+    while let Some(header_offset) = find_header(&buffer[offset..]) {
+        match TapeBlock::load(&buffer[offset..]) {
+            Ok((block, consumed)) => {
+                blocks.push(block);
+                offset += consumed;
+            }
+            Err(_) => offset += header_offset + 1,
+        }
+    }
+    blocks
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Assembly {
+    /// Blocks in tape order, tagged with the index of the source they were taken from
+    pub blocks: Vec<(usize, TapeBlock)>,
+    /// Block numbers that were not found in any of the sources
+    pub missing: Vec<u8>,
+}
+
+impl Assembly {
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+            && self.has_first_block()
+            && self
+                .blocks
+                .last()
+                .is_some_and(|(_, block)| block.header.block_no == 0)
+    }
+
+    pub fn has_first_block(&self) -> bool {
+        self.blocks
+            .first()
+            .is_some_and(|(_, block)| block.is_first_block)
+    }
+}
+
+/// Collects the valid blocks from several partial dumps of the same program.
+///
+/// The blocks are indexed by block number and load address. The first block (the one following the pilot tone)
+/// gives the number of blocks in the program, if it was not found the highest block number seen is used instead.
+/// If different blocks share a block number, the one with a load address following on from the previous block is used.
+pub fn assemble(sources: &[&[u8]]) -> Assembly {
+    let mut found: BTreeMap<(u8, u16), (usize, TapeBlock)> = BTreeMap::new();
+    for (source_idx, source) in sources.iter().enumerate() {
+        for block in scan_blocks(source) {
+            let key = (block.header.block_no, block.header.load_address);
+            let is_first_block = block.is_first_block;
+            found
+                .entry(key)
+                .or_insert((source_idx, block))
+                .1
+                .is_first_block |= is_first_block;
+        }
+    }
+
+    let first_block_no = found
+        .values()
+        .filter(|(_, block)| block.is_first_block)
+        .map(|(_, block)| block.header.block_no)
+        .max()
+        .or(found.keys().map(|(block_no, _)| *block_no).max());
+
+    let mut assembly = Assembly::default();
+    let mut previous: Option<&TapeBlock> = None;
+    for block_no in (0..=first_block_no.unwrap_or(0)).rev() {
+        let expected_address = previous.map(|block| {
+            block
+                .header
+                .load_address
+                .wrapping_add(block.data.len() as u16)
+        });
+        let mut candidates = found.range((block_no, 0)..=(block_no, u16::MAX));
+        let candidate = candidates
+            .clone()
+            .find(|((_, address), _)| Some(*address) == expected_address)
+            .or(candidates.next());
+        match candidate {
+            Some((_, (source_idx, block))) => {
+                let mut block = block.clone();
+                block.is_first_block &= Some(block_no) == first_block_no;
+                assembly.blocks.push((*source_idx, block));
+                previous = candidate.map(|(_, (_, block))| block);
+            }
+            None if first_block_no.is_some() => {
+                assembly.missing.push(block_no);
+                previous = None;
+            }
+            None => {}
+        }
+    }
+    assembly
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockEvent {
+    /// A block that passed its checksums
+    Block(TapeBlock),
+    /// A damaged block, with its header if that could be read
+    BadBlock(Option<BlockHeader>, NascomError),
+}
+
+/// Block framer
+///
+/// Transforms from decoded bytes (or decoder errors) to NASCOM blocks.
+/// Bytes within a block are kept together, and a decoder error only marks the block it occurred in as bad.
+/// After a block is completed or damaged, it searches for the next block header.
+/// The output is tagged with the sample index of the first byte of the block.
+#[derive(Debug, Clone, Default)]
+pub struct BlockFramer {
+    recent: VecDeque<(usize, u8)>,
+    zero_run: usize,
+    zeros_before_header: usize,
+    block: Vec<u8>,
+    block_idx: usize,
+    is_first_block: bool,
+    header: Option<BlockHeader>,
+}
+
+impl BlockFramer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process(
+        &mut self,
+        input: (usize, Result<u8, DecoderError>),
+    ) -> Option<(usize, BlockEvent)> {
+        let (sample_index, val) = input;
+        let val = match val {
+            Ok(val) => val,
+            Err(error) => {
+                self.recent.clear();
+                self.zero_run = 0;
+                return self.abort(NascomError::Decoder(error));
+            }
+        };
+        if !self.block.is_empty() {
+            return self.receive(val);
+        }
+
+        // Searching for a header, keep track of the preceding zeros to identify the pilot tone
+        if val == 0 {
+            self.zero_run += 1;
+        } else if self.zero_run > 0 {
+            self.zeros_before_header = self.zero_run;
+            self.zero_run = 0;
+        }
+        self.recent.push_back((sample_index, val));
+        if self.recent.len() > HEADER_MAGIC.len() {
+            self.recent.pop_front();
+        }
+        if self.recent.iter().map(|(_, val)| *val).eq(HEADER_MAGIC) {
+            self.block_idx = self.recent[0].0;
+            self.block = HEADER_MAGIC.to_vec();
+            // The first magic byte is also a zero
+            self.is_first_block = self.zeros_before_header > PILOT_LENGTH;
+            self.recent.clear();
+        }
+        None
+    }
+
+    /// Reports a block that was still being received when the input ended
+    pub fn finish(&mut self) -> Option<(usize, BlockEvent)> {
+        let error = match self.header {
+            Some(header) => NascomError::IncompleteBlock(header.block_no),
+            None => NascomError::IncompleteHeader,
+        };
+        self.abort(error)
+    }
+
+    fn receive(&mut self, val: u8) -> Option<(usize, BlockEvent)> {
+        self.block.push(val);
+        if self.block.len() == HEADER_LENGTH {
+            match BlockHeader::parse(&self.block) {
+                Ok(header) => self.header = Some(header),
+                Err(error) => return self.abort(error),
+            }
+        }
+        match self.header {
+            Some(header) if self.block.len() == HEADER_LENGTH + header.data_size + 1 => {
+                let block = std::mem::take(&mut self.block);
+                self.header = None;
+                let event = match TapeBlock::load(&block) {
+                    Ok((mut block, _)) => {
+                        block.is_first_block = self.is_first_block;
+                        BlockEvent::Block(block)
+                    }
+                    Err(error) => BlockEvent::BadBlock(Some(header), error),
+                };
+                Some((self.block_idx, event))
+            }
+            _ => None,
+        }
+    }
+
+    fn abort(&mut self, error: NascomError) -> Option<(usize, BlockEvent)> {
+        if self.block.is_empty() {
+            return None;
+        }
+        self.block.clear();
+        Some((
+            self.block_idx,
+            BlockEvent::BadBlock(self.header.take(), error),
+        ))
+    }
+}
+
+/// True if the blocks start with the first block of a program and count down to the last block without gaps
+pub fn is_complete(blocks: &[TapeBlock]) -> bool {
+    match blocks.first() {
+        Some(first) => {
+            first.is_first_block
+                && blocks
+                    .iter()
+                    .map(|block| block.header.block_no)
+                    .eq((0..=first.header.block_no).rev())
+        }
+        None => false,
+    }
+}
+
+/// Block numbers counting down from the first of `blocks` to 0 that are not in `blocks`, as in `assemble`
+pub fn missing_blocks(blocks: &[TapeBlock]) -> Vec<u8> {
+    let first_block_no = match blocks.first() {
+        Some(first) => first.header.block_no,
+        None => return vec![],
+    };
+    (0..=first_block_no)
+        .rev()
+        .filter(|block_no| {
+            !blocks
+                .iter()
+                .any(|block| block.header.block_no == *block_no)
+        })
+        .collect()
+}
+
+/// Splits a program into blocks of up to 256 bytes loaded at consecutive addresses starting at `load_address`.
+/// The blocks are numbered down to 0 for the last block, as written by the NAS-SYS WRITE command.
+/// Fails if the program needs more than `MAX_NUM_BLOCKS` blocks.
+pub fn split_blocks(data: &[u8], load_address: u16) -> Result<Vec<TapeBlock>, NascomError> {
+    let num_blocks = data.chunks(MAX_DATA_SIZE).len();
+    if num_blocks > MAX_NUM_BLOCKS {
+        return Err(NascomError::TooManyBlocks(num_blocks));
+    }
+    Ok(data
+        .chunks(MAX_DATA_SIZE)
+        .enumerate()
+        .filter_map(|(idx, chunk)| {
+            TapeBlock::new(
+                load_address.wrapping_add((idx * MAX_DATA_SIZE) as u16),
+                (num_blocks - 1 - idx) as u8,
+                chunk,
+                idx == 0,
+            )
+        })
+        .collect())
+}
+
+/// Writes the blocks in .cas format, preceded by the pilot tone if the first block is the start of a program
+pub fn write_cas(blocks: &[TapeBlock], writer: &mut impl Write) -> std::io::Result<()> {
+    if blocks.first().is_some_and(|block| block.is_first_block) {
+        writer.write_all(&[0u8; PILOT_LENGTH])?;
+    }
+    for block in blocks {
+        writer.write_all(&block.to_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_tape() -> Vec<u8> {
+        let blocks = [
+            TapeBlock::new(0x1000, 1, &[0xAA; 256], true).unwrap(),
+            TapeBlock::new(0x1100, 0, &[1, 2, 3], false).unwrap(),
+        ];
+        let mut tape = vec![];
+        write_cas(&blocks, &mut tape).unwrap();
+        tape
+    }
+
+    #[test]
+    fn nascom_header_roundtrip() {
+        let header = BlockHeader {
+            load_address: 0x1234,
+            data_size: 256,
+            block_no: 7,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(bytes, [0, 0xFF, 0xFF, 0xFF, 0xFF, 0x34, 0x12, 0, 7, 0x4D]);
+        assert_eq!(BlockHeader::parse(&bytes), Ok(header));
+    }
+
+    #[test]
+    fn nascom_read_blocks_complete() {
+        let mut tape = vec![0x55, 0x12];
+        tape.extend(test_tape());
+        let (blocks, error) = read_blocks(&tape);
+
+        assert_eq!(error, None);
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[0].is_first_block);
+        assert_eq!(blocks[0].header.load_address, 0x1000);
+        assert_eq!(blocks[1].data, vec![1, 2, 3]);
+        assert!(is_complete(&blocks));
+
+        let mut cleaned = vec![];
+        write_cas(&blocks, &mut cleaned).unwrap();
+        assert_eq!(cleaned, test_tape());
+    }
+
+    #[test]
+    fn nascom_missing_blocks() {
+        let data = (0..1000).map(|n| n as u8).collect::<Vec<_>>();
+        let mut blocks = split_blocks(&data, 0x1000).unwrap();
+        assert!(is_complete(&blocks));
+        assert_eq!(missing_blocks(&blocks), vec![]);
+
+        // A damaged block in the middle
+        blocks.remove(1);
+        assert!(!is_complete(&blocks));
+        assert_eq!(missing_blocks(&blocks), vec![2]);
+
+        // And the end of the program
+        blocks.pop();
+        assert!(!is_complete(&blocks));
+        assert_eq!(missing_blocks(&blocks), vec![2, 0]);
+        assert_eq!(missing_blocks(&[]), vec![]);
+    }
+
+    #[test]
+    fn nascom_split_blocks() {
+        let data = (0..600).map(|n| n as u8).collect::<Vec<_>>();
+        let blocks = split_blocks(&data, 0x1000).unwrap();
+
+        assert_eq!(
+            blocks
+                .iter()
+                .map(|block| (
+                    block.header.load_address,
+                    block.header.block_no,
+                    block.data.len()
+                ))
+                .collect::<Vec<_>>(),
+            vec![(0x1000, 2, 256), (0x1100, 1, 256), (0x1200, 0, 88)]
+        );
+        assert!(blocks[0].is_first_block);
+        assert!(is_complete(&blocks));
+
+        // Block numbers would wrap around
+        assert!(split_blocks(&[0; MAX_NUM_BLOCKS * MAX_DATA_SIZE], 0).is_ok());
+        assert_eq!(
+            split_blocks(&[0; MAX_NUM_BLOCKS * MAX_DATA_SIZE + 1], 0),
+            Err(NascomError::TooManyBlocks(MAX_NUM_BLOCKS + 1))
+        );
+    }
+
+    #[test]
+    fn nascom_read_blocks_checksum_error() {
+        let mut tape = test_tape();
+        // Corrupt the first data byte of the second block
+        let offset = PILOT_LENGTH + HEADER_LENGTH + 256 + 1 + TRAILER_LENGTH + HEADER_LENGTH;
+        tape[offset] ^= 0x01;
+        let (blocks, error) = read_blocks(&tape);
+
+        assert_eq!(blocks.len(), 1);
+        assert!(!is_complete(&blocks));
+        assert_eq!(
+            error,
+            Some(NascomError::DataChecksum {
+                block_no: 0,
+                expected: 6,
+                calculated: 5
+            })
+        );
+    }
+
+    #[test]
+    fn nascom_assemble_from_damaged_sources() {
+        let data = (0..=255u8).collect::<Vec<_>>();
+        let blocks = (0..4u8)
+            .map(|n| TapeBlock::new(0x1000 + n as u16 * 0x100, 3 - n, &data, n == 0).unwrap())
+            .collect::<Vec<_>>();
+        let mut tape = vec![];
+        write_cas(&blocks, &mut tape).unwrap();
+        let block_length = HEADER_LENGTH + 256 + 1 + TRAILER_LENGTH;
+
+        // Damage block 2 in the first source, block 0 in the second, and cut the pilot from the second
+        let mut source1 = tape.clone();
+        source1[PILOT_LENGTH + block_length + HEADER_LENGTH + 10] ^= 0xFF;
+        let mut source2 = tape[PILOT_LENGTH..].to_vec();
+        source2[3 * block_length + HEADER_LENGTH + 10] ^= 0xFF;
+
+        assert_eq!(scan_blocks(&source1).len(), 3);
+        assert_eq!(scan_blocks(&source2).len(), 3);
+
+        let assembly = assemble(&[&source2, &source1]);
+        assert!(assembly.is_complete());
+        assert_eq!(
+            assembly
+                .blocks
+                .iter()
+                .map(|(source_idx, block)| (*source_idx, block.header.block_no))
+                .collect::<Vec<_>>(),
+            vec![(0, 3), (0, 2), (0, 1), (1, 0)]
+        );
+
+        let mut output = vec![];
+        write_cas(
+            &assembly
+                .blocks
+                .into_iter()
+                .map(|(_, block)| block)
+                .collect::<Vec<_>>(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output, tape);
+
+        // Without the first source, block 0 is missing
+        let assembly = assemble(&[&source2]);
+        assert!(!assembly.is_complete());
+        assert_eq!(assembly.missing, vec![0]);
+    }
+
+    #[test]
+    fn nascom_block_framer_resyncs_after_error() {
+        let data = (0..=255u8).collect::<Vec<_>>();
+        let blocks = (0..3u8)
+            .map(|n| TapeBlock::new(0x1000 + n as u16 * 0x100, 2 - n, &data, n == 0).unwrap())
+            .collect::<Vec<_>>();
+        let mut tape = vec![];
+        write_cas(&blocks, &mut tape).unwrap();
+
+        // A decoder error in the middle of the second block
+        let error_idx = PILOT_LENGTH + (HEADER_LENGTH + 256 + 1 + TRAILER_LENGTH) + 100;
+        let mut framer = BlockFramer::new();
+        let mut events = tape
+            .iter()
+            .enumerate()
+            .map(|(idx, &val)| match idx {
+                idx if idx == error_idx => (idx, Err(DecoderError::Sync)),
+                idx => (idx, Ok(val)),
+            })
+            .filter_map(|val| framer.process(val))
+            .collect::<Vec<_>>();
+        events.extend(framer.finish());
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0],
+            (PILOT_LENGTH, BlockEvent::Block(blocks[0].clone()))
+        );
+        assert_eq!(
+            events[1],
+            (
+                PILOT_LENGTH + 277,
+                BlockEvent::BadBlock(
+                    Some(blocks[1].header),
+                    NascomError::Decoder(DecoderError::Sync)
+                )
+            )
+        );
+        assert_eq!(
+            events[2],
+            (PILOT_LENGTH + 2 * 277, BlockEvent::Block(blocks[2].clone()))
+        );
+    }
+}
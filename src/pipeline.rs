@@ -0,0 +1,690 @@
+use crate::resample::SampleQueue;
+use crate::*;
+use std::collections::VecDeque;
+
+/// Number of consecutive valid bits before the carrier is reported as found
+const CARRIER_MIN_BITS: usize = 8;
+/// Number of bit lengths without a valid bit before the carrier is reported as lost
+const CARRIER_TIMEOUT_BITS: usize = 4;
+
+/// Stages that transform samples to bits, tagged with their sample index
+pub type Demodulator =
+    Box<dyn Stage<Input = (usize, f32), Output = (usize, SignalCondition)> + Send>;
+
+/// Bit with its sample index and confidence
+type Bit = (usize, SignalCondition, f32);
+
+/// Event reported by `KcsDecoder`
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecoderEvent {
+    /// A byte (a word of the configured data bits) was decoded, with its quality from 0.0 to 1.0.
+    /// The quality is the least confidence of the bits of its frame, 1.0 if the demodulator does not measure it.
+    Byte(Word, f32),
+    /// A byte was decoded from a frame with an error in salvage mode, see `Decoder`. The value is a best guess,
+    /// with the quality and the first error of the frame
+    SuspectByte(Word, f32, DecoderError),
+    /// A frame could not be decoded, the decoder waits for the next start bit.
+    /// With `config.resync`, the bytes after a sync error are reported once the frame alignment has been found again.
+    FramingError(DecoderError),
+    /// A tone of the configured symbol frequencies was recognized
+    CarrierFound,
+    /// The tone stopped or can no longer be recognized
+    CarrierLost,
+}
+
+/// Streaming decoder transforms from samples -> events
+///
+/// Runs the complete chain of resampling, `PreFilter` and `Agc` (if the configuration asks for them), `ZeroCrossingDetector`,
+/// `FrequencyIdentifier`, `HiLowIdentifier` (or `BitClockRecovery`) and `Decoder`, or the `QuadratureDemodulator` instead of the zero crossing stages
+/// if `config.demodulation` selects it. The zero crossing direction is not used by the quadrature demodulator.
+/// The stages up to the bits can be replaced by a custom `Demodulator` with `with_demodulator`.
+/// Samples are pushed in blocks of any length and the events found so far are returned with their sample position.
+/// Positions count samples at `sample_rate()`, which is the resampled rate if the input is resampled.
+///
+/// ```
+/// use kcs_decoder::*;
+///
+/// let config = DecoderConfig::default();
+/// let encoder_config = EncoderConfig {
+///     sample_rate: 48000,
+///     ..Default::default()
+/// };
+/// let samples = Encoder::new(config, encoder_config).unwrap().encode(b"KCS");
+///
+/// let mut decoder = KcsDecoder::new(config, 48000, ZeroCrossingDirection::Pos).unwrap();
+/// let mut bytes = vec![];
+/// for block in samples.chunks(1024) {
+///     decoder.process_with(block, |_idx, event| {
+///         if let DecoderEvent::Byte(val, _quality) = event {
+///             bytes.push(val);
+///         }
+///     });
+/// }
+/// let events = decoder.finish();
+/// assert_eq!(config.words_to_bytes(&bytes), b"KCS");
+/// assert_eq!(events.last().unwrap().1, DecoderEvent::CarrierLost);
+/// ```
+pub struct KcsDecoder {
+    resampler: Option<Resampler<SampleQueue>>,
+    sample_rate: u32,
+    pre_filter: PreFilter,
+    agc: Agc,
+    hysteresis: Hysteresis,
+    level_summary: LevelSummary,
+    speed_summary: SpeedSummary,
+    demodulator: Demodulator,
+    config: DecoderConfig,
+    decoder: Decoder,
+    /// The bits given to the decoder since the last frame with their confidence, at most as many as there are in a frame
+    recent: VecDeque<Bit>,
+    frame_bits: usize,
+    /// Bits collected while searching for the frame alignment after a sync error,
+    /// and how many of them are from the failed frame
+    search: Option<(Vec<Bit>, usize)>,
+    num_samples: usize,
+    last_bit_idx: usize,
+    last_valid_bit_idx: usize,
+    valid_bits: usize,
+    carrier: bool,
+    carrier_timeout: usize,
+}
+
+impl KcsDecoder {
+    pub fn new(
+        config: DecoderConfig,
+        input_sample_rate: u32,
+        zc_direction: ZeroCrossingDirection,
+    ) -> Option<Self> {
+        let sample_rate = config
+            .resample_rate(input_sample_rate)
+            .unwrap_or(input_sample_rate);
+        if config.demodulation == Demodulation::Quadrature {
+            let demodulator = QuadratureDemodulator::new(&config, sample_rate);
+            return Self::with_demodulator(config, input_sample_rate, Box::new(demodulator));
+        }
+        let zc_detector = match config.hysteresis {
+            Hysteresis::Auto => {
+                ZeroCrossingDetector::with_adaptive_hysteresis(config.interpolation)
+            }
+            Hysteresis::Percent(percent) => ZeroCrossingDetector::with_interpolation(
+                percent as f32 / 100.0,
+                config.interpolation,
+            ),
+        };
+        let half_periods = config.half_period_symbols();
+        let frequency_identifier = match half_periods {
+            true => FrequencyIdentifier::with_half_periods(sample_rate),
+            false => FrequencyIdentifier::new(zc_direction, sample_rate),
+        };
+        let frequencies = zc_detector.then(frequency_identifier);
+        let demodulator: Demodulator = match config.clock_recovery {
+            ClockRecovery::Periods => {
+                let tracker = FrequencyTracker::new(
+                    config.symbols.map(|symbol| symbol.frequency as u32),
+                    config.frequency_tolerance,
+                    config.track_speed,
+                )?;
+                Box::new(frequencies.then(HiLowIdentifier::with_tracker(
+                    tracker,
+                    (
+                        config.symbols[1].count(half_periods) as u8,
+                        config.symbols[1].signal,
+                    ),
+                    (
+                        config.symbols[0].count(half_periods) as u8,
+                        config.symbols[0].signal,
+                    ),
+                )?))
+            }
+            ClockRecovery::Pll => {
+                Box::new(frequencies.then(BitClockRecovery::new(&config, sample_rate)?))
+            }
+        };
+        Self::with_demodulator(config, input_sample_rate, demodulator)
+    }
+
+    /// Decoder using a custom demodulator.
+    /// The demodulator gets the samples after resampling, pre-filtering and AGC, at `config.resample_rate(input_sample_rate)` if that is set.
+    pub fn with_demodulator(
+        config: DecoderConfig,
+        input_sample_rate: u32,
+        demodulator: Demodulator,
+    ) -> Option<Self> {
+        if input_sample_rate == 0 {
+            return None;
+        }
+        let resample_rate = config.resample_rate(input_sample_rate);
+        let sample_rate = resample_rate.unwrap_or(input_sample_rate);
+        let bit_length = config
+            .symbols
+            .iter()
+            .map(Symbol::length)
+            .fold(0.0, f64::max)
+            * sample_rate as f64;
+
+        Some(Self {
+            resampler: resample_rate.map(|rate| Resampler::streaming(input_sample_rate, rate)),
+            sample_rate,
+            pre_filter: PreFilter::new(&config, sample_rate),
+            agc: Agc::new(sample_rate, config.agc),
+            hysteresis: config.hysteresis,
+            level_summary: LevelSummary::default(),
+            speed_summary: SpeedSummary::new(sample_rate),
+            demodulator,
+            config,
+            decoder: Decoder::new(config)?,
+            recent: VecDeque::new(),
+            frame_bits: config.frame_bits(),
+            search: None,
+            num_samples: 0,
+            last_bit_idx: 0,
+            last_valid_bit_idx: 0,
+            valid_bits: 0,
+            carrier: false,
+            carrier_timeout: (bit_length * CARRIER_TIMEOUT_BITS as f64).round() as usize,
+        })
+    }
+
+    /// Sample rate of the reported sample positions
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Range of the signal levels, gain and hysteresis so far.
+    /// The hysteresis is the one the configuration asks for, a custom demodulator may use a different one.
+    pub fn level_summary(&self) -> LevelSummary {
+        self.level_summary
+    }
+
+    /// Tape speed measured by the demodulator at the valid bits while there is a carrier, over time
+    pub fn speed_summary(&self) -> &SpeedSummary {
+        &self.speed_summary
+    }
+
+    /// Decodes a block of samples and returns the events found in it
+    pub fn process(&mut self, samples: &[f32]) -> Vec<(usize, DecoderEvent)> {
+        let mut events = vec![];
+        self.process_with(samples, |idx, event| events.push((idx, event)));
+        events
+    }
+
+    /// Decodes a block of samples and passes each event to `sink` as it is found
+    pub fn process_with(&mut self, samples: &[f32], mut sink: impl FnMut(usize, DecoderEvent)) {
+        let resampled = self
+            .resampler
+            .as_mut()
+            .map(|resampler| resampler.process(samples));
+        self.demodulate(resampled.as_deref().unwrap_or(samples), &mut sink);
+    }
+
+    /// Decodes what is left at the end of the input and returns the events
+    pub fn finish(&mut self) -> Vec<(usize, DecoderEvent)> {
+        let mut events = vec![];
+        self.finish_with(|idx, event| events.push((idx, event)));
+        events
+    }
+
+    /// Same as `finish`, passing each event to `sink`
+    pub fn finish_with(&mut self, mut sink: impl FnMut(usize, DecoderEvent)) {
+        if let Some(samples) = self.resampler.as_mut().map(|resampler| resampler.finish()) {
+            self.demodulate(&samples, &mut sink);
+        }
+        for (idx, level) in self.demodulator.flush() {
+            self.process_bit(idx, level, &mut sink);
+        }
+        while self.search.is_some() {
+            self.end_search(&mut sink);
+        }
+        self.pre_filter.reset();
+        self.agc.reset();
+        self.demodulator.reset();
+        // To make sure we clock out the last data byte
+        let result = self.decoder.process(SignalCondition::Mark);
+        if let Some(event @ (DecoderEvent::Byte(..) | DecoderEvent::SuspectByte(..))) =
+            self.frame_event(result)
+        {
+            sink(self.last_bit_idx, event);
+        }
+        self.decoder.reset();
+        self.recent.clear();
+        if self.carrier {
+            self.carrier = false;
+            sink(self.num_samples, DecoderEvent::CarrierLost);
+        }
+    }
+
+    fn demodulate(&mut self, samples: &[f32], sink: &mut impl FnMut(usize, DecoderEvent)) {
+        for &sample in samples {
+            let idx = self.num_samples;
+            self.num_samples += 1;
+            let Some(val) = self
+                .pre_filter
+                .process((idx, sample))
+                .and_then(|val| self.agc.process(val))
+            else {
+                continue;
+            };
+            let levels = self.agc.levels();
+            let hysteresis = match self.hysteresis {
+                Hysteresis::Auto => levels.hysteresis(),
+                Hysteresis::Percent(percent) => percent as f32 / 100.0,
+            };
+            if self.agc.noise_floor_measured() {
+                self.level_summary.update(&levels, hysteresis);
+            }
+            self.demodulator.adapt(&levels);
+            match self.demodulator.process(val) {
+                Some((idx, level)) => self.process_bit(idx, level, sink),
+                None => self.check_carrier(idx, sink),
+            }
+        }
+    }
+
+    fn process_bit(
+        &mut self,
+        idx: usize,
+        level: SignalCondition,
+        sink: &mut impl FnMut(usize, DecoderEvent),
+    ) {
+        self.last_bit_idx = idx;
+        let confidence = match level {
+            SignalCondition::Error => 0.0,
+            _ => self.demodulator.confidence().unwrap_or(1.0),
+        };
+        if level == SignalCondition::Error {
+            self.valid_bits = 0;
+            self.check_carrier(idx, sink);
+        } else {
+            if idx > self.last_valid_bit_idx + self.carrier_timeout {
+                self.valid_bits = 0;
+            }
+            self.valid_bits += 1;
+            self.last_valid_bit_idx = idx;
+            if !self.carrier && self.valid_bits >= CARRIER_MIN_BITS {
+                self.carrier = true;
+                sink(idx, DecoderEvent::CarrierFound);
+            }
+            if let Some(speed) = self.demodulator.speed().filter(|_| self.carrier) {
+                self.speed_summary.update(idx, speed);
+            }
+        }
+
+        self.decode_bit((idx, level, confidence), sink);
+    }
+
+    /// Feeds a bit with its confidence to the decoder, or to the search for the frame alignment after a sync error
+    fn decode_bit(&mut self, bit: Bit, sink: &mut impl FnMut(usize, DecoderEvent)) {
+        if let Some((bits, _)) = &mut self.search {
+            bits.push(bit);
+            if bits.len() >= (self.config.resync + 1) * self.frame_bits {
+                self.end_search(sink);
+            }
+            return;
+        }
+        if self.recent.len() == self.frame_bits {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(bit);
+        let result = self.decoder.process(bit.1);
+        let resync = self.config.resync > 0 && result == Err(Some(DecoderError::Sync));
+        // A sync error is reported like any other, so a salvaged byte is kept before the search
+        if let Some(event) = self.frame_event(result) {
+            sink(bit.0, event);
+            if !resync {
+                self.recent.clear();
+            }
+        }
+        if resync {
+            // The next frame may start anywhere after the start bit of the failed frame
+            let start = self
+                .recent
+                .iter()
+                .position(|bit| bit.1 == self.config.startbits.1)
+                .map_or(0, |position| position + 1);
+            let bits = self.recent.drain(..).skip(start).collect::<Vec<_>>();
+            let failed = bits.len();
+            self.search = Some((bits, failed));
+        }
+    }
+
+    /// Decodes the bits collected after a sync error again, from the frame alignment found in them.
+    /// Without an alignment, decoding continues after the failed frame.
+    fn end_search(&mut self, sink: &mut impl FnMut(usize, DecoderEvent)) {
+        let Some((bits, failed)) = self.search.take() else {
+            return;
+        };
+        let levels = bits.iter().map(|bit| bit.1).collect::<Vec<_>>();
+        let frames = self.config.resync.min(bits.len() / self.frame_bits).max(1);
+        let start = find_alignment(&self.config, &levels, frames).unwrap_or(failed);
+        self.decoder.reset();
+        for bit in &bits[start..] {
+            self.decode_bit(*bit, sink);
+        }
+    }
+
+    /// Event for the result of the decoder, if it completed a frame
+    fn frame_event(&self, result: Result<Word, Option<DecoderError>>) -> Option<DecoderEvent> {
+        match result {
+            Ok(val) => Some(DecoderEvent::Byte(val, self.quality())),
+            Err(Some(error)) => Some(match self.decoder.salvaged() {
+                Some(val) => DecoderEvent::SuspectByte(val, self.quality(), error),
+                None => DecoderEvent::FramingError(error),
+            }),
+            Err(None) => None,
+        }
+    }
+
+    /// Least confidence of the bits of the last frame
+    fn quality(&self) -> f32 {
+        self.recent.iter().map(|bit| bit.2).fold(1.0, f32::min)
+    }
+
+    fn check_carrier(&mut self, idx: usize, sink: &mut impl FnMut(usize, DecoderEvent)) {
+        if self.carrier && idx > self.last_valid_bit_idx + self.carrier_timeout {
+            self.end_search(sink);
+            self.carrier = false;
+            self.valid_bits = 0;
+            sink(idx, DecoderEvent::CarrierLost);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(config: DecoderConfig, data: &[u8], sample_rate: u32) -> Vec<f32> {
+        let encoder_config = EncoderConfig {
+            sample_rate,
+            leader_length: 0.1,
+            trailer_length: 0.1,
+            ..Default::default()
+        };
+        Encoder::new(config, encoder_config).unwrap().encode(data)
+    }
+
+    fn bytes(events: &[(usize, DecoderEvent)]) -> Vec<u8> {
+        events
+            .iter()
+            .filter_map(|(_, event)| match event {
+                DecoderEvent::Byte(val, _) => Some(*val as u8),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kcs_decoder_block_sizes() {
+        let config = DecoderConfig::default();
+        let data = b"Kansas City Standard \x00\xFF\x55\xAA";
+        let samples = encode(config, data, 48000);
+        for block_length in [1, 7, 1000, samples.len()] {
+            let mut decoder = KcsDecoder::new(config, 48000, ZeroCrossingDirection::Pos).unwrap();
+            let mut events = vec![];
+            for block in samples.chunks(block_length) {
+                events.extend(decoder.process(block));
+            }
+            events.extend(decoder.finish());
+            assert_eq!(bytes(&events), data, "{block_length}");
+        }
+    }
+
+    #[test]
+    fn kcs_decoder_resampled() {
+        // 16kHz is too low for the standard preset without interpolation, so it is resampled to 192kHz
+        let config = DecoderConfig::get_preset(&Preset::Std);
+        let data = b"KCS";
+        let mut decoder = KcsDecoder::new(config, 16000, ZeroCrossingDirection::Pos).unwrap();
+        assert_eq!(decoder.sample_rate(), 192000);
+
+        let mut events = decoder.process(&encode(config, data, 16000));
+        events.extend(decoder.finish());
+        assert_eq!(bytes(&events), data);
+    }
+
+    #[test]
+    fn kcs_decoder_carrier_events() {
+        let config = DecoderConfig {
+            resample: Resample::Off,
+            ..Default::default()
+        };
+        let tone = encode(config, b"A", 48000);
+        let silence = vec![0.0; 4800];
+        let input = [&silence[..], &tone, &silence, &tone, &silence].concat();
+
+        let mut decoder = KcsDecoder::new(config, 48000, ZeroCrossingDirection::Pos).unwrap();
+        let events = decoder.process(&input);
+        let carrier = events
+            .iter()
+            .filter(|(_, event)| {
+                matches!(
+                    event,
+                    DecoderEvent::CarrierFound | DecoderEvent::CarrierLost
+                )
+            })
+            .map(|(idx, event)| (*idx, event.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(carrier.len(), 4, "{carrier:?}");
+        assert_eq!(carrier[0].1, DecoderEvent::CarrierFound);
+        assert_eq!(carrier[1].1, DecoderEvent::CarrierLost);
+        assert_eq!(carrier[2].1, DecoderEvent::CarrierFound);
+        assert_eq!(carrier[3].1, DecoderEvent::CarrierLost);
+        // Lost within 4 bits of the end of the first tone, found within 8 bits of the start of the second
+        let bit_length = 40;
+        let end = silence.len() + tone.len();
+        assert!((end..end + 5 * bit_length).contains(&carrier[1].0));
+        let start = end + silence.len();
+        assert!((start..start + 9 * bit_length).contains(&carrier[2].0));
+        assert_eq!(bytes(&events), b"AA");
+    }
+
+    #[test]
+    fn kcs_decoder_custom_demodulator() {
+        let config = DecoderConfig {
+            resample: Resample::Off,
+            ..Default::default()
+        };
+        let samples = encode(config, b"KCS", 48000);
+        let num_bits = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = num_bits.clone();
+        let demodulator = ZeroCrossingDetector::new(0.0)
+            .then(FrequencyIdentifier::new(ZeroCrossingDirection::Pos, 48000))
+            .then(
+                HiLowIdentifier::new(
+                    2400,
+                    1200,
+                    10,
+                    (1, SignalCondition::Space),
+                    (2, SignalCondition::Mark),
+                )
+                .unwrap(),
+            )
+            .then(Probe::new(move |_: &(usize, SignalCondition)| {
+                counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }));
+
+        let mut decoder =
+            KcsDecoder::with_demodulator(config, 48000, Box::new(demodulator)).unwrap();
+        let mut events = decoder.process(&samples);
+        events.extend(decoder.finish());
+        assert_eq!(bytes(&events), b"KCS");
+        // 0.1 seconds of leader and trailer and 3 frames of 10 bits at 1200 baud
+        let num_bits = num_bits.load(std::sync::atomic::Ordering::Relaxed);
+        assert!((260..=270).contains(&num_bits), "{num_bits}");
+    }
+
+    #[test]
+    fn kcs_decoder_noise_between_blocks() {
+        let tone = encode(DecoderConfig::default(), b"A", 48000);
+        // Uniform noise at -40 dBFS
+        let mut source = crate::tests::noise();
+        let mut noise = |len: usize| {
+            source
+                .by_ref()
+                .take(len)
+                .map(|val| 0.01 * val)
+                .collect::<Vec<_>>()
+        };
+        // The noise at the start fills the noise floor window, the last block is 26 dB quieter
+        let quiet = tone.iter().map(|val| val * 0.05).collect::<Vec<_>>();
+        let input = [
+            &noise(120000)[..],
+            &tone,
+            &noise(24000),
+            &tone,
+            &noise(24000),
+            &quiet,
+        ]
+        .concat();
+
+        // Counting periods, so every crossing in the noise gives an error
+        let decode = |agc: bool, hysteresis: Hysteresis| {
+            let config = DecoderConfig {
+                resample: Resample::Off,
+                agc,
+                hysteresis,
+                ..Default::default()
+            };
+            let mut decoder = KcsDecoder::new(config, 48000, ZeroCrossingDirection::Pos).unwrap();
+            let mut events = decoder.process(&input);
+            events.extend(decoder.finish());
+            let errors = events
+                .iter()
+                .filter(|(idx, event)| {
+                    *idx > 120000 && matches!(event, DecoderEvent::FramingError(_))
+                })
+                .count();
+            (bytes(&events), errors)
+        };
+
+        // Without hysteresis, the noise between the blocks is decoded as bits
+        let (data, errors) = decode(false, Hysteresis::Percent(0));
+        assert_eq!(data, b"AAA");
+        assert!(errors > 1000, "{errors}");
+
+        let (data, errors) = decode(false, Hysteresis::Auto);
+        assert_eq!(data, b"AAA");
+        assert!(errors < 5, "{errors}");
+
+        // A fixed hysteresis is too high for the quiet block unless the level is normalized
+        assert_ne!(decode(false, Hysteresis::Percent(2)).0, b"AAA");
+        assert_eq!(decode(true, Hysteresis::Percent(2)).0, b"AAA");
+    }
+
+    #[test]
+    fn kcs_decoder_byte_quality() {
+        let data = b"Kansas City Standard";
+        let tone = encode(DecoderConfig::default(), data, 48000);
+        let noisy = tone
+            .iter()
+            .zip(crate::tests::noise())
+            .map(|(val, noise)| val + 0.2 * noise)
+            .collect::<Vec<_>>();
+        let qualities = |input: &[f32], config: DecoderConfig| {
+            let mut decoder = KcsDecoder::new(config, 48000, ZeroCrossingDirection::Pos).unwrap();
+            let mut events = decoder.process(input);
+            events.extend(decoder.finish());
+            events
+                .iter()
+                .filter_map(|(_, event)| match event {
+                    DecoderEvent::Byte(_, quality) => Some(*quality),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        for (clock_recovery, demodulation) in [
+            (ClockRecovery::Pll, Demodulation::ZeroCrossing),
+            (ClockRecovery::Periods, Demodulation::ZeroCrossing),
+            (ClockRecovery::Pll, Demodulation::Quadrature),
+        ] {
+            let config = DecoderConfig {
+                resample: Resample::Off,
+                clock_recovery,
+                demodulation,
+                interpolation: ZeroCrossingInterpolation::Linear,
+                ..Default::default()
+            };
+            let clean = qualities(&tone, config);
+            assert_eq!(clean.len(), data.len(), "{clock_recovery} {demodulation}");
+            assert!(clean.iter().all(|quality| *quality > 0.85), "{clean:?}");
+
+            // The noise lowers the quality of every byte that is still decoded
+            let noisy = qualities(&noisy, config);
+            let least = clean.iter().copied().fold(1.0, f32::min);
+            assert!(!noisy.is_empty());
+            assert!(noisy.iter().all(|quality| *quality < least), "{noisy:?}");
+        }
+    }
+
+    #[test]
+    fn kcs_decoder_resync() {
+        // Frames without gaps, the last stop bit of one of them is lost
+        let config = DecoderConfig {
+            resample: Resample::Off,
+            interpolation: ZeroCrossingInterpolation::Linear,
+            ..Default::default()
+        };
+        let data = (0..60u8)
+            .map(|val| val.wrapping_mul(73))
+            .collect::<Vec<_>>();
+        let mut encoder = Encoder::new(config, EncoderConfig::default()).unwrap();
+        let bit_length = encoder.encode_bit(SignalCondition::Mark).unwrap().len();
+        let mut samples = encoder.tone(0.1);
+        for (idx, val) in data.iter().enumerate() {
+            let frame = encoder.encode_word(Word::from(*val));
+            match idx == 20 {
+                true => samples.extend(&frame[..frame.len() - bit_length]),
+                false => samples.extend(frame),
+            }
+        }
+        samples.extend(encoder.tone(0.1));
+
+        let decode = |resync: usize| {
+            let config = DecoderConfig { resync, ..config };
+            let mut decoder = KcsDecoder::new(config, 44100, ZeroCrossingDirection::Pos).unwrap();
+            let mut events = decoder.process(&samples);
+            events.extend(decoder.finish());
+            events
+        };
+        // Waiting for the next start bit takes a data bit of the next frame as the start bit
+        let expected = [&data[..20], &data[21..]].concat();
+        assert_ne!(bytes(&decode(0)), expected);
+
+        // The frames after the damaged one are found again, with their bytes in order
+        let events = decode(4);
+        assert_eq!(bytes(&events), expected);
+        let errors = events
+            .iter()
+            .filter(|(_, event)| matches!(event, DecoderEvent::FramingError(_)))
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 1);
+        assert!(events.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+
+        // With salvage the damaged frame is kept as a suspect byte, its data bits are intact
+        let config = DecoderConfig {
+            salvage: true,
+            ..config
+        };
+        let mut decoder = KcsDecoder::new(
+            DecoderConfig {
+                resync: 4,
+                ..config
+            },
+            44100,
+            ZeroCrossingDirection::Pos,
+        )
+        .unwrap();
+        let mut events = decoder.process(&samples);
+        events.extend(decoder.finish());
+        assert_eq!(bytes(&events), expected);
+        let suspect = events
+            .iter()
+            .filter_map(|(_, event)| match event {
+                DecoderEvent::SuspectByte(val, _, error) => Some((*val, error.clone())),
+                DecoderEvent::FramingError(_) => panic!("{event:?}"),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(suspect, vec![(Word::from(data[20]), DecoderError::Sync)]);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{DecoderConfig, SignalCondition, Stage, Symbol};
+use std::collections::VecDeque;
+
+/// Lowest tone power relative to the power of the input for a bit to be reported as mark or space
+const MIN_TONE_FRACTION: f64 = 0.3;
+/// Input power per sample below which the input is treated as silence, -100 dBFS
+const MIN_POWER: f64 = 1e-10;
+/// Largest correction of the bit clock at a change of tone, in bit lengths
+const MAX_CLOCK_CORRECTION: f64 = 0.5;
+
+/// I/Q mixer for one symbol frequency
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Tone {
+    signal: SignalCondition,
+    phase_increment: f64,
+    phase: f64,
+    /// Sum of the mixed samples over the window
+    sum: (f64, f64),
+}
+
+impl Tone {
+    /// Mixes a sample down and returns the product that is added to the window
+    fn mix(&mut self, sample: f64) -> (f64, f64) {
+        let product = (sample * self.phase.cos(), -sample * self.phase.sin());
+        self.phase = (self.phase + self.phase_increment) % std::f64::consts::TAU;
+        self.sum = (self.sum.0 + product.0, self.sum.1 + product.1);
+        product
+    }
+
+    /// Power at the tone frequency over the window, relative to the power of the input.
+    /// 1.0 for a pure tone filling the window, close to zero for other frequencies and noise.
+    fn fraction(&self, window_power: f64, window_length: usize) -> f64 {
+        let power = self.sum.0 * self.sum.0 + self.sum.1 * self.sum.1;
+        power / (window_power * window_length as f64 / 2.0)
+    }
+}
+
+/// Quadrature demodulator transforms from samples -> bits
+///
+/// Mixes the input with the mark and space frequencies and integrates each over a sliding window of one bit length
+/// (an I/Q mixer with a moving average low-pass, or a sliding Goertzel filter), so the decision is based on the
+/// energy of complete bits instead of single periods. The tone with the most energy decides the bit.
+/// The bits are sampled once per bit length by a clock that is adjusted whenever the tone changes, at the end of
+/// each bit when the window covers just that bit.
+/// A bit is reported as `SignalCondition::Error` if neither tone stands out from noise, silence gives no bits.
+/// The confidence in a bit is by how much the power of its tone exceeds the power of the other tone.
+///
+/// ```
+/// use kcs_decoder::*;
+///
+/// let config = DecoderConfig::default();
+/// let encoder_config = EncoderConfig {
+///     sample_rate: 48000,
+///     leader_length: 0.01,
+///     trailer_length: 0.01,
+///     ..Default::default()
+/// };
+/// let samples = Encoder::new(config, encoder_config).unwrap().encode(b"K");
+///
+/// let mut demodulator = QuadratureDemodulator::new(&config, 48000);
+/// let bits = samples
+///     .iter()
+///     .enumerate()
+///     .filter_map(|(idx, sample)| demodulator.process((idx, *sample)))
+///     .map(|(_, bit)| bit)
+///     .collect::<Vec<_>>();
+/// // Leader, start bit, 'K' (0x4B) with the least significant bit first, stop bit and trailer
+/// let (mark, space) = (SignalCondition::Mark, SignalCondition::Space);
+/// let frame = [space, mark, mark, space, mark, space, space, mark, space, mark];
+/// assert!(bits.windows(frame.len()).any(|window| window == frame));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuadratureDemodulator {
+    tones: [Tone; 2],
+    bit_length: f64,
+    /// Mixed samples of both tones and the power of each input sample in the window
+    window: VecDeque<[f64; 5]>,
+    window_length: usize,
+    window_power: f64,
+    /// Tone that was strongest at the previous sample
+    last_tone: Option<usize>,
+    last_bit: Option<f64>,
+    next_bit: f64,
+    num_samples: usize,
+    confidence: f32,
+}
+
+impl QuadratureDemodulator {
+    pub fn new(config: &DecoderConfig, sample_rate: u32) -> Self {
+        let bit_length = config
+            .symbols
+            .iter()
+            .map(Symbol::length)
+            .fold(0.0, f64::max)
+            * sample_rate as f64;
+        let window_length = (bit_length.round() as usize).max(1);
+        Self {
+            tones: config.symbols.map(|symbol| Tone {
+                signal: symbol.signal,
+                phase_increment: std::f64::consts::TAU * symbol.frequency as f64
+                    / sample_rate as f64,
+                phase: 0.0,
+                sum: (0.0, 0.0),
+            }),
+            bit_length,
+            window: VecDeque::with_capacity(window_length + 1),
+            window_length,
+            window_power: 0.0,
+            last_tone: None,
+            last_bit: None,
+            next_bit: 0.0,
+            num_samples: 0,
+            confidence: 1.0,
+        }
+    }
+
+    /// Updates the window with a sample, returns the index of the strongest tone and the fractions of the power of both
+    fn update(&mut self, sample: f32) -> Option<(usize, [f64; 2])> {
+        let sample = sample as f64;
+        let (i0, q0) = self.tones[0].mix(sample);
+        let (i1, q1) = self.tones[1].mix(sample);
+        self.window_power += sample * sample;
+        self.window.push_back([i0, q0, i1, q1, sample * sample]);
+        if self.window.len() > self.window_length {
+            let [i0, q0, i1, q1, power] = self.window.pop_front().unwrap();
+            self.tones[0].sum = (self.tones[0].sum.0 - i0, self.tones[0].sum.1 - q0);
+            self.tones[1].sum = (self.tones[1].sum.0 - i1, self.tones[1].sum.1 - q1);
+            self.window_power -= power;
+        }
+        if self.window_power < MIN_POWER * self.window_length as f64 {
+            return None;
+        }
+        let fractions = self
+            .tones
+            .map(|tone| tone.fraction(self.window_power, self.window_length));
+        match fractions[0] >= fractions[1] {
+            true => Some((0, fractions)),
+            false => Some((1, fractions)),
+        }
+    }
+}
+
+impl Stage for QuadratureDemodulator {
+    type Input = (usize, f32);
+    type Output = (usize, SignalCondition);
+
+    fn process(&mut self, input: Self::Input) -> Option<Self::Output> {
+        let (sample_index, sample) = input;
+        let position = self.num_samples as f64;
+        self.num_samples += 1;
+        let Some((tone, fractions)) = self.update(sample) else {
+            self.last_tone = None;
+            self.last_bit = None;
+            return None;
+        };
+        let last_bit = *self.last_bit.get_or_insert_with(|| {
+            self.next_bit = position + self.bit_length;
+            position
+        });
+
+        // Halfway into the window at a change of tone, so the bit ends half a bit length later
+        if self.last_tone.is_some_and(|last_tone| last_tone != tone) {
+            let max_correction = MAX_CLOCK_CORRECTION * self.bit_length;
+            self.next_bit = (position + self.bit_length / 2.0).clamp(
+                last_bit + self.bit_length - max_correction,
+                last_bit + self.bit_length + max_correction,
+            );
+        }
+        self.last_tone = Some(tone);
+
+        if position < self.next_bit {
+            return None;
+        }
+        self.last_bit = Some(self.next_bit);
+        self.next_bit += self.bit_length;
+        if fractions[tone] < MIN_TONE_FRACTION {
+            self.confidence = 0.0;
+            return Some((sample_index, SignalCondition::Error));
+        }
+        self.confidence = (fractions[tone] - fractions[1 - tone]).clamp(0.0, 1.0) as f32;
+        Some((sample_index, self.tones[tone].signal))
+    }
+
+    fn reset(&mut self) {
+        for tone in &mut self.tones {
+            tone.phase = 0.0;
+            tone.sum = (0.0, 0.0);
+        }
+        self.window.clear();
+        self.window_power = 0.0;
+        self.last_tone = None;
+        self.last_bit = None;
+        self.next_bit = 0.0;
+        self.num_samples = 0;
+        self.confidence = 1.0;
+    }
+
+    fn confidence(&self) -> Option<f32> {
+        Some(self.confidence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    fn decode(config: DecoderConfig, samples: &[f32], sample_rate: u32) -> Vec<u8> {
+        let mut stages =
+            QuadratureDemodulator::new(&config, sample_rate).then(Decoder::new(config).unwrap());
+        samples
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, sample)| stages.process((idx, *sample)))
+            .filter_map(|(_, val)| val.ok())
+            .map(|val| val as u8)
+            .collect()
+    }
+
+    #[test]
+    fn quadrature_demodulator_presets() {
+        let data = b"Kansas City Standard \x00\xFF\x55\xAA";
+        for preset in [
+            Preset::Std,
+            Preset::NASCOM,
+            Preset::MSX1200,
+            Preset::MSX2400,
+        ] {
+            let config = DecoderConfig::get_preset(&preset);
+            let encoder_config = EncoderConfig {
+                sample_rate: 22050,
+                ..Default::default()
+            };
+            let samples = Encoder::new(config, encoder_config).unwrap().encode(data);
+            assert_eq!(decode(config, &samples, 22050), data, "{preset}");
+            // The tape runs 3% fast
+            assert_eq!(decode(config, &samples, 22050 * 97 / 100), data, "{preset}");
+        }
+    }
+
+    #[test]
+    fn quadrature_demodulator_silence_and_noise() {
+        let config = DecoderConfig::default();
+        let mut demodulator = QuadratureDemodulator::new(&config, 48000);
+        assert!((0..4800).all(|idx| demodulator.process((idx, 0.0)).is_none()));
+
+        // Noise gives errors, at least one per one and a half bit lengths as the clock follows the changes of tone
+        let bits = crate::tests::noise()
+            .take(4800)
+            .enumerate()
+            .filter_map(|(idx, noise)| demodulator.process((idx, 0.5 * noise)))
+            .collect::<Vec<_>>();
+        let errors = bits
+            .iter()
+            .filter(|(_, bit)| *bit == SignalCondition::Error)
+            .count();
+        assert!((80..=120).contains(&bits.len()), "{}", bits.len());
+        assert!(errors > bits.len() * 9 / 10, "{errors}");
+    }
+}
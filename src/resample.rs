@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+
+/// Number of zero crossings of the sinc function on each side of the kernel centre
+const KERNEL_ZERO_CROSSINGS: usize = 12;
+/// Kernel table resolution, entries per input sample
+const KERNEL_PHASES: usize = 256;
+/// Shape parameter of the Kaiser window applied to the sinc kernel
+const KAISER_BETA: f64 = 8.0;
+/// Cut-off relative to the Nyquist frequency of the lower of the two sample rates
+const CUTOFF: f64 = 0.95;
+
+/// Band-limited sample rate converter
+///
+/// Converts a stream of samples from one sample rate to another using a Kaiser windowed sinc kernel.
+/// Works for both up- and downsampling with arbitrary ratios.
+///
+/// ```
+/// use kcs_decoder::Resampler;
+///
+/// let input = (0..100).map(|n| (n as f32 * 0.1).sin());
+/// let output = Resampler::new(input, 16000, 48000).collect::<Vec<_>>();
+/// assert_eq!(output.len(), 300);
+/// ```
+pub struct Resampler<I: Iterator<Item = f32>> {
+    input: I,
+    step: f64,
+    kernel: Vec<f32>,
+    half_width: usize,
+    buffer: VecDeque<f32>,
+    buffer_start: i64,
+    input_len: Option<i64>,
+    output_idx: u64,
+}
+
+impl<I: Iterator<Item = f32>> Resampler<I> {
+    pub fn new(input: I, input_sample_rate: u32, output_sample_rate: u32) -> Self {
+        let step = input_sample_rate as f64 / output_sample_rate as f64;
+        // When downsampling, the kernel is stretched to filter at the output Nyquist frequency
+        let cutoff = CUTOFF * f64::min(1.0, 1.0 / step);
+        let half_width = (KERNEL_ZERO_CROSSINGS as f64 / cutoff).ceil() as usize;
+
+        // One row of taps per phase, tap n is at distance phase + half_width - 1 - n from the output sample
+        let kernel = (0..=KERNEL_PHASES)
+            .flat_map(|phase| {
+                (0..2 * half_width).map(move |tap| {
+                    let x =
+                        phase as f64 / KERNEL_PHASES as f64 + half_width as f64 - 1.0 - tap as f64;
+                    (cutoff * sinc(cutoff * x) * kaiser(x / half_width as f64)) as f32
+                })
+            })
+            .collect();
+
+        Resampler {
+            input,
+            step,
+            kernel,
+            half_width,
+            buffer: VecDeque::from(vec![0.0; half_width]),
+            buffer_start: -(half_width as i64),
+            input_len: None,
+            output_idx: 0,
+        }
+    }
+}
+
+impl<I: Iterator<Item = f32>> Iterator for Resampler<I> {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.resample(true)
+    }
+}
+
+/// Input of a `Resampler` that is fed with blocks of samples
+#[derive(Debug, Default)]
+pub(crate) struct SampleQueue(VecDeque<f32>);
+
+impl Iterator for SampleQueue {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.pop_front()
+    }
+}
+
+impl Resampler<SampleQueue> {
+    /// Resampler for input that arrives in blocks, see `process` and `finish`
+    pub(crate) fn streaming(input_sample_rate: u32, output_sample_rate: u32) -> Self {
+        Self::new(
+            SampleQueue::default(),
+            input_sample_rate,
+            output_sample_rate,
+        )
+    }
+
+    /// Adds a block of input and returns the output samples that can be calculated so far
+    pub(crate) fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.input.0.extend(samples);
+        std::iter::from_fn(|| self.resample(false)).collect()
+    }
+
+    /// Returns the remaining output samples at the end of the input
+    pub(crate) fn finish(&mut self) -> Vec<f32> {
+        std::iter::from_fn(|| self.resample(true)).collect()
+    }
+}
+
+impl<I: Iterator<Item = f32>> Resampler<I> {
+    /// Calculates the next output sample.
+    /// When the input runs dry, it is padded with zeros if `end_of_input` is set, otherwise `None` is returned until more input is available.
+    fn resample(&mut self, end_of_input: bool) -> Option<f32> {
+        let position = self.output_idx as f64 * self.step;
+        let centre = position.floor() as i64;
+        let first = centre - self.half_width as i64 + 1;
+        let last = centre + self.half_width as i64;
+
+        // Make sure all input samples covered by the kernel are available, zero padding at the end
+        while self.buffer_start + (self.buffer.len() as i64) <= last {
+            match self.input.next() {
+                Some(sample) => self.buffer.push_back(sample),
+                None if !end_of_input && self.input_len.is_none() => return None,
+                None => {
+                    self.input_len
+                        .get_or_insert(self.buffer_start + self.buffer.len() as i64);
+                    self.buffer.push_back(0.0);
+                }
+            }
+        }
+        if self.input_len.is_some_and(|len| position >= len as f64) {
+            return None;
+        }
+        while self.buffer_start < first {
+            self.buffer.pop_front();
+            self.buffer_start += 1;
+        }
+
+        // Interpolate linearly between the two nearest kernel phases
+        let phase = (position - centre as f64) * KERNEL_PHASES as f64;
+        let frac = phase.fract() as f32;
+        let num_taps = 2 * self.half_width;
+        let row = phase as usize * num_taps;
+        let taps = &self.buffer.make_contiguous()[..num_taps];
+        let dot = |weights: &[f32]| -> f32 { taps.iter().zip(weights).map(|(s, w)| s * w).sum() };
+        let a = dot(&self.kernel[row..row + num_taps]);
+        let b = dot(&self.kernel[row + num_taps..row + 2 * num_taps]);
+        let sample = a + (b - a) * frac;
+        self.output_idx += 1;
+        Some(sample)
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * std::f64::consts::PI;
+        x.sin() / x
+    }
+}
+
+/// Kaiser window evaluated at `x` in the range -1..1
+fn kaiser(x: f64) -> f64 {
+    if x.abs() > 1.0 {
+        0.0
+    } else {
+        bessel_i0(KAISER_BETA * (1.0 - x * x).sqrt()) / bessel_i0(KAISER_BETA)
+    }
+}
+
+/// Zeroth order modified Bessel function of the first kind
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    for k in 1..50 {
+        term *= (x / (2.0 * k as f64)).powi(2);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f32, sample_rate: u32, len: usize) -> impl Iterator<Item = f32> {
+        (0..len).map(move |n| {
+            (2.0 * std::f32::consts::PI * frequency * n as f32 / sample_rate as f32).sin()
+        })
+    }
+
+    #[test]
+    fn resampler_upsample_sine() {
+        let output = Resampler::new(sine(1200.0, 16000, 1600), 16000, 192000).collect::<Vec<_>>();
+        assert_eq!(output.len(), 19200);
+
+        // Compare against the ideal signal away from the edges
+        let expected = sine(1200.0, 192000, 19200).collect::<Vec<_>>();
+        for idx in 1000..18000 {
+            assert!((output[idx] - expected[idx]).abs() < 0.01, "sample {idx}");
+        }
+    }
+
+    #[test]
+    fn resampler_downsample_sine() {
+        let output = Resampler::new(sine(2400.0, 48000, 4800), 48000, 44100).collect::<Vec<_>>();
+        assert_eq!(output.len(), 4410);
+
+        let expected = sine(2400.0, 44100, 4410).collect::<Vec<_>>();
+        for idx in 200..4200 {
+            assert!((output[idx] - expected[idx]).abs() < 0.01, "sample {idx}");
+        }
+    }
+
+    #[test]
+    fn resampler_streaming_blocks() {
+        let input = sine(1200.0, 16000, 1600).collect::<Vec<_>>();
+        let expected = Resampler::new(input.clone().into_iter(), 16000, 44100).collect::<Vec<_>>();
+
+        let mut resampler = Resampler::streaming(16000, 44100);
+        let mut output = vec![];
+        for block in input.chunks(100) {
+            output.extend(resampler.process(block));
+        }
+        output.extend(resampler.finish());
+        assert_eq!(output, expected);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{Decoder, DecoderConfig, SignalCondition};
+
+/// Finds the start of a frame in bits that lost the frame alignment, e.g. after a sync error in a long run of data
+/// without idle gaps, where waiting for the next start bit often locks onto a data bit.
+///
+/// Tries every start position within the first frame length of `bits` for the next `frames` frames to decode without errors.
+/// A wrong alignment can decode a few frames by chance, but usually needs idle bits between them, so of the positions that
+/// decode the frames the one where they take the fewest bits is returned (the earliest on a tie), or `None` if no position does
+/// or the configuration is invalid.
+///
+/// ```
+/// use kcs_decoder::*;
+/// use SignalCondition::{Mark, Space};
+///
+/// let config = DecoderConfig {
+///     parity: Parity::NONE,
+///     stopbits: (1, SignalCondition::Mark),
+///     ..Default::default()
+/// };
+/// // Two bits of a broken frame, then 0x01 and 0x02 without gaps
+/// let bits = [
+///     vec![Mark, Space],
+///     vec![Space, Mark, Space, Space, Space, Space, Space, Space, Space, Mark],
+///     vec![Space, Space, Mark, Space, Space, Space, Space, Space, Space, Mark],
+/// ]
+/// .concat();
+/// assert_eq!(find_alignment(&config, &bits, 2), Some(2));
+/// assert_eq!(find_alignment(&config, &bits, 3), None);
+/// ```
+pub fn find_alignment(
+    config: &DecoderConfig,
+    bits: &[SignalCondition],
+    frames: usize,
+) -> Option<usize> {
+    let decoder = Decoder::new(DecoderConfig {
+        salvage: false,
+        ..*config
+    })?;
+    (0..config.frame_bits().min(bits.len()))
+        .filter(|offset| bits[*offset] == config.startbits.1)
+        .filter_map(|offset| {
+            let mut decoder = decoder.clone();
+            let mut valid = 0;
+            for (length, bit) in bits[offset..].iter().enumerate() {
+                match decoder.process(*bit) {
+                    Ok(_) => valid += 1,
+                    Err(Some(_)) => return None,
+                    Err(None) => {}
+                }
+                if valid == frames {
+                    return Some((length, offset));
+                }
+            }
+            None
+        })
+        .min()
+        .map(|(_, offset)| offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    /// Bits of the frames of `data`, without gaps between them
+    fn frames(config: &DecoderConfig, data: &[u8]) -> Vec<SignalCondition> {
+        data.iter()
+            .flat_map(|val| {
+                let data = (0..config.num_databits).map(move |bit| match val >> bit & 1 {
+                    1 => SignalCondition::Mark,
+                    _ => SignalCondition::Space,
+                });
+                std::iter::repeat_n(config.startbits.1, config.startbits.0)
+                    .chain(data)
+                    .chain(std::iter::repeat_n(config.stopbits.1, config.stopbits.0))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn find_alignment_after_slip() {
+        let config = DecoderConfig {
+            parity: Parity::NONE,
+            ..Default::default()
+        };
+        let data = (0..40u8)
+            .map(|val| val.wrapping_mul(73))
+            .collect::<Vec<_>>();
+        let bits = frames(&config, &data);
+        assert_eq!(find_alignment(&config, &bits, 4), Some(0));
+
+        // Starting in the middle of a frame, the next frame start is found
+        let frame_bits = config.frame_bits();
+        for slip in 1..frame_bits {
+            let offset = find_alignment(&config, &bits[slip..], 4);
+            assert_eq!(offset, Some(frame_bits - slip), "{slip}");
+        }
+
+        // In lower case text, the top data bit and the stop bits line up as well
+        // as the real start and stop bits, it takes more frames to tell them apart
+        let bits = frames(&config, b"Kansas City Standard");
+        assert_eq!(find_alignment(&config, &bits[1..], 4), Some(7));
+        assert_eq!(find_alignment(&config, &bits[1..], 8), Some(frame_bits - 1));
+
+        // Noise in every frame
+        let noisy = bits
+            .iter()
+            .enumerate()
+            .map(|(idx, bit)| match idx % frame_bits == 5 {
+                true => SignalCondition::Error,
+                false => *bit,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(find_alignment(&config, &noisy, 1), None);
+        assert_eq!(find_alignment(&config, &[], 1), None);
+
+        let invalid = DecoderConfig {
+            num_databits: 0,
+            ..config
+        };
+        assert_eq!(find_alignment(&invalid, &bits, 4), None);
+    }
+}
@@ -0,0 +1,331 @@
+//! Processor Technology Sol-20 tape data format, as written by SOLOS and CUTER with the CUTS presets
+//!
+//! Each file:
+//! - Leader of 0x00 bytes, at least `MIN_LEADER_LENGTH` are expected before the sync byte
+//! - 1 byte 0x01 sync
+//! - 16 bytes header
+//! - 1 byte header CRC
+//! - The data in blocks of 256 bytes (the last block holds the rest), each followed by 1 byte CRC
+//!
+//! Header:
+//! - 5 bytes: Name, padded with 0x00
+//! - 1 byte 0x00
+//! - 1 byte: File type
+//! - 2 bytes: Length of the data, LSB first
+//! - 2 bytes: Load address, LSB first
+//! - 2 bytes: Execution address, LSB first
+//! - 3 bytes spare
+//!
+//! The CRC of the header and of each block starts at 0 and is updated with `crc = !(byte - crc)` for every byte,
+//! as in the DOCRC routine of SOLOS.
+
+use std::error::Error;
+use std::fmt::Display;
+
+pub const MIN_LEADER_LENGTH: usize = 10;
+/// Number of 0x00 bytes written before each file
+pub const LEADER_LENGTH: usize = 30;
+pub const SYNC_BYTE: u8 = 0x01;
+pub const HEADER_LENGTH: usize = 16;
+pub const NAME_LENGTH: usize = 5;
+pub const BLOCK_SIZE: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sol20Error {
+    MissingHeader,
+    IncompleteHeader,
+    HeaderCrc {
+        expected: u8,
+        calculated: u8,
+    },
+    IncompleteBlock(usize),
+    DataCrc {
+        block: usize,
+        expected: u8,
+        calculated: u8,
+    },
+}
+
+impl Display for Sol20Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Sol20Error::MissingHeader => write!(f, "no file header found"),
+            Sol20Error::IncompleteHeader => write!(f, "incomplete header"),
+            Sol20Error::HeaderCrc {
+                expected,
+                calculated,
+            } => write!(
+                f,
+                "mismatching header CRC found, expected {expected:#04x}, got {calculated:#04x}"
+            ),
+            Sol20Error::IncompleteBlock(block) => write!(f, "block {block} is incomplete"),
+            Sol20Error::DataCrc {
+                block,
+                expected,
+                calculated,
+            } => write!(
+                f,
+                "mismatching data CRC found in block {block}, expected {expected:#04x}, got {calculated:#04x}"
+            ),
+        }
+    }
+}
+
+impl Error for Sol20Error {}
+
+/// CRC of a header or data block
+pub fn crc(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, &val| !val.wrapping_sub(crc))
+}
+
+/// Returns the offset of the sync byte of the first file in `buffer`
+pub fn find_header(buffer: &[u8]) -> Option<usize> {
+    buffer
+        .windows(MIN_LEADER_LENGTH + 1)
+        .position(|window| {
+            window[MIN_LEADER_LENGTH] == SYNC_BYTE
+                && window[..MIN_LEADER_LENGTH].iter().all(|&val| val == 0)
+        })
+        .map(|offset| offset + MIN_LEADER_LENGTH)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHeader {
+    pub name: String,
+    pub file_type: u8,
+    pub length: u16,
+    pub load_address: u16,
+    pub exec_address: u16,
+}
+
+impl FileHeader {
+    /// Parses and validates a header, `buffer` must begin after the sync byte
+    pub fn parse(buffer: &[u8]) -> Result<Self, Sol20Error> {
+        if buffer.len() < HEADER_LENGTH + 1 {
+            return Err(Sol20Error::IncompleteHeader);
+        }
+        let expected = buffer[HEADER_LENGTH];
+        let calculated = crc(&buffer[..HEADER_LENGTH]);
+        if expected != calculated {
+            return Err(Sol20Error::HeaderCrc {
+                expected,
+                calculated,
+            });
+        }
+        let name = &buffer[..NAME_LENGTH];
+        let name_length = name.iter().position(|&val| val == 0).unwrap_or(NAME_LENGTH);
+        Ok(Self {
+            name: String::from_utf8_lossy(&name[..name_length]).to_string(),
+            file_type: buffer[6],
+            length: u16::from_le_bytes([buffer[7], buffer[8]]),
+            load_address: u16::from_le_bytes([buffer[9], buffer[10]]),
+            exec_address: u16::from_le_bytes([buffer[11], buffer[12]]),
+        })
+    }
+
+    /// The header followed by its CRC. The name is cut to `NAME_LENGTH` bytes
+    pub fn to_bytes(&self) -> [u8; HEADER_LENGTH + 1] {
+        let mut header = [0u8; HEADER_LENGTH + 1];
+        for (byte, val) in header.iter_mut().zip(self.name.bytes().take(NAME_LENGTH)) {
+            *byte = val;
+        }
+        header[6] = self.file_type;
+        header[7..9].copy_from_slice(&self.length.to_le_bytes());
+        header[9..11].copy_from_slice(&self.load_address.to_le_bytes());
+        header[11..13].copy_from_slice(&self.exec_address.to_le_bytes());
+        header[HEADER_LENGTH] = crc(&header[..HEADER_LENGTH]);
+        header
+    }
+}
+
+impl Display for FileHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "name='{}', type={:#04x}, length={}, load address={:#06x}, exec address={:#06x}",
+            self.name, self.file_type, self.length, self.load_address, self.exec_address
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapeFile {
+    pub header: FileHeader,
+    pub data: Vec<u8>,
+}
+
+impl TapeFile {
+    /// Creates a file of up to 65535 bytes of data
+    pub fn new(
+        name: &str,
+        file_type: u8,
+        load_address: u16,
+        exec_address: u16,
+        data: &[u8],
+    ) -> Option<Self> {
+        Some(Self {
+            header: FileHeader {
+                name: name.to_string(),
+                file_type,
+                length: u16::try_from(data.len()).ok()?,
+                load_address,
+                exec_address,
+            },
+            data: data.to_vec(),
+        })
+    }
+
+    /// Locates and validates the first file in `buffer`, checking the CRC of the header and of every block.
+    /// Returns the file and the number of bytes consumed from `buffer`.
+    pub fn load(buffer: &[u8]) -> Result<(Self, usize), Sol20Error> {
+        let offset = find_header(buffer).ok_or(Sol20Error::MissingHeader)? + 1;
+        let header = FileHeader::parse(&buffer[offset..])?;
+
+        let mut position = offset + HEADER_LENGTH + 1;
+        let mut data = Vec::with_capacity(header.length as usize);
+        for (block, start) in (0..header.length as usize).step_by(BLOCK_SIZE).enumerate() {
+            let size = usize::min(BLOCK_SIZE, header.length as usize - start);
+            if buffer.len() < position + size + 1 {
+                return Err(Sol20Error::IncompleteBlock(block));
+            }
+            let block_data = &buffer[position..position + size];
+            let expected = buffer[position + size];
+            let calculated = crc(block_data);
+            if expected != calculated {
+                return Err(Sol20Error::DataCrc {
+                    block,
+                    expected,
+                    calculated,
+                });
+            }
+            data.extend_from_slice(block_data);
+            position += size + 1;
+        }
+        Ok((Self { header, data }, position))
+    }
+
+    /// Number of data blocks
+    pub fn num_blocks(&self) -> usize {
+        self.data.len().div_ceil(BLOCK_SIZE)
+    }
+
+    /// The file as it is written to tape, including the leader
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; LEADER_LENGTH];
+        bytes.push(SYNC_BYTE);
+        bytes.extend_from_slice(&self.header.to_bytes());
+        for block in self.data.chunks(BLOCK_SIZE) {
+            bytes.extend_from_slice(block);
+            bytes.push(crc(block));
+        }
+        bytes
+    }
+}
+
+impl Display for TapeFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}, blocks={}", self.header, self.num_blocks())
+    }
+}
+
+/// Reads consecutive valid files from `buffer`, stopping at the first invalid file.
+/// The error that stopped the reading is returned along with the files,
+/// a missing header after the last file is not considered an error.
+pub fn read_files(buffer: &[u8]) -> (Vec<TapeFile>, Option<Sol20Error>) {
+    let mut files = vec![];
+    let mut offset = 0;
+    loop {
+        match TapeFile::load(&buffer[offset..]) {
+            Ok((file, consumed)) => {
+                files.push(file);
+                offset += consumed;
+            }
+            Err(Sol20Error::MissingHeader) => return (files, None),
+            Err(error) => return (files, Some(error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_tape() -> Vec<u8> {
+        let data = (0..300).map(|n| n as u8).collect::<Vec<_>>();
+        [
+            TapeFile::new("GAME", b'B', 0x0000, 0x0000, &data).unwrap(),
+            TapeFile::new("MON", 0x80, 0xc800, 0xc803, &[0x01, 0x00, 0xc3]).unwrap(),
+        ]
+        .iter()
+        .flat_map(|file| file.to_bytes())
+        .collect()
+    }
+
+    #[test]
+    fn sol20_crc() {
+        assert_eq!(crc(&[]), 0x00);
+        // !(0x01 - 0x00), then !(0x02 - 0xfe)
+        assert_eq!(crc(&[0x01]), 0xfe);
+        assert_eq!(crc(&[0x01, 0x02]), 0xfb);
+    }
+
+    #[test]
+    fn sol20_header_roundtrip() {
+        let header = FileHeader {
+            name: "BASIC".to_string(),
+            file_type: b'C',
+            length: 0x1234,
+            load_address: 0x0100,
+            exec_address: 0x0103,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[..6], b"BASIC\0");
+        assert_eq!(&bytes[6..13], [b'C', 0x34, 0x12, 0x00, 0x01, 0x03, 0x01]);
+        assert_eq!(bytes[HEADER_LENGTH], crc(&bytes[..HEADER_LENGTH]));
+        assert_eq!(FileHeader::parse(&bytes), Ok(header));
+        assert_eq!(
+            FileHeader::parse(&bytes[..HEADER_LENGTH]),
+            Err(Sol20Error::IncompleteHeader)
+        );
+    }
+
+    #[test]
+    fn sol20_read_files() {
+        let mut tape = vec![0x55, 0x01, 0x00];
+        tape.extend(test_tape());
+        let (files, error) = read_files(&tape);
+
+        assert_eq!(error, None);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].header.name, "GAME");
+        assert_eq!(files[0].num_blocks(), 2);
+        assert_eq!(files[0].data.len(), 300);
+        assert_eq!(files[1].header.exec_address, 0xc803);
+        assert_eq!(files[1].data, vec![0x01, 0x00, 0xc3]);
+    }
+
+    #[test]
+    fn sol20_read_files_errors() {
+        let tape = test_tape();
+        let header = LEADER_LENGTH + 1;
+
+        let mut damaged = tape.clone();
+        damaged[header + 7] ^= 0x01;
+        let (files, error) = read_files(&damaged);
+        assert!(files.is_empty());
+        assert!(matches!(error, Some(Sol20Error::HeaderCrc { .. })));
+
+        // A byte in the second block of the first file
+        let mut damaged = tape.clone();
+        damaged[header + HEADER_LENGTH + 1 + BLOCK_SIZE + 10] ^= 0x01;
+        let (files, error) = read_files(&damaged);
+        assert!(files.is_empty());
+        assert!(matches!(error, Some(Sol20Error::DataCrc { block: 1, .. })));
+
+        let (files, error) = read_files(&tape[..tape.len() - 1]);
+        assert_eq!(files.len(), 1);
+        assert_eq!(error, Some(Sol20Error::IncompleteBlock(0)));
+
+        assert_eq!(read_files(&[0u8; 20]), (vec![], None));
+    }
+}
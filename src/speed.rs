@@ -0,0 +1,260 @@
+use std::fmt::Display;
+use std::ops::Range;
+
+/// Weight of each period in the measured tape speed
+const TRACKING_GAIN: f32 = 0.05;
+/// Largest deviation of the measured frequencies from the nominal ones, periods further off are not measured
+const MAX_SPEED_DEVIATION: f32 = 0.2;
+/// Length of the intervals of `SpeedSummary` in seconds
+const SPEED_INTERVAL: f32 = 10.0;
+
+/// Tells the two tones apart and measures their actual frequencies
+///
+/// Each period within `MAX_SPEED_DEVIATION` of a nominal frequency is measured, if the periods before and after it
+/// are of the same tone. A period at a change of tone is partly of each tone, so it is left out.
+/// The tape speed relative to the nominal speed follows the measured periods of both tones, and both tones are
+/// shifted by it, so the frequency of a tone is known even if only the other tone has been measured recently.
+/// The periods are classified with bounds of `tolerance_percent` around the nominal frequencies, or around the measured
+/// frequencies if adaptive, so a tape played too fast or too slow stays within the bounds.
+///
+/// ```
+/// use kcs_decoder::*;
+///
+/// let mut tracker = FrequencyTracker::new([2400, 1200], 5, true).unwrap();
+/// // The tape runs 8% fast, outside the bounds at first
+/// assert_eq!(tracker.tone(2592.0), None);
+/// for _ in 0..100 {
+///     tracker.tone(2592.0);
+/// }
+/// assert_eq!(tracker.tone(2592.0), Some(0));
+/// assert_eq!(tracker.tone(1296.0), Some(1));
+/// assert!((tracker.speed() - 1.08).abs() < 0.001);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrequencyTracker {
+    nominal: [f32; 2],
+    tolerance: f32,
+    adaptive: bool,
+    speed: f32,
+    /// Tone within the measuring range and frequency of the previous period, and whether the period before it had the same tone
+    previous: Option<(usize, f32, bool)>,
+}
+
+impl FrequencyTracker {
+    pub fn new(frequencies: [u32; 2], tolerance_percent: usize, adaptive: bool) -> Option<Self> {
+        let nominal = frequencies.map(|frequency| frequency as f32);
+        let tolerance = tolerance_percent as f32 / 100.0;
+        let tracker = Self {
+            nominal,
+            tolerance,
+            adaptive,
+            speed: 1.0,
+            previous: None,
+        };
+        if tracker.bounds(0).contains(&nominal[1])
+            || tracker.bounds(1).contains(&nominal[0])
+            || tolerance_percent > 50
+        {
+            return None;
+        }
+        Some(tracker)
+    }
+
+    /// Frequencies that are classified as `tone`
+    pub fn bounds(&self, tone: usize) -> Range<f32> {
+        let frequency = match self.adaptive {
+            true => self.frequencies()[tone],
+            false => self.nominal[tone],
+        };
+        frequency - frequency * self.tolerance..frequency + frequency * self.tolerance
+    }
+
+    /// Actual frequency of each tone, the nominal frequency until it has been measured
+    pub fn frequencies(&self) -> [f32; 2] {
+        self.nominal.map(|frequency| frequency * self.speed)
+    }
+
+    /// Tape speed relative to the nominal speed, above 1.0 if the tones are higher than nominal
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// How well `frequency` fits `tone`, 1.0 at the centre of its bounds down to 0.0 at the edges and outside.
+    /// Falls off with the square of the distance, so the jitter of a clean signal costs little.
+    pub fn confidence(&self, tone: usize, frequency: f32) -> f32 {
+        let bounds = self.bounds(tone);
+        let centre = (bounds.start + bounds.end) / 2.0;
+        let distance = (frequency - centre) / (bounds.end - centre);
+        (1.0 - distance * distance).clamp(0.0, 1.0)
+    }
+
+    /// Measures the frequency of a period and returns the index of its tone, if it is within the bounds of one
+    pub fn tone(&mut self, frequency: f32) -> Option<usize> {
+        self.measure(frequency);
+        (0..2).find(|tone| self.bounds(*tone).contains(&frequency))
+    }
+
+    fn measure(&mut self, frequency: f32) {
+        let ratios = self.nominal.map(|nominal| frequency / nominal);
+        let nearest = match (ratios[0].ln().abs()) <= (ratios[1].ln().abs()) {
+            true => 0,
+            false => 1,
+        };
+        if (ratios[nearest] - 1.0).abs() > MAX_SPEED_DEVIATION {
+            self.previous = None;
+            return;
+        }
+
+        let same = match self.previous {
+            Some((tone, previous, same)) => {
+                if same && tone == nearest {
+                    self.speed += TRACKING_GAIN * (previous / self.nominal[tone] - self.speed);
+                }
+                tone == nearest
+            }
+            None => false,
+        };
+        self.previous = Some((nearest, frequency, same));
+    }
+
+    pub fn reset(&mut self) {
+        self.speed = 1.0;
+        self.previous = None;
+    }
+}
+
+/// Tape speed measured while decoding, for the diagnostic summary
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeedSummary {
+    interval: usize,
+    /// Lowest and highest speed
+    pub range: (f32, f32),
+    /// Start of each interval of `SPEED_INTERVAL` seconds with a carrier, and the mean speed in it
+    pub intervals: Vec<(usize, f32)>,
+    /// Sum and number of the speeds in the last interval
+    sum: (f32, usize),
+}
+
+impl SpeedSummary {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            interval: ((SPEED_INTERVAL * sample_rate as f32) as usize).max(1),
+            range: (f32::MAX, 0.0),
+            intervals: vec![],
+            sum: (0.0, 0),
+        }
+    }
+
+    /// Adds the speed at sample `idx`
+    pub fn update(&mut self, idx: usize, speed: f32) {
+        let start = idx - idx % self.interval;
+        if self.intervals.last().is_none_or(|(last, _)| *last != start) {
+            self.intervals.push((start, speed));
+            self.sum = (0.0, 0);
+        }
+        self.sum = (self.sum.0 + speed, self.sum.1 + 1);
+        self.intervals.last_mut().unwrap().1 = self.sum.0 / self.sum.1 as f32;
+        self.range = (self.range.0.min(speed), self.range.1.max(speed));
+    }
+
+    /// Deviation of `speed` from the nominal speed in percent, rounded to one decimal
+    pub fn deviation(speed: f32) -> f32 {
+        // Adding zero turns -0.0 into 0.0
+        ((speed - 1.0) * 1000.0).round() / 10.0 + 0.0
+    }
+
+    /// Mean speed over all intervals
+    pub fn mean(&self) -> Option<f32> {
+        match self.intervals.is_empty() {
+            true => None,
+            false => Some(
+                self.intervals.iter().map(|(_, speed)| speed).sum::<f32>()
+                    / self.intervals.len() as f32,
+            ),
+        }
+    }
+}
+
+impl Display for SpeedSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.mean() {
+            Some(mean) => write!(
+                f,
+                "tape speed {:+.1}% ({:+.1}% to {:+.1}%)",
+                Self::deviation(mean),
+                Self::deviation(self.range.0),
+                Self::deviation(self.range.1)
+            ),
+            None => write!(f, "tape speed not measured"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequency_tracker_follows_drift() {
+        let mut fixed = FrequencyTracker::new([2400, 1200], 10, false).unwrap();
+        let mut adaptive = FrequencyTracker::new([2400, 1200], 10, true).unwrap();
+        // The speed drifts from 1.0 to 1.15, with runs of four periods of each tone.
+        // The first period of each run is at the change of tone and is 10% off.
+        let mut fixed_errors = 0;
+        for run in 0..400 {
+            let speed = 1.0 + 0.15 * run as f32 / 400.0;
+            let (tone, frequency) = match run % 2 {
+                0 => (0, 2400.0 * speed),
+                _ => (1, 1200.0 * speed),
+            };
+            for period in 0..4 {
+                let frequency = match period {
+                    0 => frequency * 0.9,
+                    _ => frequency,
+                };
+                if period > 0 {
+                    assert_eq!(adaptive.tone(frequency), Some(tone), "{run}");
+                    fixed_errors += usize::from(fixed.tone(frequency) != Some(tone));
+                } else {
+                    adaptive.tone(frequency);
+                    fixed.tone(frequency);
+                }
+            }
+        }
+        assert!(fixed_errors > 300, "{fixed_errors}");
+        // The periods at the changes of tone are not measured
+        assert!(
+            (adaptive.speed() - 1.15).abs() < 0.005,
+            "{}",
+            adaptive.speed()
+        );
+        assert!((fixed.speed() - adaptive.speed()).abs() < 1e-6);
+        assert!((adaptive.frequencies()[1] - 1380.0).abs() < 5.0);
+        assert!(adaptive.confidence(1, 1380.0) > 0.95);
+        assert!((adaptive.confidence(1, 1449.0) - 0.75).abs() < 0.05);
+        assert_eq!(adaptive.confidence(0, 1380.0), 0.0);
+
+        adaptive.reset();
+        assert_eq!(adaptive.speed(), 1.0);
+        assert_eq!(adaptive.tone(2760.0), None);
+    }
+
+    #[test]
+    fn speed_summary_intervals() {
+        let mut summary = SpeedSummary::new(100);
+        assert_eq!(summary.to_string(), "tape speed not measured");
+        for idx in 0..1000 {
+            summary.update(idx, 1.02);
+        }
+        for idx in 2000..3000 {
+            summary.update(idx, 0.96 + (idx % 2) as f32 * 0.02);
+        }
+        let deviations = summary
+            .intervals
+            .iter()
+            .map(|(idx, speed)| (*idx, SpeedSummary::deviation(*speed)))
+            .collect::<Vec<_>>();
+        assert_eq!(deviations, vec![(0, 2.0), (2000, -3.0)]);
+        assert_eq!(summary.to_string(), "tape speed -0.5% (-4.0% to +2.0%)");
+    }
+}
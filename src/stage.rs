@@ -0,0 +1,328 @@
+use crate::*;
+
+/// Common interface of the processing stages
+///
+/// A stage transforms a stream of inputs to a stream of outputs, producing at most one output per input.
+/// This keeps `process` free of allocations for every sample and lets `confidence` and `speed` refer to the one
+/// output just returned. A stage that can complete several outputs from one input, like `BitClockRecovery` with a
+/// long period, queues them and returns one per input; the stages reduce the rate (samples -> periods -> bits -> words),
+/// so the queue is drained by the following inputs, and `flush` returns what is left at the end.
+///
+/// Stages are composed with `then`, so a chain like filter -> demodulator -> clock recovery -> framing
+/// is itself a stage that can be used wherever a single stage fits.
+///
+/// ```
+/// use kcs_decoder::*;
+///
+/// let mut periods = vec![];
+/// let mut demodulator = ZeroCrossingDetector::new(0.0)
+///     .then(FrequencyIdentifier::new(ZeroCrossingDirection::Pos, 4800))
+///     .then(Probe::new(|period: &(usize, f32)| periods.push(*period)));
+/// let square_wave = [1.0, 1.0, -1.0, -1.0].repeat(3);
+/// for (idx, sample) in square_wave.into_iter().enumerate() {
+///     demodulator.process((idx, sample));
+/// }
+/// drop(demodulator);
+/// assert_eq!(periods, vec![(8, 1200.0)]);
+/// ```
+pub trait Stage {
+    type Input;
+    type Output;
+
+    fn process(&mut self, input: Self::Input) -> Option<Self::Output>;
+
+    /// Returns the stage to its initial state
+    fn reset(&mut self);
+
+    /// Returns any output held back at the end of the input
+    fn flush(&mut self) -> Vec<Self::Output> {
+        vec![]
+    }
+
+    /// Called with the signal levels measured in front of the stages, so a stage can adapt its thresholds
+    fn adapt(&mut self, _levels: &SignalLevels) {}
+
+    /// Tape speed relative to the nominal speed, if the stage measures it
+    fn speed(&self) -> Option<f32> {
+        None
+    }
+
+    /// Confidence in the last output, from 0.0 for a guess to 1.0 for a clean signal, if the stage measures it
+    fn confidence(&self) -> Option<f32> {
+        None
+    }
+
+    /// Feeds the output of this stage to `next`
+    fn then<S: Stage<Input = Self::Output>>(self, next: S) -> Chain<Self, S>
+    where
+        Self: Sized,
+    {
+        Chain {
+            first: self,
+            second: next,
+        }
+    }
+}
+
+impl<S: Stage + ?Sized> Stage for Box<S> {
+    type Input = S::Input;
+    type Output = S::Output;
+
+    fn process(&mut self, input: Self::Input) -> Option<Self::Output> {
+        (**self).process(input)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn flush(&mut self) -> Vec<Self::Output> {
+        (**self).flush()
+    }
+
+    fn adapt(&mut self, levels: &SignalLevels) {
+        (**self).adapt(levels)
+    }
+
+    fn speed(&self) -> Option<f32> {
+        (**self).speed()
+    }
+
+    fn confidence(&self) -> Option<f32> {
+        (**self).confidence()
+    }
+}
+
+/// Two stages run one after the other, see `Stage::then`
+#[derive(Debug, Clone)]
+pub struct Chain<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Stage, B: Stage<Input = A::Output>> Stage for Chain<A, B> {
+    type Input = A::Input;
+    type Output = B::Output;
+
+    fn process(&mut self, input: Self::Input) -> Option<Self::Output> {
+        self.first
+            .process(input)
+            .and_then(|val| self.second.process(val))
+    }
+
+    fn reset(&mut self) {
+        self.first.reset();
+        self.second.reset();
+    }
+
+    fn adapt(&mut self, levels: &SignalLevels) {
+        self.first.adapt(levels);
+        self.second.adapt(levels);
+    }
+
+    /// The speed measured closest to the output
+    fn speed(&self) -> Option<f32> {
+        self.second.speed().or_else(|| self.first.speed())
+    }
+
+    /// The confidence measured closest to the output
+    fn confidence(&self) -> Option<f32> {
+        self.second.confidence().or_else(|| self.first.confidence())
+    }
+
+    fn flush(&mut self) -> Vec<Self::Output> {
+        let mut output = self
+            .first
+            .flush()
+            .into_iter()
+            .filter_map(|val| self.second.process(val))
+            .collect::<Vec<_>>();
+        output.extend(self.second.flush());
+        output
+    }
+}
+
+/// Passes values through unchanged and shows each of them to a callback, e.g. for logging or plotting
+pub struct Probe<T, F: FnMut(&T)> {
+    callback: F,
+    marker: std::marker::PhantomData<fn(T)>,
+}
+
+impl<T, F: FnMut(&T)> Probe<T, F> {
+    pub fn new(callback: F) -> Self {
+        Self {
+            callback,
+            marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T, F: FnMut(&T)> Stage for Probe<T, F> {
+    type Input = T;
+    type Output = T;
+
+    fn process(&mut self, input: T) -> Option<T> {
+        (self.callback)(&input);
+        Some(input)
+    }
+
+    fn reset(&mut self) {}
+}
+
+impl Stage for ZeroCrossingDetector {
+    type Input = (usize, f32);
+    type Output = (f64, ZeroCrossingDirection);
+
+    fn process(&mut self, input: Self::Input) -> Option<Self::Output> {
+        ZeroCrossingDetector::process(self, input)
+    }
+
+    fn reset(&mut self) {
+        *self = Self {
+            adaptive: self.adaptive,
+            ..Self::with_interpolation(self.hysteresis, self.interpolation)
+        };
+    }
+
+    fn adapt(&mut self, levels: &SignalLevels) {
+        if self.adaptive {
+            self.hysteresis = levels.hysteresis();
+        }
+    }
+
+    /// A crossing waiting for the sample after it (cubic interpolation) is estimated linearly
+    fn flush(&mut self) -> Vec<Self::Output> {
+        self.pending
+            .take()
+            .map(|(idx, direction)| (self.linear_crossing(idx), direction))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl Stage for FrequencyIdentifier {
+    type Input = (f64, ZeroCrossingDirection);
+    type Output = (usize, f32);
+
+    fn process(&mut self, input: Self::Input) -> Option<Self::Output> {
+        FrequencyIdentifier::process(self, input)
+    }
+
+    fn reset(&mut self) {
+        self.last_sample_idx = None;
+    }
+}
+
+impl Stage for HiLowIdentifier {
+    type Input = (usize, f32);
+    type Output = (usize, SignalCondition);
+
+    fn process(&mut self, input: Self::Input) -> Option<Self::Output> {
+        HiLowIdentifier::process(self, input)
+    }
+
+    fn reset(&mut self) {
+        self.bitcount = 0;
+        self.confidence = 0.0;
+        self.bit_confidence = 1.0;
+        self.tracker.reset();
+    }
+
+    fn speed(&self) -> Option<f32> {
+        Some(self.tracker.speed())
+    }
+
+    /// The mean confidence of the periods of the last bit
+    fn confidence(&self) -> Option<f32> {
+        Some(self.bit_confidence)
+    }
+}
+
+/// Decodes bits tagged with their sample index to words or framing errors at the same index
+impl Stage for Decoder {
+    type Input = (usize, SignalCondition);
+    type Output = (usize, Result<Word, DecoderError>);
+
+    fn process(&mut self, input: Self::Input) -> Option<Self::Output> {
+        let (idx, level) = input;
+        match Decoder::process(self, level) {
+            Ok(val) => Some((idx, Ok(val))),
+            Err(Some(error)) => Some((idx, Err(error))),
+            Err(None) => None,
+        }
+    }
+
+    fn reset(&mut self) {
+        Decoder::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stage_chain_matches_stages() {
+        let config = DecoderConfig::default();
+        let encoder_config = EncoderConfig {
+            sample_rate: 48000,
+            ..Default::default()
+        };
+        let data = b"Kansas City Standard";
+        let samples = Encoder::new(config, encoder_config).unwrap().encode(data);
+
+        let mut chain =
+            ZeroCrossingDetector::with_interpolation(0.0, ZeroCrossingInterpolation::Cubic)
+                .then(FrequencyIdentifier::new(ZeroCrossingDirection::Pos, 48000))
+                .then(
+                    HiLowIdentifier::new(
+                        2400,
+                        1200,
+                        10,
+                        (1, SignalCondition::Space),
+                        (2, SignalCondition::Mark),
+                    )
+                    .unwrap(),
+                )
+                .then(Decoder::new(config).unwrap());
+        let decode = |chain: &mut dyn Stage<
+            Input = (usize, f32),
+            Output = (usize, Result<Word, DecoderError>),
+        >| {
+            let mut output = samples
+                .iter()
+                .copied()
+                .enumerate()
+                .filter_map(|val| chain.process(val))
+                .collect::<Vec<_>>();
+            output.extend(chain.flush());
+            output
+                .into_iter()
+                .filter_map(|(_, val)| val.ok())
+                .map(|val| val as u8)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(decode(&mut chain), data);
+
+        // The same again after a reset, also when boxed
+        chain.reset();
+        let mut boxed: Box<dyn Stage<Input = _, Output = _>> = Box::new(chain);
+        assert_eq!(decode(&mut boxed), data);
+    }
+
+    #[test]
+    fn stage_flush_pending_crossing() {
+        let mut zc =
+            ZeroCrossingDetector::with_interpolation(0.0, ZeroCrossingInterpolation::Cubic);
+        assert_eq!(Stage::process(&mut zc, (0, 1.0)), None);
+        assert_eq!(Stage::process(&mut zc, (1, -3.0)), None);
+        assert_eq!(zc.flush(), vec![(0.25, ZeroCrossingDirection::Neg)]);
+        assert_eq!(zc.flush(), vec![]);
+
+        // Without interpolation nothing is held back
+        let mut zc = ZeroCrossingDetector::new(0.0);
+        assert_eq!(Stage::process(&mut zc, (0, 1.0)), None);
+        assert!(Stage::process(&mut zc, (1, -3.0)).is_some());
+        assert_eq!(zc.flush(), vec![]);
+    }
+}
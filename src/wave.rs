@@ -0,0 +1,481 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::io::{self, Read};
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// Last 14 bytes of the sub format GUID for the formats that are also defined as a format tag
+const KSDATAFORMAT_SUBTYPE_SUFFIX: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+/// Length of the 'fmt ' chunk with WAVE_FORMAT_EXTENSIBLE, any bytes after it are not used
+const MAX_FORMAT_CHUNK_LENGTH: u32 = 40;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WaveError {
+    NotRiff,
+    NotWave,
+    MissingChunk(&'static str),
+    InvalidHeader(&'static str),
+    UnsupportedFormat(u16),
+    UnsupportedSubFormat([u8; 16]),
+    UnsupportedBitsPerSample(SampleFormat, u16),
+    IO(String),
+}
+
+impl From<io::Error> for WaveError {
+    fn from(value: io::Error) -> Self {
+        Self::IO(value.to_string())
+    }
+}
+
+impl Display for WaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WaveError::NotRiff => write!(f, "not a RIFF file"),
+            WaveError::NotWave => write!(f, "not a WAVE file"),
+            WaveError::MissingChunk(name) => write!(f, "no '{name}' chunk found"),
+            WaveError::InvalidHeader(reason) => write!(f, "invalid header, {reason}"),
+            WaveError::UnsupportedFormat(tag) => write!(
+                f,
+                "unsupported wave format {tag:#06x} ({}), only integer PCM and IEEE float are supported",
+                format_name(*tag)
+            ),
+            WaveError::UnsupportedSubFormat(guid) => write!(
+                f,
+                "unsupported WAVE_FORMAT_EXTENSIBLE sub format {}, only integer PCM and IEEE float are supported",
+                guid.iter().map(|byte| format!("{byte:02x}")).collect::<String>()
+            ),
+            WaveError::UnsupportedBitsPerSample(format, bits) => {
+                write!(f, "unsupported sample size, {bits} bit {format}")
+            }
+            WaveError::IO(val) => write!(f, "{val}"),
+        }
+    }
+}
+
+impl Error for WaveError {}
+
+fn format_name(tag: u16) -> &'static str {
+    match tag {
+        0x0001 => "PCM",
+        0x0002 => "Microsoft ADPCM",
+        0x0003 => "IEEE float",
+        0x0006 => "A-law",
+        0x0007 => "mu-law",
+        0x0011 => "IMA ADPCM",
+        0x0031 => "GSM 6.10",
+        0x0050 => "MPEG",
+        0x0055 => "MPEG Layer 3",
+        0xFFFE => "extensible",
+        _ => "unknown",
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Eq)]
+pub enum SampleFormat {
+    Int,
+    Float,
+}
+
+impl Display for SampleFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format: &str = match self {
+            SampleFormat::Int => "integer PCM",
+            SampleFormat::Float => "IEEE float",
+        };
+        write!(f, "{}", format)
+    }
+}
+
+impl From<&str> for SampleFormat {
+    fn from(value: &str) -> Self {
+        match value.to_uppercase() {
+            value if value.contains("FLOAT") || value.starts_with('F') => SampleFormat::Float,
+            _ => SampleFormat::Int,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl Display for Endianness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let endianness: &str = match self {
+            Endianness::Little => "Little",
+            Endianness::Big => "Big",
+        };
+        write!(f, "{}", endianness)
+    }
+}
+
+impl From<&str> for Endianness {
+    fn from(value: &str) -> Self {
+        match value.to_uppercase().chars().nth(0).unwrap_or('L') {
+            'B' => Endianness::Big,
+            _ => Endianness::Little,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Eq)]
+pub struct WaveFormat {
+    pub num_channels: u16,
+    pub sample_rate: u32,
+    /// Size of each sample in the file
+    pub bits_per_sample: u16,
+    pub sample_format: SampleFormat,
+}
+
+/// Wave file reader
+///
+/// Reads 8/16/24/32 bit integer PCM and 32/64 bit IEEE float samples, with either a plain or a
+/// WAVE_FORMAT_EXTENSIBLE format header. Samples are normalized to -1.0 - 1.0.
+/// Only the 'data' chunk is read, any chunks following it are ignored.
+/// Headerless raw PCM streams can be read with `new_raw`.
+///
+/// ```
+/// use kcs_decoder::*;
+///
+/// let mut wav = std::io::Cursor::new(vec![]);
+/// write_wav(&mut wav, &[0.5, -0.25], 48000, 16).unwrap();
+/// wav.set_position(0);
+///
+/// let mut reader = WaveReader::new(wav).unwrap();
+/// assert_eq!(reader.format.sample_rate, 48000);
+/// assert_eq!(reader.format.sample_format, SampleFormat::Int);
+/// assert!((reader.read_sample().unwrap() - 0.5).abs() < 0.001);
+/// ```
+pub struct WaveReader<T: Read> {
+    reader: T,
+    pub format: WaveFormat,
+    /// Bytes left in the data chunk, None if the length is unknown
+    remaining: Option<u64>,
+    endianness: Endianness,
+}
+
+impl<T: Read> WaveReader<T> {
+    pub fn new(mut reader: T) -> Result<Self, WaveError> {
+        let mut tag = [0u8; 4];
+        reader
+            .read_exact(&mut tag)
+            .map_err(|_| WaveError::NotRiff)?;
+        if &tag != b"RIFF" {
+            return Err(WaveError::NotRiff);
+        }
+        read_u32(&mut reader)?;
+        reader
+            .read_exact(&mut tag)
+            .map_err(|_| WaveError::NotWave)?;
+        if &tag != b"WAVE" {
+            return Err(WaveError::NotWave);
+        }
+
+        let mut format = None;
+        loop {
+            if reader.read_exact(&mut tag).is_err() {
+                return Err(WaveError::MissingChunk(match format {
+                    None => "fmt ",
+                    Some(_) => "data",
+                }));
+            }
+            let size = read_u32(&mut reader)?;
+            match &tag {
+                b"fmt " => {
+                    // The size is not trusted for the allocation, the rest of the chunk is skipped
+                    let length = u32::min(size, MAX_FORMAT_CHUNK_LENGTH);
+                    let mut chunk = vec![0u8; length as usize];
+                    reader.read_exact(&mut chunk)?;
+                    format = Some(parse_format(&chunk)?);
+                    skip(&mut reader, (size - length) as u64 + (size % 2) as u64)?;
+                }
+                b"data" => {
+                    let format = format.ok_or(WaveError::MissingChunk("fmt "))?;
+                    // Streamed files may not have the final size in the header
+                    let remaining = match size {
+                        0 | u32::MAX => None,
+                        size => Some(size as u64),
+                    };
+                    return Ok(Self {
+                        reader,
+                        format,
+                        remaining,
+                        endianness: Endianness::Little,
+                    });
+                }
+                _ => skip(&mut reader, size as u64 + (size % 2) as u64)?,
+            }
+        }
+    }
+
+    /// Reads a headerless stream of interleaved samples in the given format until the end of the stream
+    pub fn new_raw(
+        reader: T,
+        format: WaveFormat,
+        endianness: Endianness,
+    ) -> Result<Self, WaveError> {
+        validate_format(&format)?;
+        Ok(Self {
+            reader,
+            format,
+            remaining: None,
+            endianness,
+        })
+    }
+
+    /// Reads the next sample, of the next channel for multi-channel files
+    pub fn read_sample(&mut self) -> io::Result<f32> {
+        let num_bytes = self.format.bits_per_sample as usize / 8;
+        if let Some(remaining) = self.remaining.as_mut() {
+            if *remaining < num_bytes as u64 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            *remaining -= num_bytes as u64;
+        }
+        let mut buffer = [0u8; 8];
+        self.reader.read_exact(&mut buffer[..num_bytes])?;
+        if self.endianness == Endianness::Big {
+            buffer[..num_bytes].reverse();
+        }
+
+        Ok(match (self.format.sample_format, num_bytes) {
+            (SampleFormat::Int, 1) => (buffer[0] as f32 - 128.0) / 128.0,
+            (SampleFormat::Float, 4) => f32::from_le_bytes(buffer[..4].try_into().unwrap()),
+            (SampleFormat::Float, _) => f64::from_le_bytes(buffer) as f32,
+            (SampleFormat::Int, _) => {
+                // Sign extend by placing the sample in the top bytes of an i32
+                let mut bytes = [0u8; 4];
+                bytes[4 - num_bytes..].copy_from_slice(&buffer[..num_bytes]);
+                i32::from_le_bytes(bytes) as f32 / (i32::MAX as f32 + 1.0)
+            }
+        })
+    }
+}
+
+fn parse_format(chunk: &[u8]) -> Result<WaveFormat, WaveError> {
+    if chunk.len() < 16 {
+        return Err(WaveError::InvalidHeader("'fmt ' chunk is too short"));
+    }
+    let u16_at = |idx: usize| u16::from_le_bytes([chunk[idx], chunk[idx + 1]]);
+    let format_tag = u16_at(0);
+    let num_channels = u16_at(2);
+    let sample_rate = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+    let bits_per_sample = u16_at(14);
+
+    let format_tag = match format_tag {
+        WAVE_FORMAT_EXTENSIBLE => {
+            if chunk.len() < 40 {
+                return Err(WaveError::InvalidHeader(
+                    "'fmt ' chunk is too short for WAVE_FORMAT_EXTENSIBLE",
+                ));
+            }
+            let guid: [u8; 16] = chunk[24..40].try_into().unwrap();
+            if guid[2..] != KSDATAFORMAT_SUBTYPE_SUFFIX {
+                return Err(WaveError::UnsupportedSubFormat(guid));
+            }
+            u16::from_le_bytes([guid[0], guid[1]])
+        }
+        format_tag => format_tag,
+    };
+    let sample_format = match format_tag {
+        WAVE_FORMAT_PCM => SampleFormat::Int,
+        WAVE_FORMAT_IEEE_FLOAT => SampleFormat::Float,
+        format_tag => return Err(WaveError::UnsupportedFormat(format_tag)),
+    };
+
+    let format = WaveFormat {
+        num_channels,
+        sample_rate,
+        bits_per_sample,
+        sample_format,
+    };
+    validate_format(&format)?;
+    Ok(format)
+}
+
+fn validate_format(format: &WaveFormat) -> Result<(), WaveError> {
+    match (format.sample_format, format.bits_per_sample) {
+        (SampleFormat::Int, 8 | 16 | 24 | 32) | (SampleFormat::Float, 32 | 64) => {}
+        (sample_format, bits_per_sample) => {
+            return Err(WaveError::UnsupportedBitsPerSample(
+                sample_format,
+                bits_per_sample,
+            ))
+        }
+    }
+    if format.num_channels == 0 {
+        return Err(WaveError::InvalidHeader("number of channels is zero"));
+    }
+    if format.sample_rate == 0 {
+        return Err(WaveError::InvalidHeader("sample rate is zero"));
+    }
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, WaveError> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn skip(reader: &mut impl Read, num_bytes: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(num_bytes), &mut io::sink())?;
+    if skipped < num_bytes {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::iter;
+
+    fn wave_file(format_chunk: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut file = b"RIFF\0\0\0\0WAVE".to_vec();
+        file.extend(b"LIST\x04\0\0\0INFO");
+        file.extend(b"fmt ");
+        file.extend((format_chunk.len() as u32).to_le_bytes());
+        file.extend(format_chunk);
+        file.extend(b"data");
+        file.extend((data.len() as u32).to_le_bytes());
+        file.extend(data);
+        file.extend(b"LIST\x04\0\0\0INFO");
+        file
+    }
+
+    fn format_chunk(format_tag: u16, num_channels: u16, bits_per_sample: u16) -> Vec<u8> {
+        let block_align = num_channels * bits_per_sample / 8;
+        let mut chunk = vec![];
+        chunk.extend(format_tag.to_le_bytes());
+        chunk.extend(num_channels.to_le_bytes());
+        chunk.extend(44100u32.to_le_bytes());
+        chunk.extend((44100 * block_align as u32).to_le_bytes());
+        chunk.extend(block_align.to_le_bytes());
+        chunk.extend(bits_per_sample.to_le_bytes());
+        chunk
+    }
+
+    fn extensible_chunk(sub_format: u16, bits_per_sample: u16, valid_bits: u16) -> Vec<u8> {
+        let mut chunk = format_chunk(WAVE_FORMAT_EXTENSIBLE, 1, bits_per_sample);
+        chunk.extend(22u16.to_le_bytes());
+        chunk.extend(valid_bits.to_le_bytes());
+        chunk.extend(4u32.to_le_bytes());
+        chunk.extend(sub_format.to_le_bytes());
+        chunk.extend(KSDATAFORMAT_SUBTYPE_SUFFIX);
+        chunk
+    }
+
+    fn read_all(file: Vec<u8>) -> Result<Vec<f32>, WaveError> {
+        let mut reader = WaveReader::new(file.as_slice())?;
+        Ok(iter::from_fn(|| reader.read_sample().ok()).collect())
+    }
+
+    #[test]
+    fn wave_reader_integer_pcm() {
+        let samples = read_all(wave_file(&format_chunk(1, 1, 8), &[0x80, 0xC0, 0x00])).unwrap();
+        assert_eq!(samples, vec![0.0, 0.5, -1.0]);
+
+        let data = [0x00, 0x40, 0x00, 0xC0];
+        let samples = read_all(wave_file(&format_chunk(1, 1, 16), &data)).unwrap();
+        assert_eq!(samples, vec![0.5, -0.5]);
+
+        let data = [0x00, 0x00, 0x40, 0x00, 0x00, 0xC0];
+        let samples = read_all(wave_file(&format_chunk(1, 1, 24), &data)).unwrap();
+        assert_eq!(samples, vec![0.5, -0.5]);
+    }
+
+    #[test]
+    fn wave_reader_float() {
+        let data = [0.25f32, -1.0]
+            .iter()
+            .flat_map(|val| val.to_le_bytes())
+            .collect::<Vec<_>>();
+        let samples = read_all(wave_file(&format_chunk(3, 1, 32), &data)).unwrap();
+        assert_eq!(samples, vec![0.25, -1.0]);
+
+        let data = [0.125f64, 0.75]
+            .iter()
+            .flat_map(|val| val.to_le_bytes())
+            .collect::<Vec<_>>();
+        let samples = read_all(wave_file(&format_chunk(3, 1, 64), &data)).unwrap();
+        assert_eq!(samples, vec![0.125, 0.75]);
+    }
+
+    #[test]
+    fn wave_reader_extensible() {
+        let data = 0.5f32.to_le_bytes();
+        let samples = read_all(wave_file(&extensible_chunk(3, 32, 32), &data)).unwrap();
+        assert_eq!(samples, vec![0.5]);
+
+        // 20 valid bits in a 24 bit container
+        let data = [0x00, 0x00, 0xC0];
+        let file = wave_file(&extensible_chunk(1, 24, 20), &data);
+        let reader = WaveReader::new(file.as_slice()).unwrap();
+        assert_eq!(reader.format.bits_per_sample, 24);
+        assert_eq!(read_all(file).unwrap(), vec![-0.5]);
+    }
+
+    #[test]
+    fn wave_reader_unsupported_formats() {
+        let error = read_all(wave_file(&format_chunk(0x55, 1, 0), &[])).unwrap_err();
+        assert_eq!(error, WaveError::UnsupportedFormat(0x55));
+        assert_eq!(
+            error.to_string(),
+            "unsupported wave format 0x0055 (MPEG Layer 3), only integer PCM and IEEE float are supported"
+        );
+
+        let error = read_all(wave_file(&extensible_chunk(6, 8, 8), &[])).unwrap_err();
+        assert_eq!(error, WaveError::UnsupportedFormat(6));
+
+        let error = read_all(wave_file(&format_chunk(3, 1, 16), &[])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "unsupported sample size, 16 bit IEEE float"
+        );
+
+        assert_eq!(read_all(b"RIFX".to_vec()).unwrap_err(), WaveError::NotRiff);
+    }
+
+    #[test]
+    fn wave_reader_chunk_sizes() {
+        // Bytes after the known fields of the 'fmt ' chunk are skipped
+        let mut chunk = format_chunk(1, 1, 16);
+        chunk.extend([0u8; 50]);
+        let samples = read_all(wave_file(&chunk, &[0x00, 0x40])).unwrap();
+        assert_eq!(samples, vec![0.5]);
+
+        // A chunk size larger than the file
+        let mut file = wave_file(&format_chunk(1, 1, 16), &[]);
+        file[28..32].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(read_all(file.clone()), Err(WaveError::IO(_))));
+        file[24..28].copy_from_slice(b"JUNK");
+        assert!(matches!(read_all(file), Err(WaveError::IO(_))));
+    }
+
+    #[test]
+    fn wave_reader_raw() {
+        let format = WaveFormat {
+            num_channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let data = [0x40, 0x00, 0xC0, 0x00, 0x20];
+        let mut reader = WaveReader::new_raw(&data[..], format, Endianness::Big).unwrap();
+        let samples = iter::from_fn(|| reader.read_sample().ok()).collect::<Vec<_>>();
+        assert_eq!(samples, vec![0.5, -0.5]);
+
+        let format = WaveFormat {
+            bits_per_sample: 24,
+            sample_format: SampleFormat::Float,
+            ..format
+        };
+        assert!(WaveReader::new_raw(&data[..], format, Endianness::Little).is_err());
+    }
+}
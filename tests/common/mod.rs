@@ -0,0 +1,230 @@
+//! Synthetic tape recordings and helpers for running the decoder on them
+
+use kcs_decoder::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// Generates a tape recording of known data and degrades it like a real tape would
+pub struct SyntheticTape {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    rng_state: u64,
+}
+
+impl SyntheticTape {
+    pub fn new(config: DecoderConfig, data: &[u8], sample_rate: u32) -> Self {
+        let encoder_config = EncoderConfig {
+            sample_rate,
+            leader_length: 0.5,
+            trailer_length: 0.2,
+            ..Default::default()
+        };
+        let samples = Encoder::new(config, encoder_config).unwrap().encode(data);
+        Self::from_samples(samples, sample_rate)
+    }
+
+    /// Tape with the given samples, e.g. generated without the `Encoder`
+    pub fn from_samples(samples: Vec<f32>, sample_rate: u32) -> Self {
+        Self {
+            samples,
+            sample_rate,
+            rng_state: 0x2545F4914F6CDD1D,
+        }
+    }
+
+    /// Adds gaussian noise with the given standard deviation
+    pub fn noise(mut self, level: f32) -> Self {
+        for idx in 0..self.samples.len() {
+            // Box-Muller transform
+            let u1 = self.random().max(f64::MIN_POSITIVE);
+            let u2 = self.random();
+            let gaussian = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+            self.samples[idx] += level * gaussian as f32;
+        }
+        self
+    }
+
+    pub fn dc_offset(mut self, offset: f32) -> Self {
+        self.samples.iter_mut().for_each(|sample| *sample += offset);
+        self
+    }
+
+    /// Adds a sine wave, e.g. mains hum
+    pub fn hum(mut self, level: f32, frequency: f32) -> Self {
+        let omega = 2.0 * std::f32::consts::PI * frequency / self.sample_rate as f32;
+        for (idx, sample) in self.samples.iter_mut().enumerate() {
+            *sample += level * (omega * idx as f32).sin();
+        }
+        self
+    }
+
+    /// Varies the amplitude sinusoidally by `depth` (0.0 - 1.0) with the given period in seconds
+    pub fn amplitude_drift(mut self, depth: f32, period: f32) -> Self {
+        let omega = 2.0 * std::f32::consts::PI / (period * self.sample_rate as f32);
+        for (idx, sample) in self.samples.iter_mut().enumerate() {
+            *sample *= 1.0 - depth * 0.5 * (1.0 - (omega * idx as f32).cos());
+        }
+        self
+    }
+
+    /// Varies the tape speed, `wow` and `flutter` are (relative deviation, frequency in Hz)
+    pub fn speed_variation(self, wow: (f64, f64), flutter: (f64, f64)) -> Self {
+        self.play(|t: f64| {
+            1.0 + wow.0 * (2.0 * std::f64::consts::PI * wow.1 * t).sin()
+                + flutter.0 * (2.0 * std::f64::consts::PI * flutter.1 * t).sin()
+        })
+    }
+
+    /// Plays the tape on a deck running at `speed` times the nominal speed
+    pub fn off_speed(self, speed: f64) -> Self {
+        self.play(|_| speed)
+    }
+
+    /// Resamples the tape with the speed at each time in seconds
+    fn play(mut self, speed: impl Fn(f64) -> f64) -> Self {
+        let sample_rate = self.sample_rate as f64;
+        let mut output = vec![];
+        let mut position = 0.0;
+        while position < (self.samples.len() - 1) as f64 {
+            let idx = position as usize;
+            let frac = (position - idx as f64) as f32;
+            output.push(self.samples[idx] + (self.samples[idx + 1] - self.samples[idx]) * frac);
+            position += speed(output.len() as f64 / sample_rate);
+        }
+        self.samples = output;
+        self
+    }
+
+    /// Attenuates the signal from `start` for `length` seconds
+    pub fn dropout(mut self, start: f32, length: f32, attenuation: f32) -> Self {
+        let len = self.samples.len();
+        let first = ((start * self.sample_rate as f32) as usize).min(len);
+        let last = (((start + length) * self.sample_rate as f32) as usize).min(len);
+        for sample in &mut self.samples[first..last] {
+            *sample *= attenuation;
+        }
+        self
+    }
+
+    pub fn write_wav(&self, path: &Path) {
+        let writer = BufWriter::new(File::create(path).unwrap());
+        write_wav(writer, &self.samples, self.sample_rate, 16).unwrap();
+    }
+
+    /// Uniform random number in 0..1 (xorshift64*)
+    fn random(&mut self) -> f64 {
+        self.rng_state ^= self.rng_state >> 12;
+        self.rng_state ^= self.rng_state << 25;
+        self.rng_state ^= self.rng_state >> 27;
+        (self.rng_state.wrapping_mul(0x2545F4914F6CDD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Empty directory for the output of one test
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("kcs_decoder-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs the decoder on `input` with the output prefix `<dir>/out` and returns the names of the files written
+pub fn run_decoder(input: &Path, dir: &Path, args: &[&str]) -> Vec<PathBuf> {
+    let output = Command::new(env!("CARGO_BIN_EXE_kcs_decoder"))
+        .arg(input)
+        .arg("--prefix")
+        .arg(dir.join("out"))
+        .args(args)
+        .output()
+        .unwrap();
+    check_output(output, dir)
+}
+
+/// Runs the analyze command on `input` and returns what it printed
+pub fn run_analyze(input: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_kcs_decoder"))
+        .arg("analyze")
+        .arg(input)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Runs the verify command with `args` and returns what it printed
+pub fn run_verify(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_kcs_decoder"))
+        .arg("verify")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Same as `run_decoder`, with the input piped to stdin
+pub fn run_decoder_stdin(input: &[u8], dir: &Path, args: &[&str]) -> Vec<PathBuf> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kcs_decoder"))
+        .arg("-")
+        .arg("--prefix")
+        .arg(dir.join("out"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    check_output(child.wait_with_output().unwrap(), dir)
+}
+
+fn check_output(output: Output, dir: &Path) -> Vec<PathBuf> {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mut files = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("out-")
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// Test data with all byte values and some text
+pub fn test_data(len: usize) -> Vec<u8> {
+    b"Kansas City Standard "
+        .iter()
+        .copied()
+        .chain((0..=255u8).cycle())
+        .take(len)
+        .collect()
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
@@ -0,0 +1,552 @@
+mod common;
+
+use common::*;
+use kcs_decoder::*;
+
+const PRESETS: [Preset; 7] = [
+    Preset::Std,
+    Preset::NASCOM,
+    Preset::Acorn,
+    Preset::MSX1200,
+    Preset::MSX2400,
+    Preset::CUTS300,
+    Preset::CUTS1200,
+];
+
+#[test]
+fn roundtrip_all_presets() {
+    let data = test_data(300);
+    for preset in PRESETS {
+        let dir = test_dir(&format!("preset-{preset}"));
+        let input = dir.join("tape.wav");
+        SyntheticTape::new(DecoderConfig::get_preset(&preset), &data, 192000).write_wav(&input);
+
+        let files = run_decoder(&input, &dir, &["--preset", &preset.to_string(), "--merge"]);
+        assert_eq!(files, vec![dir.join("out-merged.dat")], "{preset}");
+        assert_eq!(std::fs::read(&files[0]).unwrap(), data, "{preset}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn roundtrip_all_presets_interpolated() {
+    let data = test_data(300);
+    for preset in PRESETS {
+        let dir = test_dir(&format!("preset-interpolated-{preset}"));
+        let input = dir.join("tape.wav");
+        SyntheticTape::new(DecoderConfig::get_preset(&preset), &data, 44100).write_wav(&input);
+
+        let args = [
+            "--preset",
+            &preset.to_string(),
+            "-i",
+            "linear",
+            "-r",
+            "off",
+            "--merge",
+        ];
+        let files = run_decoder(&input, &dir, &args);
+        assert_eq!(files, vec![dir.join("out-merged.dat")], "{preset}");
+        assert_eq!(std::fs::read(&files[0]).unwrap(), data, "{preset}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn roundtrip_degraded_tape() {
+    let data = test_data(1000);
+    let dir = test_dir("degraded");
+    let input = dir.join("tape.wav");
+    SyntheticTape::new(DecoderConfig::get_preset(&Preset::NASCOM), &data, 44100)
+        .speed_variation((0.01, 0.5), (0.002, 12.0))
+        .amplitude_drift(0.3, 3.0)
+        .dc_offset(0.01)
+        .noise(0.03)
+        .write_wav(&input);
+
+    let args = ["--preset", "NASCOM", "-i", "linear", "-r", "off", "--merge"];
+    let files = run_decoder(&input, &dir, &args);
+    assert_eq!(std::fs::read(&files[0]).unwrap(), data);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn roundtrip_hum_filtered() {
+    let data = test_data(300);
+    let dir = test_dir("hum");
+    let input = dir.join("tape.wav");
+    SyntheticTape::new(DecoderConfig::get_preset(&Preset::NASCOM), &data, 48000)
+        .hum(0.6, 50.0)
+        .dc_offset(0.2)
+        .write_wav(&input);
+
+    let args = ["--preset", "NASCOM", "-i", "linear", "-r", "off", "--merge"];
+    let files = run_decoder(&input, &dir, &args);
+    assert_eq!(std::fs::read(&files[0]).unwrap(), data);
+
+    // The hum moves the zero crossings too much without the filter
+    std::fs::remove_file(&files[0]).unwrap();
+    let files = run_decoder(&input, &dir, &[&args[..], &["--filter", "off"]].concat());
+    assert!(files.is_empty() || std::fs::read(&files[0]).unwrap() != data);
+    files
+        .iter()
+        .for_each(|file| std::fs::remove_file(file).unwrap());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn roundtrip_raw_stdin() {
+    let data = test_data(300);
+    let dir = test_dir("raw-stdin");
+    let tape = SyntheticTape::new(DecoderConfig::get_preset(&Preset::NASCOM), &data, 48000);
+    let raw = tape
+        .samples
+        .iter()
+        .flat_map(|sample| ((sample * 32767.0) as i16).to_be_bytes())
+        .collect::<Vec<_>>();
+
+    let args = ["--raw", "--raw-rate", "48000", "--raw-endian", "big"];
+    let args = [&args[..], &["--preset", "NASCOM", "--merge"]].concat();
+    let files = run_decoder_stdin(&raw, &dir, &args);
+    assert_eq!(files, vec![dir.join("out-merged.dat")]);
+    assert_eq!(std::fs::read(&files[0]).unwrap(), data);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn roundtrip_wav_stdin_streamed() {
+    let data = test_data(300);
+    let dir = test_dir("wav-stdin");
+    let tape = SyntheticTape::new(DecoderConfig::get_preset(&Preset::NASCOM), &data, 48000);
+    let mut wav = std::io::Cursor::new(vec![]);
+    write_wav(&mut wav, &tape.samples, 48000, 16).unwrap();
+    // A streaming writer does not know the length of the data chunk
+    let mut wav = wav.into_inner();
+    wav[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+
+    let files = run_decoder_stdin(&wav, &dir, &["--preset", "NASCOM", "--merge"]);
+    assert_eq!(files, vec![dir.join("out-merged.dat")]);
+    assert_eq!(std::fs::read(&files[0]).unwrap(), data);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn roundtrip_dropout_damages_one_block() {
+    let data = test_data(3 * 256);
+    let mut tape = vec![];
+    nascom::write_cas(&nascom::split_blocks(&data, 0x1000).unwrap(), &mut tape).unwrap();
+
+    // Leader, pilot and the first block take about 0.5 + 2.1 + 2.3 seconds at 1200 baud
+    let dir = test_dir("dropout");
+    let input = dir.join("tape.wav");
+    SyntheticTape::new(DecoderConfig::get_preset(&Preset::NASCOM), &tape, 48000)
+        .dropout(5.6, 0.05, 0.0)
+        .write_wav(&input);
+
+    let args = ["--preset", "NASCOM", "--framing", "NASCOM", "--merge"];
+    let args = [&args[..], &["-i", "linear", "-r", "off"]].concat();
+    let files = run_decoder(&input, &dir, &args);
+
+    // The program is written without the damaged block and marked as incomplete
+    assert_eq!(files.len(), 1);
+    assert!(files[0].to_string_lossy().ends_with("-incomplete.cas"));
+    let (blocks, error) = nascom::read_blocks(&std::fs::read(&files[0]).unwrap());
+    assert_eq!(error, None);
+    assert_eq!(
+        blocks
+            .iter()
+            .map(|block| block.header.block_no)
+            .collect::<Vec<_>>(),
+        vec![2, 0]
+    );
+    assert!(!nascom::is_complete(&blocks));
+    assert_eq!(nascom::missing_blocks(&blocks), vec![1]);
+    assert_eq!(blocks[1].data, data[512..]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn roundtrip_noisy_quadrature() {
+    let data = test_data(1000);
+    let dir = test_dir("noisy-quadrature");
+    let input = dir.join("tape.wav");
+    SyntheticTape::new(DecoderConfig::get_preset(&Preset::NASCOM), &data, 44100)
+        .speed_variation((0.01, 0.5), (0.002, 12.0))
+        .noise(0.2)
+        .write_wav(&input);
+
+    // Too noisy for the periods between zero crossings, the energy over a whole bit still tells the tones apart
+    let args = ["--preset", "NASCOM", "-i", "linear", "--merge"];
+    let files = run_decoder(&input, &dir, &args);
+    assert!(files.is_empty() || std::fs::read(&files[0]).unwrap() != data);
+    files
+        .iter()
+        .for_each(|file| std::fs::remove_file(file).unwrap());
+
+    let args = [&args[..], &["--demodulator", "quadrature"]].concat();
+    let files = run_decoder(&input, &dir, &args);
+    assert_eq!(files, vec![dir.join("out-merged.dat")]);
+    assert_eq!(std::fs::read(&files[0]).unwrap(), data);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn roundtrip_fast_deck_track_speed() {
+    let data = test_data(1000);
+    let dir = test_dir("fast-deck");
+    let input = dir.join("tape.wav");
+    SyntheticTape::new(DecoderConfig::get_preset(&Preset::NASCOM), &data, 44100)
+        .off_speed(1.08)
+        .speed_variation((0.0, 0.0), (0.03, 3.0))
+        .write_wav(&input);
+
+    // The flutter pushes the tones out of the bounds around the nominal frequencies
+    for clock_recovery in ["PLL", "Periods"] {
+        let args = [
+            "--preset",
+            "NASCOM",
+            "-i",
+            "linear",
+            "-r",
+            "off",
+            "--merge",
+            "--clock-recovery",
+            clock_recovery,
+        ];
+        let files = run_decoder(&input, &dir, &args);
+        assert!(files.is_empty() || std::fs::read(&files[0]).unwrap() != data);
+        files
+            .iter()
+            .for_each(|file| std::fs::remove_file(file).unwrap());
+
+        let args = [&args[..], &["--track-speed"]].concat();
+        let files = run_decoder(&input, &dir, &args);
+        assert_eq!(files, vec![dir.join("out-merged.dat")], "{clock_recovery}");
+        assert_eq!(std::fs::read(&files[0]).unwrap(), data, "{clock_recovery}");
+        std::fs::remove_file(&files[0]).unwrap();
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn roundtrip_analyze_auto() {
+    // 7 data bits with even parity and 2 stop bits at the tones of MSX1200
+    let config = DecoderConfig {
+        num_databits: 7,
+        parity: Parity::EVEN,
+        ..DecoderConfig::get_preset(&Preset::MSX1200)
+    };
+    let data = test_data(1000)
+        .into_iter()
+        .map(|val| val & 0x7f)
+        .collect::<Vec<_>>();
+    let dir = test_dir("analyze");
+    let input = dir.join("tape.wav");
+    SyntheticTape::new(config, &data, 44100)
+        .off_speed(1.03)
+        .noise(0.05)
+        .write_wav(&input);
+
+    let output = run_analyze(&input);
+    assert!(
+        output.contains("Recommended options: --preset MSX1200 --num-databits 7 --parity Even\n"),
+        "{output}"
+    );
+    assert!(
+        output.contains("7E2: 1000 bytes, 0 framing errors"),
+        "{output}"
+    );
+
+    let files = run_decoder(&input, &dir, &["--auto", "--merge"]);
+    assert_eq!(files, vec![dir.join("out-merged.dat")]);
+    assert_eq!(std::fs::read(&files[0]).unwrap(), data);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// Decodes the example recording with `args` and checks that every file written holds the complete program.
+/// The raw output has the same bytes as the .cas file, as the recording has no noise outside the program.
+fn golden_blspascal(name: &str, args: &[&str], expected: &[&str]) {
+    let dir = test_dir(name);
+    let input = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/BLSPASCAL.wav");
+    let args = [&["--preset", "NASCOM", "-i", "linear", "-r", "off"], args].concat();
+    let files = run_decoder(&input, &dir, &args);
+
+    let expected = expected
+        .iter()
+        .map(|file| dir.join(file))
+        .collect::<Vec<_>>();
+    assert_eq!(files, expected);
+    for file in &files {
+        let program = std::fs::read(file).unwrap();
+        let (blocks, error) = nascom::read_blocks(&program);
+        assert_eq!(error, None, "{file:?}");
+        assert_eq!(blocks.len(), 48, "{file:?}");
+        assert!(nascom::is_complete(&blocks), "{file:?}");
+        assert_eq!(program.len(), 13552, "{file:?}");
+        assert_eq!(crc32(&program), 0x4f02c624, "{file:?}");
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn golden_blspascal_nascom_merged() {
+    golden_blspascal(
+        "blspascal",
+        &["--framing", "NASCOM", "--merge"],
+        &["out-merged-00m06s.cas"],
+    );
+}
+
+// Counting periods, the positive zero crossings of the recording do not decode, the bit clock decodes both streams
+#[test]
+fn golden_blspascal_nascom_per_stream() {
+    golden_blspascal(
+        "blspascal-streams",
+        &["--framing", "NASCOM", "--clock-recovery", "PLL"],
+        &["out-ch0-00m06s-neg.cas", "out-ch0-00m06s-pos.cas"],
+    );
+}
+
+#[test]
+fn golden_blspascal_raw() {
+    golden_blspascal("blspascal-raw-merged", &["--merge"], &["out-merged.dat"]);
+    golden_blspascal(
+        "blspascal-raw",
+        &["--clock-recovery", "PLL"],
+        &["out-ch0-00m00s-neg.dat", "out-ch0-00m00s-pos.dat"],
+    );
+}
+
+#[test]
+fn roundtrip_quality_sidecar() {
+    let data = test_data(1000);
+    let dir = test_dir("quality");
+    let input = dir.join("tape.wav");
+    SyntheticTape::new(DecoderConfig::get_preset(&Preset::NASCOM), &data, 44100)
+        .dropout(3.0, 0.2, 0.1)
+        .noise(0.03)
+        .write_wav(&input);
+
+    // The bit clock rates each bit by how well its periods fit the tones
+    let args = [
+        "--preset",
+        "NASCOM",
+        "-i",
+        "linear",
+        "-r",
+        "off",
+        "--quality",
+        "--clock-recovery",
+        "PLL",
+    ];
+    let files = run_decoder(&input, &dir, &[&args[..], &["--merge"]].concat());
+    assert!(files.contains(&dir.join("out-merged-quality.csv")));
+    let merged = std::fs::read(dir.join("out-merged.dat")).unwrap();
+    let csv = std::fs::read_to_string(dir.join("out-merged-quality.csv")).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("sample,seconds,value,quality,low"));
+    let rows = lines
+        .map(|line| line.split(',').collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(rows.len(), merged.len());
+
+    // Only the bytes in the dropout are flagged
+    let flagged = rows
+        .iter()
+        .filter(|row| row[4] == "1")
+        .map(|row| row[1].parse::<f32>().unwrap())
+        .collect::<Vec<_>>();
+    assert!(flagged.len() > 5, "{flagged:?}");
+    assert!(
+        flagged.iter().all(|seconds| (3.0..3.25).contains(seconds)),
+        "{flagged:?}"
+    );
+
+    // Without merging, one file per stream
+    let files = run_decoder(&input, &dir, &args);
+    assert!(files.contains(&dir.join("out-ch0-neg-quality.csv")));
+    assert!(files.contains(&dir.join("out-ch0-pos-quality.csv")));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn roundtrip_salvage_parity_errors() {
+    // Eight data bits decoded as seven with even parity, so the top bit of each byte is taken as the parity bit
+    let data = test_data(500);
+    let dir = test_dir("salvage");
+    let input = dir.join("tape.wav");
+    SyntheticTape::new(DecoderConfig::get_preset(&Preset::NASCOM), &data, 44100).write_wav(&input);
+    let expected = data.iter().map(|val| val & 0x7f).collect::<Vec<_>>();
+    let suspect = data
+        .iter()
+        .enumerate()
+        .filter(|(_, val)| val.count_ones() % 2 == 1)
+        .map(|(offset, _)| offset)
+        .collect::<Vec<_>>();
+
+    // Both streams decode without signal errors with the bit clock, see `golden_blspascal_nascom_per_stream`
+    let args = [
+        "--preset",
+        "NASCOM",
+        "--num-databits",
+        "7",
+        "--parity",
+        "even",
+        "-i",
+        "linear",
+        "-r",
+        "off",
+        "--salvage",
+        "--clock-recovery",
+        "PLL",
+    ];
+    let files = run_decoder(&input, &dir, &[&args[..], &["--merge"]].concat());
+    assert_eq!(
+        files,
+        vec![
+            dir.join("out-merged-errors.csv"),
+            dir.join("out-merged.dat")
+        ]
+    );
+    assert_eq!(std::fs::read(&files[1]).unwrap(), expected);
+    let csv = std::fs::read_to_string(&files[0]).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("offset,sample,seconds,value,error"));
+    let rows = lines
+        .map(|line| line.split(',').map(str::to_string).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rows.iter()
+            .map(|row| row[0].parse::<usize>().unwrap())
+            .collect::<Vec<_>>(),
+        suspect
+    );
+    assert!(rows.iter().all(|row| row[4] == "parity"));
+    files
+        .iter()
+        .for_each(|file| std::fs::remove_file(file).unwrap());
+
+    // Without merging, one file and one error map per stream
+    let files = run_decoder(&input, &dir, &args);
+    assert_eq!(files.len(), 4, "{files:?}");
+    assert_eq!(
+        std::fs::read(dir.join("out-ch0-00m00s-neg.dat")).unwrap(),
+        expected
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn roundtrip_wide_words_msb_first() {
+    // 9 data bits sent MSB first with 1.5 stop bits, each word is stored in two bytes
+    let config = DecoderConfig {
+        num_databits: 9,
+        bit_order: BitOrder::MsbFirst,
+        half_stopbit: true,
+        ..DecoderConfig::get_preset(&Preset::NASCOM)
+    };
+    let words = (0..600u16)
+        .map(|val| val.wrapping_mul(331) % 512)
+        .collect::<Vec<_>>();
+    let data = config.words_to_bytes(&words);
+    let dir = test_dir("wide_words");
+    let input = dir.join("tape.wav");
+    SyntheticTape::new(config, &data, 44100)
+        .noise(0.05)
+        .write_wav(&input);
+
+    let args = [
+        "--preset",
+        "NASCOM",
+        "--num-databits",
+        "9",
+        "--bit-order",
+        "msb",
+        "--num-stopbits",
+        "1.5",
+        "--merge",
+    ];
+    let files = run_decoder(&input, &dir, &args);
+    assert_eq!(files, vec![dir.join("out-merged.dat")]);
+    assert_eq!(std::fs::read(&files[0]).unwrap(), data);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn roundtrip_sol20_tape() {
+    let program = test_data(700);
+    let files = [
+        sol20::TapeFile::new("TREK", b'B', 0x0000, 0x0000, &program).unwrap(),
+        sol20::TapeFile::new("SUB", 0x80, 0x1000, 0x1000, &program[..40]).unwrap(),
+    ];
+    let data = files
+        .iter()
+        .flat_map(|file| file.to_bytes())
+        .collect::<Vec<_>>();
+    for preset in [Preset::CUTS300, Preset::CUTS1200] {
+        let dir = test_dir(&format!("sol20-{preset}"));
+        let input = dir.join("tape.wav");
+        SyntheticTape::new(DecoderConfig::get_preset(&preset), &data, 44100)
+            .noise(0.05)
+            .write_wav(&input);
+
+        let args = ["--preset", &preset.to_string(), "-i", "linear", "--merge"];
+        let output = run_decoder(&input, &dir, &args);
+        assert_eq!(output, vec![dir.join("out-merged.dat")], "{preset}");
+        let decoded = std::fs::read(&output[0]).unwrap();
+        assert_eq!(
+            sol20::read_files(&decoded),
+            (files.to_vec(), None),
+            "{preset}"
+        );
+
+        let report = run_verify(&["--sol20", output[0].to_str().unwrap()]);
+        assert!(
+            report.starts_with(
+                "Got 2 valid files:\n\
+                 name='TREK', type=0x42, length=700, load address=0x0000, exec address=0x0000, blocks=3\n"
+            ),
+            "{report}"
+        );
+        assert!(report.ends_with("All files are valid\n"), "{report}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn roundtrip_cuts1200_half_period_spaces() {
+    // Generated here rather than by the encoder: phase continuous FSK at 1200 baud,
+    // where a space is half a period of 600 Hz and a mark one period of 1200 Hz
+    let data = test_data(300);
+    let sample_rate = 44100;
+    let bits = std::iter::repeat_n(true, 600)
+        .chain(data.iter().flat_map(|byte| {
+            let data_bits = (0..8).map(move |bit| byte >> bit & 1 == 1);
+            std::iter::once(false).chain(data_bits).chain([true, true])
+        }))
+        .chain(std::iter::repeat_n(true, 240));
+    // Each bit starts at a crossing, rising after an even number of spaces and falling after an odd number
+    let mut samples = Vec::new();
+    let mut spaces = 0;
+    for (idx, bit) in bits.enumerate() {
+        let frequency = if bit { 1200.0 } else { 600.0 };
+        let start = idx as f64 / 1200.0;
+        while (samples.len() as f64) < (idx + 1) as f64 * sample_rate as f64 / 1200.0 {
+            let time = samples.len() as f64 / sample_rate as f64 - start;
+            let phase = std::f64::consts::PI * spaces as f64
+                + 2.0 * std::f64::consts::PI * frequency * time;
+            samples.push(0.5 * phase.sin() as f32);
+        }
+        spaces += !bit as usize;
+    }
+
+    let dir = test_dir("cuts1200");
+    let input = dir.join("tape.wav");
+    SyntheticTape::from_samples(samples, sample_rate)
+        .noise(0.02)
+        .write_wav(&input);
+    let files = run_decoder(&input, &dir, &["--preset", "CUTS1200", "--merge"]);
+    assert_eq!(files, vec![dir.join("out-merged.dat")]);
+    assert_eq!(std::fs::read(&files[0]).unwrap(), data);
+    std::fs::remove_dir_all(dir).unwrap();
+}